@@ -0,0 +1,64 @@
+#!/usr/bin/env python3
+"""Regenerate src/schema/catalog.json from the schemastore.org catalog.
+
+The bundled catalog only needs the entries a JSON language server can use,
+so YAML and TOML patterns are dropped, along with entries left without any
+pattern. The retrieval date and source are recorded in the output.
+
+Usage:
+    scripts/update-catalog.py                    # fetch the live catalog
+    scripts/update-catalog.py --input FILE       # use a downloaded copy
+"""
+
+import argparse
+import datetime
+import json
+import pathlib
+import sys
+import urllib.request
+
+SOURCE = "https://www.schemastore.org/api/json/catalog.json"
+OUTPUT = pathlib.Path(__file__).resolve().parent.parent / "src/schema/catalog.json"
+NOT_JSON = (".yml", ".yaml", ".toml")
+
+
+def json_patterns(patterns):
+    return [p for p in patterns if not p.lower().endswith(NOT_JSON)]
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("--input", type=pathlib.Path, help="catalog file to use instead of fetching")
+    parser.add_argument("--date", help="retrieval date to record (default: today, UTC)")
+    args = parser.parse_args()
+
+    if args.input:
+        catalog = json.loads(args.input.read_text(encoding="utf-8"))
+    else:
+        with urllib.request.urlopen(SOURCE, timeout=60) as resp:
+            catalog = json.load(resp)
+
+    schemas = []
+    for entry in catalog["schemas"]:
+        patterns = json_patterns(entry.get("fileMatch") or [])
+        if not patterns or not entry.get("url"):
+            continue
+        kept = {"name": entry.get("name", ""), "fileMatch": patterns, "url": entry["url"]}
+        if entry.get("description"):
+            kept["description"] = entry["description"]
+        schemas.append(kept)
+
+    date = args.date or datetime.datetime.now(datetime.timezone.utc).date().isoformat()
+    out = {
+        "$schema": catalog.get("$schema", "https://json.schemastore.org/schema-catalog.json"),
+        "version": catalog.get("version", 1),
+        "source": SOURCE,
+        "retrieved": date,
+        "schemas": schemas,
+    }
+    OUTPUT.write_text(json.dumps(out, indent=2, ensure_ascii=False) + "\n", encoding="utf-8")
+    print(f"wrote {len(schemas)} of {len(catalog['schemas'])} entries to {OUTPUT}", file=sys.stderr)
+
+
+if __name__ == "__main__":
+    main()
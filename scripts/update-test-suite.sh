@@ -0,0 +1,66 @@
+#!/bin/sh
+# Vendor the JSON-Schema-Test-Suite's tests/ and remotes/ at a given commit,
+# then regenerate KNOWN_FAILURES in tests/json_schema_test_suite.rs from a
+# run against them.
+#
+# Usage: scripts/update-test-suite.sh <commit, tag or branch>
+#
+# SUITE_REPO overrides the repository to fetch from (e.g. a local mirror).
+set -eu
+
+rev=${1:?usage: $0 <commit, tag or branch>}
+root=$(cd "$(dirname "$0")/.." && pwd)
+dest=$root/tests/JSON-Schema-Test-Suite
+runner=$root/tests/json_schema_test_suite.rs
+
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+repo=${SUITE_REPO:-https://github.com/json-schema-org/JSON-Schema-Test-Suite.git}
+git -C "$tmp" init -q
+git -C "$tmp" fetch -q --depth 1 "$repo" "$rev"
+git -C "$tmp" checkout -q FETCH_HEAD
+sha=$(git -C "$tmp" rev-parse HEAD)
+
+rm -rf "$dest/tests" "$dest/remotes"
+cp -R "$tmp/tests" "$tmp/remotes" "$dest/"
+# The runner only knows these dialects.
+for dir in "$dest"/tests/*; do
+    case $(basename "$dir") in
+        draft4 | draft6 | draft7 | draft2019-09 | draft2020-12) ;;
+        *) rm -rf "$dir" ;;
+    esac
+done
+
+python3 - "$dest/README.md" "$sha" <<'PY'
+import re, sys
+path, sha = sys.argv[1:]
+text = open(path, encoding="utf-8").read()
+text = re.sub(r"(?m)^Pinned commit: .*$", f"Pinned commit: `{sha}`", text)
+text = re.sub(r"<!-- transcribed -->.*?<!-- /transcribed -->\n\n", "", text, flags=re.S)
+open(path, "w", encoding="utf-8").write(text)
+PY
+
+# The run fails until the list is regenerated; it writes the failures first.
+SUITE_FAILURES_OUT=$tmp/failures.txt \
+    cargo test --manifest-path "$root/Cargo.toml" --test json_schema_test_suite -- --nocapture || true
+
+python3 - "$runner" "$tmp/failures.txt" <<'PY'
+import re, sys
+runner, failures = sys.argv[1:]
+ids = [line for line in open(failures, encoding="utf-8").read().splitlines() if line]
+quote = lambda s: '"' + s.replace("\\", "\\\\").replace('"', '\\"') + '"'
+body = "".join(f"    {quote(i)},\n" for i in ids)
+text = open(runner, encoding="utf-8").read()
+text = re.sub(
+    r"(const KNOWN_FAILURES: &\[&str\] = &\[\n).*?(\];)",
+    lambda m: m.group(1) + body + m.group(2),
+    text,
+    count=1,
+    flags=re.S,
+)
+open(runner, "w", encoding="utf-8").write(text)
+print(f"{len(ids)} known failures", file=sys.stderr)
+PY
+
+echo "vendored JSON-Schema-Test-Suite at $sha"
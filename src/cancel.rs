@@ -0,0 +1,83 @@
+/// Cooperative cancellation for long-running work (validation, symbols,
+/// sorting). The server flags a token when the client cancels a request or
+/// edits the document the work is about; the work polls the token and bails
+/// out with `Cancelled`.
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Work stopped early because its token was cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+/// Why a token was cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelReason {
+    /// The client sent `$/cancelRequest`.
+    Requested,
+    /// The document changed, so the result would be stale.
+    ContentModified,
+}
+
+const ACTIVE: u8 = 0;
+const REQUESTED: u8 = 1;
+const CONTENT_MODIFIED: u8 = 2;
+
+/// Shared cancellation flag. Clones observe the same state.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicU8>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the token. The first reason given sticks.
+    pub fn cancel(&self, reason: CancelReason) {
+        let value = match reason {
+            CancelReason::Requested => REQUESTED,
+            CancelReason::ContentModified => CONTENT_MODIFIED,
+        };
+        let _ = self
+            .0
+            .compare_exchange(ACTIVE, value, Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    pub fn reason(&self) -> Option<CancelReason> {
+        match self.0.load(Ordering::Relaxed) {
+            REQUESTED => Some(CancelReason::Requested),
+            CONTENT_MODIFIED => Some(CancelReason::ContentModified),
+            _ => None,
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed) != ACTIVE
+    }
+
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_state_and_first_reason_wins() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        assert_eq!(clone.check(), Ok(()));
+        assert_eq!(token.reason(), None);
+
+        clone.cancel(CancelReason::ContentModified);
+        token.cancel(CancelReason::Requested);
+        assert!(token.is_cancelled());
+        assert_eq!(token.check(), Err(Cancelled));
+        assert_eq!(token.reason(), Some(CancelReason::ContentModified));
+    }
+}
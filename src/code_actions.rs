@@ -0,0 +1,509 @@
+/// Code actions: quick fixes for the diagnostics this server publishes,
+/// driven by each diagnostic's `code` and `data`.
+use std::collections::HashMap;
+
+use lsp_types::*;
+use tree_sitter::Node;
+
+use crate::diagnostics::codes;
+use crate::document::Document;
+use crate::schema::validation::ErrorCode;
+use crate::tree::{self, kinds};
+
+/// Quick fixes for the given diagnostics (as sent back by the client in the
+/// code action request context).
+pub fn code_actions(doc: &Document, uri: &Uri, diagnostics: &[Diagnostic]) -> Vec<CodeAction> {
+    diagnostics
+        .iter()
+        .filter(|d| d.source.as_deref() == Some("json"))
+        .filter_map(|d| {
+            let (title, edits) = quick_fix(doc, d)?;
+            Some(CodeAction {
+                title,
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![d.clone()]),
+                edit: Some(WorkspaceEdit {
+                    changes: Some(HashMap::from([(uri.clone(), edits)])),
+                    ..WorkspaceEdit::default()
+                }),
+                is_preferred: Some(true),
+                ..CodeAction::default()
+            })
+        })
+        .collect()
+}
+
+fn quick_fix(doc: &Document, diag: &Diagnostic) -> Option<(String, Vec<TextEdit>)> {
+    let Some(NumberOrString::String(code)) = &diag.code else {
+        return None;
+    };
+    let start = doc.offset_of(diag.range.start);
+    let end = doc.offset_of(diag.range.end);
+
+    if let Some(code) = ErrorCode::parse(code) {
+        return match code {
+            ErrorCode::MissingProperty | ErrorCode::MissingDependency => {
+                insert_property(doc, start, end, diag.data.as_ref()?)
+            }
+            ErrorCode::PropertyNotAllowed => remove_property(doc, start, end),
+            ErrorCode::EnumMismatch | ErrorCode::ConstMismatch => {
+                replace_value(doc, start, end, diag.data.as_ref()?)
+            }
+            _ => None,
+        };
+    }
+    match code.as_str() {
+        codes::DUPLICATE_KEY => remove_property(doc, start, end),
+        codes::TRAILING_COMMA => Some((
+            "Remove trailing comma".into(),
+            vec![TextEdit {
+                range: diag.range,
+                new_text: String::new(),
+            }],
+        )),
+        codes::COMMENT => Some(remove_comment(doc, start, end)),
+        _ => None,
+    }
+}
+
+/// The node spanning exactly `start..end`.
+fn node_at(doc: &Document, start: usize, end: usize) -> Option<Node<'_>> {
+    doc.tree
+        .root_node()
+        .descendant_for_byte_range(start, end)
+        .filter(|n| n.start_byte() == start && n.end_byte() == end)
+}
+
+fn edit(doc: &Document, start: usize, end: usize, new_text: String) -> TextEdit {
+    TextEdit {
+        range: doc.range_of(start, end),
+        new_text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics;
+    use crate::schema::validation::{self, RegexCache, ValidationOptions};
+    use crate::schema::{JsonSchema, SchemaStore};
+
+    /// Every quick fix for `src` as `(title, fixed source)`.
+    fn fixes(src: &str, schema: Option<&str>, strict: bool) -> Vec<(String, String)> {
+        let doc = Document::new(src.into(), 0);
+        let mut diags = diagnostics::syntax_diagnostics(&doc, strict);
+        if let Some(schema) = schema {
+            let schema = JsonSchema::from_value(&serde_json::from_str(schema).unwrap());
+            let root = tree::root_value(&doc.tree).unwrap();
+            let errors = validation::validate(
+                root,
+                doc.source(),
+                &schema,
+                SchemaStore::new().registry(),
+                &RegexCache::new(),
+                ValidationOptions::default(),
+            );
+            diags.extend(
+                errors
+                    .iter()
+                    .map(|e| diagnostics::schema_diagnostic(&doc, e)),
+            );
+        }
+        let uri: Uri = "file:///test.json".parse().unwrap();
+        code_actions(&doc, &uri, &diags)
+            .into_iter()
+            .map(|action| {
+                let mut edits = action.edit.unwrap().changes.unwrap().remove(&uri).unwrap();
+                edits.sort_by_key(|e| std::cmp::Reverse(doc.offset_of(e.range.start)));
+                let mut text = src.to_string();
+                for e in edits {
+                    let start = doc.offset_of(e.range.start);
+                    let end = doc.offset_of(e.range.end);
+                    text.replace_range(start..end, &e.new_text);
+                }
+                (action.title, text)
+            })
+            .collect()
+    }
+
+    fn only_fix(src: &str, schema: Option<&str>, strict: bool) -> (String, String) {
+        let mut all = fixes(src, schema, strict);
+        assert_eq!(all.len(), 1, "{all:?}");
+        all.remove(0)
+    }
+
+    #[test]
+    fn insert_missing_property_with_default() {
+        let schema =
+            r#"{"required": ["b"], "properties": {"b": {"type": "string", "default": "x"}}}"#;
+        let (title, text) = only_fix(r#"{"a": 1}"#, Some(schema), false);
+        assert_eq!(title, r#"Add property "b""#);
+        assert_eq!(text, r#"{"a": 1, "b": "x"}"#);
+    }
+
+    #[test]
+    fn insert_missing_property_multiline() {
+        let schema = r#"{"required": ["b"], "properties": {"b": {"type": "array"}}}"#;
+        let (_, text) = only_fix("{\n    \"a\": 1\n}", Some(schema), false);
+        assert_eq!(text, "{\n    \"a\": 1,\n    \"b\": []\n}");
+    }
+
+    #[test]
+    fn insert_missing_property_into_empty_object() {
+        let schema = r#"{"required": ["b"], "properties": {"b": {"enum": ["x", "y"]}}}"#;
+        let (_, text) = only_fix("{}", Some(schema), false);
+        assert_eq!(text, r#"{"b": "x"}"#);
+    }
+
+    #[test]
+    fn insert_missing_property_follows_ref() {
+        let schema = r##"{
+            "required": ["b"],
+            "properties": {"b": {"$ref": "#/$defs/flag"}},
+            "$defs": {"flag": {"type": "boolean"}}
+        }"##;
+        let (_, text) = only_fix("{}", Some(schema), false);
+        assert_eq!(text, r#"{"b": false}"#);
+    }
+
+    #[test]
+    fn remove_property_not_allowed() {
+        let schema = r#"{"properties": {"a": {}, "c": {}}, "additionalProperties": false}"#;
+        let (title, text) = only_fix(r#"{"a": 1, "b": 2, "c": 3}"#, Some(schema), false);
+        assert_eq!(title, r#"Remove property "b""#);
+        assert_eq!(text, r#"{"a": 1, "c": 3}"#);
+
+        let (_, text) = only_fix("{\n  \"a\": 1,\n  \"b\": 2\n}", Some(schema), false);
+        assert_eq!(text, "{\n  \"a\": 1\n}");
+
+        let (_, text) = only_fix(r#"{ "b": 2 }"#, Some(schema), false);
+        assert_eq!(text, "{}");
+    }
+
+    #[test]
+    fn remove_property_keeps_comments() {
+        let schema = r#"{"properties": {"a": {}, "c": {}}, "additionalProperties": false}"#;
+        let cases = [
+            (
+                "{\"a\": 1, \"c\": 3, // note\n \"b\": 2}",
+                "{\"a\": 1, \"c\": 3 // note\n }",
+            ),
+            (
+                "{\"a\": 1, \"b\": 2, // note\n \"c\": 3}",
+                "{\"a\": 1, // note\n \"c\": 3}",
+            ),
+            (
+                r#"{"a": 1, /* x */ "b": 2, "c": 3}"#,
+                r#"{"a": 1, /* x */ "c": 3}"#,
+            ),
+            (r#"{"a": 1 /* x */, "b": 2}"#, r#"{"a": 1 /* x */}"#),
+            ("{\"a\": 1 // note\n, \"b\": 2}", "{\"a\": 1 // note\n}"),
+            ("{\"b\": 2 // only\n}", "{ // only\n}"),
+        ];
+        for (src, expected) in cases {
+            let (_, text) = only_fix(src, Some(schema), false);
+            assert_eq!(text, expected, "{src}");
+        }
+    }
+
+    #[test]
+    fn replace_enum_with_closest_value() {
+        let schema = r#"{"enum": ["development", "production", "test"]}"#;
+        let (title, text) = only_fix(r#""prodution""#, Some(schema), false);
+        assert_eq!(title, r#"Replace with "production""#);
+        assert_eq!(text, r#""production""#);
+    }
+
+    #[test]
+    fn replace_const_value() {
+        let (_, text) = only_fix("2", Some(r#"{"const": 1}"#), false);
+        assert_eq!(text, "1");
+    }
+
+    #[test]
+    fn remove_duplicate_key() {
+        let (title, text) = only_fix(r#"{"a": 1, "a": 2}"#, None, false);
+        assert_eq!(title, r#"Remove property "a""#);
+        assert_eq!(text, r#"{"a": 1}"#);
+    }
+
+    #[test]
+    fn strict_mode_removes_trailing_commas() {
+        assert!(fixes(r#"{"a": [1, 2,],}"#, None, false).is_empty());
+        let mut texts: Vec<String> = fixes(r#"{"a": [1, 2,],}"#, None, true)
+            .into_iter()
+            .map(|(_, text)| text)
+            .collect();
+        texts.sort();
+        assert_eq!(texts, [r#"{"a": [1, 2,]}"#, r#"{"a": [1, 2],}"#]);
+    }
+
+    #[test]
+    fn strict_mode_removes_comments() {
+        let src = "{\n  // note\n  \"a\": 1 /* inline */\n}";
+        assert!(fixes(src, None, false).is_empty());
+        let texts: Vec<String> = fixes(src, None, true)
+            .into_iter()
+            .map(|(_, text)| text)
+            .collect();
+        assert_eq!(
+            texts,
+            [
+                "{\n  \"a\": 1 /* inline */\n}",
+                "{\n  // note\n  \"a\": 1\n}"
+            ]
+        );
+    }
+
+    #[test]
+    fn edit_distance_counts_edits() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("same", "same"), 0);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Missing properties
+// ---------------------------------------------------------------------------
+
+/// Insert `"property": value` as the last member of the object, following
+/// the layout of its existing members.
+fn insert_property(
+    doc: &Document,
+    start: usize,
+    end: usize,
+    data: &serde_json::Value,
+) -> Option<(String, Vec<TextEdit>)> {
+    let object = node_at(doc, start, end).filter(|n| n.kind() == kinds::OBJECT)?;
+    let name = data.get("property")?.as_str()?;
+    let value = data
+        .get("value")
+        .cloned()
+        .unwrap_or(serde_json::Value::Null);
+    let member = format!(
+        "{}: {}",
+        serde_json::to_string(name).ok()?,
+        serde_json::to_string(&value).ok()?
+    );
+
+    let source = doc.source();
+    let mut cursor = object.walk();
+    let pairs = tree::object_pairs(object, &mut cursor);
+    let edit = match pairs.last() {
+        Some(last) if last.start_position().row > object.start_position().row => {
+            let indent = line_indent(source, last.start_byte());
+            edit(
+                doc,
+                last.end_byte(),
+                last.end_byte(),
+                format!(",\n{indent}{member}"),
+            )
+        }
+        Some(last) => edit(doc, last.end_byte(), last.end_byte(), format!(", {member}")),
+        None => {
+            let open = object.start_byte() + 1;
+            let close = object.end_byte() - 1;
+            if object.start_position().row == object.end_position().row {
+                edit(doc, open, close, member)
+            } else {
+                let indent = line_indent(source, object.start_byte());
+                edit(doc, open, close, format!("\n{indent}  {member}\n{indent}"))
+            }
+        }
+    };
+    Some((format!("Add property \"{name}\""), vec![edit]))
+}
+
+/// The whitespace at the start of the line containing `offset`.
+fn line_indent(source: &[u8], offset: usize) -> String {
+    let line_start = source[..offset]
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |i| i + 1);
+    source[line_start..offset]
+        .iter()
+        .take_while(|b| **b == b' ' || **b == b'\t')
+        .map(|&b| b as char)
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Removing properties
+// ---------------------------------------------------------------------------
+
+/// Remove the member whose key spans `start..end`, together with the comma
+/// that separates it from its neighbours. Comments around the member are
+/// kept so that JSONC documents stay valid.
+fn remove_property(doc: &Document, start: usize, end: usize) -> Option<(String, Vec<TextEdit>)> {
+    let key = node_at(doc, start, end).filter(|n| n.kind() == kinds::STRING)?;
+    let pair = key.parent().filter(|p| p.kind() == kinds::PAIR)?;
+    let object = pair.parent()?;
+    let name = tree::string_value(key, doc.source())?;
+
+    let next = skip_comments(pair, Node::next_sibling);
+    let prev = skip_comments(pair, Node::prev_sibling);
+    let mut edits = Vec::new();
+    match (prev, next) {
+        // Followed by another member: remove up to whatever comes after
+        // the comma, which may be a comment about that member.
+        (_, Some(comma))
+            if comma.kind() == ","
+                && skip_comments(comma, Node::next_sibling)
+                    .is_some_and(|n| n.kind() == kinds::PAIR) =>
+        {
+            let to = comma.next_sibling()?.start_byte();
+            edits.push(edit(doc, pair.start_byte(), to, String::new()));
+        }
+        // Last member: remove the comma before it as well.
+        (Some(comma), _) if comma.kind() == "," => {
+            let to = next
+                .filter(|n| n.kind() == ",")
+                .map_or(pair.end_byte(), |n| n.end_byte());
+            if pair.prev_sibling() == Some(comma) {
+                // Take the whitespace before the comma too, unless it ends a
+                // line comment that the closing brace would then fall into.
+                let from = comma
+                    .prev_sibling()
+                    .filter(|n| n.kind() != kinds::COMMENT)
+                    .map_or(comma.start_byte(), |n| n.end_byte());
+                edits.push(edit(doc, from, to, String::new()));
+            } else {
+                edits.push(edit(
+                    doc,
+                    comma.start_byte(),
+                    comma.end_byte(),
+                    String::new(),
+                ));
+                edits.push(edit(doc, pair.start_byte(), to, String::new()));
+            }
+        }
+        // Only member: empty the object, or just drop the member when
+        // comments would go with it.
+        (Some(open), _)
+            if open.kind() == "{" && next.is_none_or(|n| n.kind() == "}" || n.kind() == ",") =>
+        {
+            let to = next
+                .filter(|n| n.kind() == ",")
+                .map_or(pair.end_byte(), |n| n.end_byte());
+            let mut cursor = object.walk();
+            if object
+                .children(&mut cursor)
+                .any(|n| n.kind() == kinds::COMMENT)
+            {
+                edits.push(edit(doc, pair.start_byte(), to, String::new()));
+            } else {
+                edits.push(edit(
+                    doc,
+                    object.start_byte() + 1,
+                    object.end_byte() - 1,
+                    String::new(),
+                ));
+            }
+        }
+        _ => return None,
+    }
+    Some((format!("Remove property \"{name}\""), edits))
+}
+
+/// The nearest sibling of `node` in the direction of `step` that is not a
+/// comment.
+fn skip_comments<'t>(node: Node<'t>, step: fn(&Node<'t>) -> Option<Node<'t>>) -> Option<Node<'t>> {
+    let mut sibling = step(&node)?;
+    while sibling.kind() == kinds::COMMENT {
+        sibling = step(&sibling)?;
+    }
+    Some(sibling)
+}
+
+// ---------------------------------------------------------------------------
+// Enum / const values
+// ---------------------------------------------------------------------------
+
+/// Replace the value with the allowed value closest to it.
+fn replace_value(
+    doc: &Document,
+    start: usize,
+    end: usize,
+    data: &serde_json::Value,
+) -> Option<(String, Vec<TextEdit>)> {
+    let node = node_at(doc, start, end)?;
+    let values = data.get("values")?.as_array()?;
+    let source = doc.source();
+
+    let current = if node.kind() == kinds::STRING {
+        tree::string_value(node, source)?
+    } else {
+        node.utf8_text(source).ok()?.to_string()
+    };
+    let closest = values.iter().min_by_key(|v| match v {
+        serde_json::Value::String(s) if node.kind() == kinds::STRING => edit_distance(&current, s),
+        // Prefer candidates of the same kind; compare others textually.
+        serde_json::Value::String(s) => edit_distance(&current, s) + current.len().max(s.len()),
+        v => {
+            let text = v.to_string();
+            let penalty = if node.kind() == kinds::STRING {
+                text.len().max(current.len())
+            } else {
+                0
+            };
+            edit_distance(&current, &text) + penalty
+        }
+    })?;
+    let text = serde_json::to_string(closest).ok()?;
+    Some((
+        format!("Replace with {text}"),
+        vec![edit(doc, start, end, text)],
+    ))
+}
+
+/// Levenshtein distance over chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (diag + usize::from(ca != *cb))
+                .min(above + 1)
+                .min(row[j] + 1);
+            diag = above;
+        }
+    }
+    row[b.len()]
+}
+
+// ---------------------------------------------------------------------------
+// Comments
+// ---------------------------------------------------------------------------
+
+/// Remove a comment. A comment alone on its line takes the line with it;
+/// otherwise the whitespace before it goes.
+fn remove_comment(doc: &Document, start: usize, end: usize) -> (String, Vec<TextEdit>) {
+    let source = doc.source();
+    let line_start = source[..start]
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |i| i + 1);
+    let line_end = source[end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(source.len(), |i| end + i);
+    let blank = |s: &[u8]| s.iter().all(|b| b.is_ascii_whitespace());
+
+    let (from, to) = if blank(&source[line_start..start]) && blank(&source[end..line_end]) {
+        (line_start, (line_end + 1).min(source.len()))
+    } else {
+        let from = source[..start]
+            .iter()
+            .rposition(|b| !matches!(b, b' ' | b'\t'))
+            .map_or(0, |i| i + 1);
+        (from, end)
+    };
+    (
+        "Remove comment".into(),
+        vec![edit(doc, from, to, String::new())],
+    )
+}
@@ -10,6 +10,7 @@ use lsp_types::*;
 use tree_sitter::Node;
 
 use crate::document::Document;
+use crate::schema::resolver::SchemaStore;
 use crate::schema::types::*;
 use crate::tree::{self, kinds};
 
@@ -18,6 +19,7 @@ pub fn completions(
     doc: &Document,
     offset: usize,
     schema: Option<&Arc<JsonSchema>>,
+    store: &SchemaStore,
 ) -> Vec<CompletionItem> {
     let mut items = Vec::new();
 
@@ -26,8 +28,14 @@ pub fn completions(
     match ctx {
         Context::PropertyName { object } => {
             if let Some(schema) = schema {
-                let sub = resolve_schema_for_node(doc, object, schema);
-                complete_property_names(doc, object, &sub, &mut items);
+                let sub = resolve_schema_for_node(doc, object, schema, store);
+                complete_property_names(
+                    doc,
+                    object,
+                    &sub.branches(store.registry()),
+                    store,
+                    &mut items,
+                );
             }
         }
         Context::PropertyValue {
@@ -36,9 +44,9 @@ pub fn completions(
             has_value,
         } => {
             if let Some(schema) = schema {
-                let sub = resolve_schema_for_node(doc, object, schema);
-                if let Some(prop_schema) = sub.properties.get(&key) {
-                    complete_value(prop_schema, &mut items);
+                let sub = resolve_schema_for_node(doc, object, schema, store);
+                if let Some(prop_schema) = sub.resolve_path_segment(&key, store.registry()) {
+                    complete_value(&prop_schema.branches(store.registry()), &mut items);
                 }
             }
             if !has_value {
@@ -49,15 +57,21 @@ pub fn completions(
         }
         Context::ArrayItem { array, index } => {
             if let Some(schema) = schema {
-                let sub = resolve_schema_for_node(doc, array, schema);
-                if let Some(item_schema) = array_item_schema(&sub, index) {
-                    complete_value(&item_schema, &mut items);
+                let sub = resolve_schema_for_node(doc, array, schema, store);
+                if let Some(item_schema) =
+                    sub.resolve_path_segment(&index.to_string(), store.registry())
+                {
+                    complete_value(&item_schema.branches(store.registry()), &mut items);
                 }
             }
         }
         Context::None => {}
     }
 
+    // The same property or value can be offered by several branches.
+    let mut seen = HashSet::new();
+    items.retain(|item| seen.insert(item.label.clone()));
+
     items
 }
 
@@ -211,22 +225,12 @@ fn resolve_schema_for_node(
     doc: &Document,
     node: Node<'_>,
     root_schema: &Arc<JsonSchema>,
-) -> Arc<JsonSchema> {
+    store: &SchemaStore,
+) -> ScopedSchema {
     let path = tree::json_path(node, doc.source());
-    let mut current = root_schema.clone();
-    for seg in &path {
-        current = current
-            .resolve_path_segment(seg)
-            .unwrap_or_else(|| Arc::new(JsonSchema::default()));
-    }
-    current
-}
-
-fn array_item_schema(schema: &JsonSchema, index: usize) -> Option<Arc<JsonSchema>> {
-    if let Some(ps) = schema.prefix_items.get(index) {
-        return Some(ps.clone());
-    }
-    schema.items.as_ref()?.as_schema().cloned()
+    ScopedSchema::new(root_schema)
+        .resolve_path(&path, store.registry())
+        .unwrap_or_else(|| ScopedSchema::new(&Arc::new(JsonSchema::default())))
 }
 
 // ---------------------------------------------------------------------------
@@ -236,7 +240,8 @@ fn array_item_schema(schema: &JsonSchema, index: usize) -> Option<Arc<JsonSchema
 fn complete_property_names(
     doc: &Document,
     object: Node<'_>,
-    schema: &JsonSchema,
+    branches: &[ScopedSchema],
+    store: &SchemaStore,
     items: &mut Vec<CompletionItem>,
 ) {
     // Collect existing keys.
@@ -247,74 +252,73 @@ fn complete_property_names(
         .filter_map(|p| tree::pair_key_unescaped(*p, doc.source()))
         .collect();
 
-    for (key, prop_schema) in &schema.properties {
-        if existing.contains(key) || prop_schema.do_not_suggest {
-            continue;
-        }
+    // allOf/anyOf/oneOf/then/else and `$ref` targets all contribute, so a
+    // property is required if any branch requires it.
+    let required: HashSet<&str> = branches
+        .iter()
+        .flat_map(|b| b.schema.required.iter().map(String::as_str))
+        .collect();
 
-        let detail = prop_schema
-            .types
-            .first()
-            .map(|t| format!("{t:?}").to_lowercase());
+    for branch in branches {
+        for (key, prop_schema) in &branch.schema.properties {
+            if existing.contains(key) {
+                continue;
+            }
+            let prop_branches = branch.with_schema(prop_schema).branches(store.registry());
+            if prop_branches.iter().any(|b| b.schema.do_not_suggest) {
+                continue;
+            }
 
-        let documentation = prop_schema
-            .markdown_description
-            .as_deref()
-            .or(prop_schema.description.as_deref())
-            .map(|d| {
-                Documentation::MarkupContent(MarkupContent {
-                    kind: MarkupKind::Markdown,
-                    value: d.to_string(),
+            let detail = prop_branches
+                .iter()
+                .find_map(|b| b.schema.types.first())
+                .map(|t| format!("{t:?}").to_lowercase());
+
+            let documentation = prop_branches
+                .iter()
+                .find_map(|b| {
+                    b.schema
+                        .markdown_description
+                        .as_deref()
+                        .or(b.schema.description.as_deref())
                 })
-            });
+                .map(|d| {
+                    Documentation::MarkupContent(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value: d.to_string(),
+                    })
+                });
+
+            let is_required = required.contains(key.as_str());
+            let default_value = default_value_snippet(&prop_branches);
+            let insert_text = format!("\"{key}\": {default_value}");
+
+            let mut item = CompletionItem {
+                label: key.clone(),
+                kind: Some(CompletionItemKind::PROPERTY),
+                detail,
+                documentation,
+                insert_text: Some(insert_text),
+                insert_text_format: Some(InsertTextFormat::SNIPPET),
+                sort_text: Some(if is_required {
+                    format!("0_{key}")
+                } else {
+                    format!("1_{key}")
+                }),
+                ..CompletionItem::default()
+            };
 
-        let is_required = schema.required.contains(key);
-        let default_value = default_value_snippet(prop_schema);
-        let insert_text = format!("\"{key}\": {default_value}");
-
-        let mut item = CompletionItem {
-            label: key.clone(),
-            kind: Some(CompletionItemKind::PROPERTY),
-            detail,
-            documentation,
-            insert_text: Some(insert_text),
-            insert_text_format: Some(InsertTextFormat::SNIPPET),
-            sort_text: Some(if is_required {
-                format!("0_{key}")
-            } else {
-                format!("1_{key}")
-            }),
-            ..CompletionItem::default()
-        };
+            if prop_branches.iter().any(|b| b.schema.deprecated) {
+                item.deprecated = Some(true);
+                item.tags = Some(vec![CompletionItemTag::DEPRECATED]);
+            }
 
-        if prop_schema.deprecated {
-            item.deprecated = Some(true);
-            item.tags = Some(vec![CompletionItemTag::DEPRECATED]);
+            items.push(item);
         }
-
-        items.push(item);
-    }
-
-    // allOf: merge properties from all subschemas.
-    for sub in &schema.all_of {
-        complete_property_names(doc, object, sub, items);
-    }
-
-    // anyOf/oneOf: show union of all branch completions.
-    for sub in schema.any_of.iter().chain(schema.one_of.iter()) {
-        complete_property_names(doc, object, sub, items);
-    }
-
-    // if/then/else: offer from relevant branch.
-    if let Some(ref then_schema) = schema.then_schema {
-        complete_property_names(doc, object, then_schema, items);
-    }
-    if let Some(ref else_schema) = schema.else_schema {
-        complete_property_names(doc, object, else_schema, items);
     }
 
     // Default snippets from schema.
-    for snip in &schema.default_snippets {
+    for snip in branches.iter().flat_map(|b| &b.schema.default_snippets) {
         if let Some(ref body) = snip.body {
             let label = snip.label.as_deref().unwrap_or("snippet");
             let insert = serde_json::to_string_pretty(body).unwrap_or_default();
@@ -330,7 +334,13 @@ fn complete_property_names(
     }
 }
 
-fn complete_value(schema: &JsonSchema, items: &mut Vec<CompletionItem>) {
+fn complete_value(branches: &[ScopedSchema], items: &mut Vec<CompletionItem>) {
+    for branch in branches {
+        complete_value_from(&branch.schema, items);
+    }
+}
+
+fn complete_value_from(schema: &JsonSchema, items: &mut Vec<CompletionItem>) {
     // Enum values with optional descriptions.
     for (i, val) in schema.enum_values.iter().enumerate() {
         let label = format_json_value(val);
@@ -392,17 +402,18 @@ fn complete_value(schema: &JsonSchema, items: &mut Vec<CompletionItem>) {
     }
 }
 
-fn default_value_snippet(schema: &JsonSchema) -> String {
-    if let Some(ref c) = schema.const_value {
+fn default_value_snippet(branches: &[ScopedSchema]) -> String {
+    let schemas = || branches.iter().map(|b| &*b.schema);
+    if let Some(c) = schemas().find_map(|s| s.const_value.as_ref()) {
         return format_json_value(c);
     }
-    if schema.enum_values.len() == 1 {
-        return format_json_value(&schema.enum_values[0]);
+    if let Some(s) = schemas().find(|s| s.enum_values.len() == 1) {
+        return format_json_value(&s.enum_values[0]);
     }
-    if let Some(ref def) = schema.default {
+    if let Some(def) = schemas().find_map(|s| s.default.as_ref()) {
         return format_json_value(def);
     }
-    match schema.types.first() {
+    match schemas().find_map(|s| s.types.first()) {
         Some(SchemaType::String) => "\"$1\"".into(),
         Some(SchemaType::Number | SchemaType::Integer) => "${1:0}".into(),
         Some(SchemaType::Boolean) => "${1:false}".into(),
@@ -439,3 +450,32 @@ fn snippet_item(label: &str, detail: &str, insert: &str) -> CompletionItem {
         ..CompletionItem::default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(src: &str, offset: usize, schema: serde_json::Value) -> Vec<String> {
+        let doc = Document::new(src.into(), 0);
+        let schema = JsonSchema::from_value(&schema);
+        completions(&doc, offset, Some(&schema), &SchemaStore::new())
+            .into_iter()
+            .map(|item| item.label)
+            .collect()
+    }
+
+    #[test]
+    fn array_items_complete_from_item_keywords() {
+        let schema = serde_json::json!({
+            "anyOf": [
+                {"items": {"enum": ["any"]}},
+                {"prefixItems": [{"enum": ["first"]}]},
+                {"additionalProperties": {"enum": ["property"]}}
+            ]
+        });
+        let src = "[ ]";
+        assert_eq!(labels(src, 1, schema.clone()), ["\"first\""]);
+        let src = "[\"first\", ]";
+        assert_eq!(labels(src, src.len() - 1, schema), ["\"any\""]);
+    }
+}
@@ -2,32 +2,72 @@
 /// nodes and detects duplicate keys in objects.
 use std::collections::HashSet;
 
-use lsp_types::{Diagnostic, DiagnosticSeverity};
+use lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString};
 use tree_sitter::Node;
 
 use crate::document::Document;
+use crate::schema::validation::{Severity, ValidationError};
 use crate::tree::{self, kinds};
 
-/// Produce all syntax diagnostics for a document.
-pub fn syntax_diagnostics(doc: &Document) -> Vec<Diagnostic> {
+/// Diagnostic codes published with syntax diagnostics, so code actions can
+/// recognise them.
+pub mod codes {
+    pub const SYNTAX_ERROR: &str = "syntax-error";
+    pub const DUPLICATE_KEY: &str = "duplicate-key";
+    pub const TRAILING_COMMA: &str = "trailing-comma";
+    pub const COMMENT: &str = "comment";
+}
+
+/// Produce all syntax diagnostics for a document. In `strict` mode trailing
+/// commas and comments are reported instead of tolerated.
+pub fn syntax_diagnostics(doc: &Document, strict: bool) -> Vec<Diagnostic> {
     let mut diags = Vec::new();
     let root = doc.tree.root_node();
 
     if root.has_error() {
-        collect_errors(doc, root, &mut diags);
+        collect_errors(doc, root, strict, &mut diags);
     }
     collect_duplicate_keys(doc, root, &mut diags);
+    if strict {
+        collect_comments(doc, root, &mut diags);
+    }
 
     diags
 }
 
+/// Convert a schema validation error into a diagnostic, carrying its code
+/// and quick-fix data.
+pub fn schema_diagnostic(doc: &Document, error: &ValidationError) -> Diagnostic {
+    Diagnostic {
+        range: doc.range_of(error.start_byte, error.end_byte),
+        severity: Some(match error.severity {
+            Severity::Error => DiagnosticSeverity::ERROR,
+            Severity::Warning => DiagnosticSeverity::WARNING,
+        }),
+        code: code(error.code.as_str()),
+        source: Some("json".into()),
+        message: error.message.clone(),
+        data: error.data.clone(),
+        ..Diagnostic::default()
+    }
+}
+
+fn code(code: &str) -> Option<NumberOrString> {
+    Some(NumberOrString::String(code.into()))
+}
+
 /// Recursively collect ERROR and MISSING nodes, skipping trailing commas.
 /// tree-sitter-json treats trailing commas as errors, but they are widely
 /// accepted in practice (JSONC, tsconfig, VS Code settings), so we tolerate
-/// them silently.
-fn collect_errors(doc: &Document, node: Node<'_>, diags: &mut Vec<Diagnostic>) {
+/// them silently unless `strict` is set.
+fn collect_errors(doc: &Document, node: Node<'_>, strict: bool, diags: &mut Vec<Diagnostic>) {
     if node.is_error() {
         if is_trailing_comma_error(node, doc.source()) {
+            if strict {
+                let text = &doc.source()[node.start_byte()..node.end_byte()];
+                let comma = node.start_byte() + text.iter().position(|&b| b == b',').unwrap_or(0);
+                diags.push(trailing_comma(doc, comma));
+            }
             return;
         }
         // Suppress spurious closing bracket ERRORs that are artifacts of a
@@ -41,6 +81,7 @@ fn collect_errors(doc: &Document, node: Node<'_>, diags: &mut Vec<Diagnostic>) {
         diags.push(Diagnostic {
             range,
             severity: Some(DiagnosticSeverity::ERROR),
+            code: code(codes::SYNTAX_ERROR),
             source: Some("json".into()),
             message,
             ..Diagnostic::default()
@@ -50,6 +91,9 @@ fn collect_errors(doc: &Document, node: Node<'_>, diags: &mut Vec<Diagnostic>) {
 
     if node.is_missing() {
         if is_trailing_comma_missing(node) {
+            if strict && let Some(comma) = node.prev_sibling() {
+                diags.push(trailing_comma(doc, comma.start_byte()));
+            }
             return;
         }
         let range = doc.range_of(node.start_byte(), node.start_byte());
@@ -57,6 +101,7 @@ fn collect_errors(doc: &Document, node: Node<'_>, diags: &mut Vec<Diagnostic>) {
         diags.push(Diagnostic {
             range,
             severity: Some(DiagnosticSeverity::ERROR),
+            code: code(codes::SYNTAX_ERROR),
             source: Some("json".into()),
             message,
             ..Diagnostic::default()
@@ -68,7 +113,37 @@ fn collect_errors(doc: &Document, node: Node<'_>, diags: &mut Vec<Diagnostic>) {
     let mut cursor = node.walk();
     for child in node.children(&mut cursor) {
         if child.has_error() || child.is_error() || child.is_missing() {
-            collect_errors(doc, child, diags);
+            collect_errors(doc, child, strict, diags);
+        }
+    }
+}
+
+fn trailing_comma(doc: &Document, comma: usize) -> Diagnostic {
+    Diagnostic {
+        range: doc.range_of(comma, comma + 1),
+        severity: Some(DiagnosticSeverity::ERROR),
+        code: code(codes::TRAILING_COMMA),
+        source: Some("json".into()),
+        message: "Trailing comma.".into(),
+        ..Diagnostic::default()
+    }
+}
+
+/// Report every comment; only called in strict mode.
+fn collect_comments(doc: &Document, node: Node<'_>, diags: &mut Vec<Diagnostic>) {
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        if child.kind() == kinds::COMMENT {
+            diags.push(Diagnostic {
+                range: doc.range_of(child.start_byte(), child.end_byte()),
+                severity: Some(DiagnosticSeverity::ERROR),
+                code: code(codes::COMMENT),
+                source: Some("json".into()),
+                message: "Comments are not permitted in JSON.".into(),
+                ..Diagnostic::default()
+            });
+        } else if child.child_count() > 0 {
+            collect_comments(doc, child, diags);
         }
     }
 }
@@ -402,7 +477,7 @@ mod tests {
 
     fn errors(src: &str) -> Vec<Diagnostic> {
         let doc = Document::new(src.into(), 0);
-        syntax_diagnostics(&doc)
+        syntax_diagnostics(&doc, false)
     }
 
     fn error_messages(src: &str) -> Vec<String> {
@@ -706,6 +781,7 @@ pub fn collect_duplicate_keys(doc: &Document, node: Node<'_>, diags: &mut Vec<Di
                 diags.push(Diagnostic {
                     range,
                     severity: Some(DiagnosticSeverity::WARNING),
+                    code: code(codes::DUPLICATE_KEY),
                     source: Some("json".into()),
                     message: format!("Duplicate key \"{key}\""),
                     ..Diagnostic::default()
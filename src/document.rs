@@ -6,6 +6,7 @@
 /// - A tree-sitter `Tree` that is incrementally updated on edits
 /// - A per-document `JsonParser` instance
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use line_index::{LineCol, LineIndex, WideEncoding, WideLineCol};
 use lsp_types::{Position, Range, Uri};
@@ -66,6 +67,20 @@ pub struct Document {
     parser: JsonParser,
 }
 
+/// Copies the text and tree; the copy gets a parser of its own.
+impl Clone for Document {
+    fn clone(&self) -> Self {
+        Document {
+            text: self.text.clone(),
+            version: self.version,
+            line_index: self.line_index.clone(),
+            tree: self.tree.clone(),
+            is_ascii: self.is_ascii,
+            parser: JsonParser::new(),
+        }
+    }
+}
+
 impl Document {
     pub fn new(text: String, version: i32) -> Self {
         let mut parser = JsonParser::new();
@@ -224,7 +239,9 @@ impl Document {
 
 /// Manages all currently open documents.
 pub struct DocumentStore {
-    docs: HashMap<Uri, Document>,
+    /// Shared so that validation can keep a snapshot of a document without
+    /// holding the server state lock; edits copy it if one is held.
+    docs: HashMap<Uri, Arc<Document>>,
 }
 
 impl DocumentStore {
@@ -235,7 +252,8 @@ impl DocumentStore {
     }
 
     pub fn open(&mut self, uri: Uri, text: String, version: i32) {
-        self.docs.insert(uri, Document::new(text, version));
+        self.docs
+            .insert(uri, Arc::new(Document::new(text, version)));
     }
 
     pub fn close(&mut self, uri: &Uri) {
@@ -243,11 +261,16 @@ impl DocumentStore {
     }
 
     pub fn get(&self, uri: &Uri) -> Option<&Document> {
-        self.docs.get(uri)
+        self.docs.get(uri).map(|doc| &**doc)
+    }
+
+    /// The document as it is now, unaffected by later edits.
+    pub fn snapshot(&self, uri: &Uri) -> Option<Arc<Document>> {
+        self.docs.get(uri).cloned()
     }
 
     pub fn get_mut(&mut self, uri: &Uri) -> Option<&mut Document> {
-        self.docs.get_mut(uri)
+        self.docs.get_mut(uri).map(Arc::make_mut)
     }
 
     pub fn uris(&self) -> impl Iterator<Item = &Uri> {
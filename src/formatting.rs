@@ -1,22 +1,94 @@
 /// JSON formatting and sorting.
 ///
-/// Formatting walks the tree-sitter CST directly, avoiding a redundant
-/// serde_json round-trip. Sorting still uses serde_json since tree-sitter
-/// doesn't provide sorted iteration.
+/// Both work on the tree-sitter CST directly, so comments, string escapes
+/// and number literals survive. Formatting re-lays out members one per line
+/// (keeping blank lines between groups); sorting only moves pairs around and
+/// leaves the rest of the text untouched.
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use lsp_types::*;
 use tree_sitter::Node;
 
+use crate::cancel::{CancelToken, Cancelled};
 use crate::document::Document;
-use crate::tree::kinds;
+use crate::schema::resolver::SchemaStore;
+use crate::schema::types::{JsonSchema, ScopedSchema};
+use crate::tree::{self, kinds};
+
+/// Key order used by the sort command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Keys sorted alphabetically.
+    #[default]
+    Alphabetical,
+    /// Keys in the order the document's schema declares `properties`;
+    /// keys the schema doesn't declare follow alphabetically.
+    Schema,
+}
+
+impl SortOrder {
+    /// Parse a `json.sort.order` setting value.
+    pub fn from_setting(s: &str) -> Option<Self> {
+        match s {
+            "alphabetical" => Some(SortOrder::Alphabetical),
+            "schema" => Some(SortOrder::Schema),
+            _ => None,
+        }
+    }
+}
+
+/// Formatter settings (`json.format.*`), applied on top of the editor's
+/// indentation options.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FormatSettings {
+    /// Objects and arrays that fit within this many columns stay on one line.
+    pub print_width: Option<usize>,
+    /// Keep the document's existing line breaks between members.
+    pub keep_lines: bool,
+    /// Write `"key" : value` instead of `"key": value`.
+    pub space_before_colon: bool,
+    /// End the document with a newline. When unset, formatting follows the
+    /// editor's `insertFinalNewline` (default on) and sorting leaves the end
+    /// of the document alone.
+    pub insert_final_newline: Option<bool>,
+}
+
+impl FormatSettings {
+    /// Read the `json.format` settings object; missing keys keep their
+    /// defaults.
+    pub fn from_settings(value: &serde_json::Value) -> Self {
+        FormatSettings {
+            print_width: value
+                .get("printWidth")
+                .and_then(|v| v.as_u64())
+                .filter(|&w| w > 0)
+                .map(|w| w as usize),
+            keep_lines: value
+                .get("keepLines")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+            space_before_colon: value
+                .get("spaceBeforeColon")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+            insert_final_newline: value.get("insertFinalNewline").and_then(|v| v.as_bool()),
+        }
+    }
+}
 
 /// Format the entire document.
-pub fn format_document(doc: &Document, options: &FormattingOptions) -> Vec<TextEdit> {
+pub fn format_document(
+    doc: &Document,
+    options: &FormattingOptions,
+    settings: &FormatSettings,
+) -> Vec<TextEdit> {
     // Only format if the document has no syntax errors.
     if doc.tree.root_node().has_error() {
         return Vec::new();
     }
 
-    let formatted = match reformat(doc, options) {
+    let formatted = match reformat(doc, options, settings) {
         Some(f) => f,
         None => return Vec::new(),
     };
@@ -38,39 +110,276 @@ pub fn format_document(doc: &Document, options: &FormattingOptions) -> Vec<TextE
     }]
 }
 
-/// Format a range within the document.
-/// For simplicity, we format the whole document — most editors handle this fine.
-pub fn format_range(doc: &Document, _range: Range, options: &FormattingOptions) -> Vec<TextEdit> {
-    format_document(doc, options)
+/// Format a range within the document: the smallest object, array or pair
+/// covering it is reformatted in place, keeping the indentation of the line
+/// it starts on. Only that node has to be free of syntax errors.
+pub fn format_range(
+    doc: &Document,
+    range: Range,
+    options: &FormattingOptions,
+    settings: &FormatSettings,
+) -> Vec<TextEdit> {
+    let start = doc.offset_of(range.start);
+    let end = doc.offset_of(range.end);
+    match enclosing_node(doc, start, end) {
+        Some(node) => format_in_place(doc, node, options, settings),
+        None => Vec::new(),
+    }
 }
 
-/// Sort all object properties alphabetically (recursive), preserving indent style.
-/// Round-trips through serde_json to sort, then re-serializes with the document's
-/// detected indentation.
-pub fn sort_document(doc: &Document) -> Vec<TextEdit> {
-    if doc.tree.root_node().has_error() {
+/// Format as the user types: a closing `}` or `]` formats the container it
+/// closes, and a newline re-indents the new line for the container the
+/// cursor is in. `position` is just after the typed character.
+pub fn format_on_type(
+    doc: &Document,
+    position: Position,
+    ch: &str,
+    options: &FormattingOptions,
+    settings: &FormatSettings,
+) -> Vec<TextEdit> {
+    let offset = doc.offset_of(position);
+    match ch {
+        "}" | "]" if offset > 0 => {
+            let closer = doc
+                .tree
+                .root_node()
+                .descendant_for_byte_range(offset - 1, offset)
+                .filter(|n| n.kind() == ch);
+            match closer.and_then(|n| n.parent()) {
+                Some(container) if matches!(container.kind(), kinds::OBJECT | kinds::ARRAY) => {
+                    format_in_place(doc, container, options, settings)
+                }
+                _ => Vec::new(),
+            }
+        }
+        "\n" => reindent_line(doc, position.line, options),
+        _ => Vec::new(),
+    }
+}
+
+/// Reformat `node` where it stands; nothing if it contains syntax errors.
+fn format_in_place(
+    doc: &Document,
+    node: Node<'_>,
+    options: &FormattingOptions,
+    settings: &FormatSettings,
+) -> Vec<TextEdit> {
+    if node.has_error() {
         return Vec::new();
     }
 
-    let val: serde_json::Value = match serde_json::from_str(&doc.text) {
-        Ok(v) => v,
-        Err(_) => return Vec::new(),
+    // Nested lines continue from the indentation of the node's first line.
+    let base = line_indent(&doc.text, node.start_byte());
+    let line_start = doc.text[..node.start_byte()]
+        .rfind('\n')
+        .map_or(0, |i| i + 1);
+    let mut printer = Printer::new(doc.source(), options, settings);
+    printer.base_column = printer.width(&doc.text[line_start..node.start_byte()]);
+    printer.base_indent = base.to_string();
+
+    let mut out = String::new();
+    // A comma after the node stays on the line it ends.
+    let after = usize::from(node.next_sibling().is_some_and(|n| n.kind() == ","));
+    if node.kind() == kinds::PAIR {
+        printer.pair(node, 0, after, &mut out);
+    } else {
+        printer.node(node, 0, after, &mut out);
+    }
+
+    let original = &doc.text[node.start_byte()..node.end_byte()];
+    whitespace_edits(doc, node.start_byte(), original, &out)
+}
+
+/// The smallest object, array or pair spanning `start..end`.
+fn enclosing_node(doc: &Document, start: usize, end: usize) -> Option<Node<'_>> {
+    let mut node = doc.tree.root_node().descendant_for_byte_range(start, end)?;
+    if node.kind() == kinds::DOCUMENT {
+        node = tree::root_value(&doc.tree)?;
+    }
+    loop {
+        if matches!(node.kind(), kinds::OBJECT | kinds::ARRAY | kinds::PAIR) {
+            return Some(node);
+        }
+        node = node.parent()?;
+    }
+}
+
+/// Set the indentation of `line` to one level deeper than the line that
+/// opens the container it is in (the same level if it starts by closing
+/// that container). Brackets are matched on the text, so this works while
+/// the document is mid-edit and doesn't parse.
+fn reindent_line(doc: &Document, line: u32, options: &FormattingOptions) -> Vec<TextEdit> {
+    let line_start = doc.offset_of(Position { line, character: 0 });
+    let rest = &doc.text[line_start..];
+    let content = rest.trim_start_matches([' ', '\t']);
+    let current = &rest[..rest.len() - content.len()];
+
+    let Some(&opener) = open_brackets(&doc.text, line_start).last() else {
+        return Vec::new();
+    };
+    let mut indent = line_indent(&doc.text, opener).to_string();
+    let closes = match doc.text.as_bytes()[opener] {
+        b'{' => content.starts_with('}'),
+        _ => content.starts_with(']'),
     };
+    if !closes {
+        indent.push_str(&Printer::new(doc.source(), options, &FormatSettings::default()).indent);
+    }
+
+    if indent == current {
+        return Vec::new();
+    }
+    vec![TextEdit {
+        range: doc.range_of(line_start, line_start + current.len()),
+        new_text: indent,
+    }]
+}
+
+/// Offsets of the brackets still open at `offset`, skipping strings and
+/// comments.
+fn open_brackets(text: &str, offset: usize) -> Vec<usize> {
+    let bytes = &text.as_bytes()[..offset];
+    let mut stack = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' | b'[' => stack.push(i),
+            b'}' | b']' => {
+                stack.pop();
+            }
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' && bytes[i] != b'\n' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i += 2;
+                while i + 1 < bytes.len() && !(bytes[i] == b'*' && bytes[i + 1] == b'/') {
+                    i += 1;
+                }
+                i += 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    stack
+}
+
+/// The leading whitespace of the line containing `offset`.
+fn line_indent(text: &str, offset: usize) -> &str {
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = &text[line_start..offset];
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+/// Turn a reformatting of `original` (starting at byte `offset`) into edits
+/// that only touch the whitespace runs that differ. Falls back to a single
+/// edit over the changed span when the tokens themselves differ.
+fn whitespace_edits(
+    doc: &Document,
+    offset: usize,
+    original: &str,
+    formatted: &str,
+) -> Vec<TextEdit> {
+    fn ws_end(s: &[u8], from: usize) -> usize {
+        from + s[from..]
+            .iter()
+            .take_while(|b| b.is_ascii_whitespace())
+            .count()
+    }
 
-    let sorted = sort_value(&val);
+    let (a, b) = (original.as_bytes(), formatted.as_bytes());
+    let (mut i, mut j) = (0, 0);
+    let mut edits = Vec::new();
+    loop {
+        let (ia, jb) = (ws_end(a, i), ws_end(b, j));
+        if a[i..ia] != b[j..jb] {
+            edits.push(TextEdit {
+                range: doc.range_of(offset + i, offset + ia),
+                new_text: formatted[j..jb].to_string(),
+            });
+        }
+        (i, j) = (ia, jb);
+        if i == a.len() && j == b.len() {
+            return edits;
+        }
+        if i == a.len() || j == b.len() || a[i] != b[j] {
+            break;
+        }
+        i += 1;
+        j += 1;
+    }
+
+    // Tokens differ: replace everything between the common prefix and suffix.
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    vec![TextEdit {
+        range: doc.range_of(offset + prefix, offset + a.len() - suffix),
+        new_text: formatted[prefix..b.len() - suffix].to_string(),
+    }]
+}
+
+/// Sort object keys recursively: in the order `schema` declares them when
+/// given, alphabetically otherwise. Pairs move together with their
+/// comments; everything else is copied verbatim.
+pub fn sort_document(
+    doc: &Document,
+    schema: Option<&Arc<JsonSchema>>,
+    store: &SchemaStore,
+    settings: &FormatSettings,
+) -> Vec<TextEdit> {
+    sort_document_with_cancel(doc, schema, store, settings, &CancelToken::new()).unwrap_or_default()
+}
+
+/// Like [`sort_document`], but stops early once `cancel` is cancelled.
+pub fn sort_document_with_cancel(
+    doc: &Document,
+    schema: Option<&Arc<JsonSchema>>,
+    store: &SchemaStore,
+    settings: &FormatSettings,
+    cancel: &CancelToken,
+) -> Result<Vec<TextEdit>, Cancelled> {
+    let root = doc.tree.root_node();
+    if root.has_error() {
+        return Ok(Vec::new());
+    }
 
-    // Detect the current indent style from the document.
-    let indent = detect_indent(&doc.text);
+    let source = doc.source();
     let mut new_text = String::with_capacity(doc.text.len());
-    format_serde_value(&sorted, &indent, 0, &mut new_text);
-    new_text.push('\n');
+    new_text.push_str(&doc.text[..root.start_byte()]);
+    let sorter = Sorter {
+        source,
+        schema: schema.map(ScopedSchema::new),
+        store,
+        cancel,
+    };
+    sorter.write(root, &mut new_text);
+    cancel.check()?;
+    new_text.push_str(&doc.text[root.end_byte()..]);
+    match settings.insert_final_newline {
+        Some(true) if !new_text.ends_with('\n') => new_text.push_str(line_ending(source)),
+        Some(false) => new_text.truncate(new_text.trim_end_matches(['\n', '\r']).len()),
+        _ => {}
+    }
 
     if new_text == doc.text {
-        return Vec::new();
+        return Ok(Vec::new());
     }
 
     let end = doc.position_of(doc.text.len());
-    vec![TextEdit {
+    Ok(vec![TextEdit {
         range: Range {
             start: Position {
                 line: 0,
@@ -79,21 +388,7 @@ pub fn sort_document(doc: &Document) -> Vec<TextEdit> {
             end,
         },
         new_text,
-    }]
-}
-
-/// Detect the indentation style used in the document.
-fn detect_indent(text: &str) -> String {
-    for line in text.lines().skip(1) {
-        if line.starts_with('\t') {
-            return "\t".to_string();
-        }
-        let spaces = line.len() - line.trim_start_matches(' ').len();
-        if spaces > 0 {
-            return " ".repeat(spaces);
-        }
-    }
-    "  ".to_string() // Default: 2 spaces
+    }])
 }
 
 #[cfg(test)]
@@ -113,7 +408,7 @@ mod tests {
     #[test]
     fn format_compact_json() {
         let doc = Document::new(r#"{"a":1,"b":2}"#.into(), 0);
-        let edits = format_document(&doc, &make_options(2, true));
+        let edits = format_document(&doc, &make_options(2, true), &FormatSettings::default());
         assert_eq!(edits.len(), 1);
         let formatted = &edits[0].new_text;
         assert!(formatted.contains('\n'));
@@ -123,7 +418,7 @@ mod tests {
     #[test]
     fn format_with_tabs() {
         let doc = Document::new(r#"{"a":1}"#.into(), 0);
-        let edits = format_document(&doc, &make_options(1, false));
+        let edits = format_document(&doc, &make_options(1, false), &FormatSettings::default());
         assert_eq!(edits.len(), 1);
         assert!(edits[0].new_text.contains('\t'));
     }
@@ -131,7 +426,7 @@ mod tests {
     #[test]
     fn format_with_4_spaces() {
         let doc = Document::new(r#"{"a":1}"#.into(), 0);
-        let edits = format_document(&doc, &make_options(4, true));
+        let edits = format_document(&doc, &make_options(4, true), &FormatSettings::default());
         assert_eq!(edits.len(), 1);
         assert!(edits[0].new_text.contains("    \"a\""));
     }
@@ -140,21 +435,21 @@ mod tests {
     fn format_already_formatted() {
         let src = "{\n  \"a\": 1\n}\n";
         let doc = Document::new(src.into(), 0);
-        let edits = format_document(&doc, &make_options(2, true));
+        let edits = format_document(&doc, &make_options(2, true), &FormatSettings::default());
         assert!(edits.is_empty()); // No changes needed.
     }
 
     #[test]
     fn format_skips_syntax_errors() {
         let doc = Document::new(r#"{"a": }"#.into(), 0);
-        let edits = format_document(&doc, &make_options(2, true));
+        let edits = format_document(&doc, &make_options(2, true), &FormatSettings::default());
         assert!(edits.is_empty());
     }
 
     #[test]
     fn format_empty_object() {
         let doc = Document::new(r#"{}"#.into(), 0);
-        let edits = format_document(&doc, &make_options(2, true));
+        let edits = format_document(&doc, &make_options(2, true), &FormatSettings::default());
         assert_eq!(edits.len(), 1);
         assert_eq!(edits[0].new_text, "{}\n");
     }
@@ -162,262 +457,1020 @@ mod tests {
     #[test]
     fn format_empty_array() {
         let doc = Document::new(r#"[]"#.into(), 0);
-        let edits = format_document(&doc, &make_options(2, true));
+        let edits = format_document(&doc, &make_options(2, true), &FormatSettings::default());
         assert_eq!(edits.len(), 1);
         assert_eq!(edits[0].new_text, "[]\n");
     }
 
+    fn sorted(src: &str) -> String {
+        let doc = Document::new(src.into(), 0);
+        sort_document(&doc, None, &SchemaStore::new(), &FormatSettings::default())
+            .pop()
+            .map_or_else(|| src.to_string(), |e| e.new_text)
+    }
+
     #[test]
-    fn sort_document_alphabetical() {
+    fn cancelled_sort_returns_no_edits() {
         let doc = Document::new(r#"{"b": 2, "a": 1}"#.into(), 0);
-        let edits = sort_document(&doc);
-        assert_eq!(edits.len(), 1);
-        let sorted = &edits[0].new_text;
-        let a_pos = sorted.find("\"a\"").unwrap();
-        let b_pos = sorted.find("\"b\"").unwrap();
-        assert!(a_pos < b_pos);
+        let cancel = CancelToken::new();
+        cancel.cancel(crate::cancel::CancelReason::ContentModified);
+        let result = sort_document_with_cancel(
+            &doc,
+            None,
+            &SchemaStore::new(),
+            &FormatSettings::default(),
+            &cancel,
+        );
+        assert_eq!(result, Err(Cancelled));
+    }
+
+    #[test]
+    fn sort_document_alphabetical() {
+        assert_eq!(sorted(r#"{"b": 2, "a": 1}"#), r#"{"a": 1, "b": 2}"#);
+        assert_eq!(
+            sorted("{\n  \"b\": 2,\n  \"a\": 1\n}\n"),
+            "{\n  \"a\": 1,\n  \"b\": 2\n}\n"
+        );
     }
 
     #[test]
     fn sort_nested_objects() {
-        let doc = Document::new(r#"{"z": {"b": 2, "a": 1}, "a": 1}"#.into(), 0);
-        let edits = sort_document(&doc);
-        assert_eq!(edits.len(), 1);
-        let sorted = &edits[0].new_text;
-        // Top level: "a" before "z".
-        let a_pos = sorted.find("\"a\": 1").unwrap();
-        let z_pos = sorted.find("\"z\"").unwrap();
-        assert!(a_pos < z_pos);
+        assert_eq!(
+            sorted(r#"{"z": {"b": 2, "a": [{"d": 1, "c": 2}]}, "a": 1}"#),
+            r#"{"a": 1, "z": {"a": [{"c": 2, "d": 1}], "b": 2}}"#
+        );
+    }
+
+    #[test]
+    fn sort_keeps_literals_verbatim() {
+        assert_eq!(
+            sorted(r#"{"b": "\u00e9\/", "a": 1.0e10}"#),
+            r#"{"a": 1.0e10, "b": "\u00e9\/"}"#
+        );
+    }
+
+    #[test]
+    fn sort_moves_comments_with_pairs() {
+        let src = "// top\n{ // object\n  // about b\n  \"b\": 2, // b note\n  /* about a */\n  \"a\": 1\n  // dangling\n}\n";
+        assert_eq!(
+            sorted(src),
+            "// top\n{ // object\n  /* about a */\n  \"a\": 1,\n  // about b\n  \"b\": 2 // b note\n  // dangling\n}\n"
+        );
+    }
+
+    #[test]
+    fn sort_keeps_line_comment_from_swallowing_pairs() {
+        assert_eq!(
+            sorted("{\"b\": 1, \"a\": 2 // two\n}"),
+            "{\"a\": 2, // two\n \"b\": 1\n}"
+        );
+    }
+
+    fn sorted_by_schema(src: &str, schema: &str) -> String {
+        let doc = Document::new(src.into(), 0);
+        let schema = Arc::new(JsonSchema::from_value(
+            &serde_json::from_str(schema).unwrap(),
+        ));
+        sort_document(
+            &doc,
+            Some(&schema),
+            &SchemaStore::new(),
+            &FormatSettings::default(),
+        )
+        .pop()
+        .map_or_else(|| src.to_string(), |e| e.new_text)
+    }
+
+    #[test]
+    fn sort_by_schema_declaration_order() {
+        let schema = r#"{"properties": {"name": {}, "version": {}, "scripts": {}}}"#;
+        assert_eq!(
+            sorted_by_schema(
+                r#"{"dependencies": {}, "scripts": {}, "author": "", "version": "1", "name": "x"}"#,
+                schema
+            ),
+            r#"{"name": "x", "version": "1", "scripts": {}, "author": "", "dependencies": {}}"#
+        );
+    }
+
+    #[test]
+    fn sort_by_schema_follows_refs_and_nesting() {
+        let schema = r##"{
+            "properties": {
+                "z": {"$ref": "#/$defs/inner"},
+                "items": {"items": {"allOf": [{"properties": {"id": {}}}, {"properties": {"b": {}}}]}}
+            },
+            "$defs": {"inner": {"properties": {"y": {}, "x": {}}}}
+        }"##;
+        assert_eq!(
+            sorted_by_schema(
+                r#"{"items": [{"a": 1, "b": 2, "id": 3}], "z": {"x": 1, "w": 0, "y": 2}}"#,
+                schema
+            ),
+            r#"{"z": {"y": 2, "x": 1, "w": 0}, "items": [{"id": 3, "b": 2, "a": 1}]}"#
+        );
+    }
+
+    #[test]
+    fn sort_order_from_setting() {
+        assert_eq!(SortOrder::from_setting("schema"), Some(SortOrder::Schema));
+        assert_eq!(
+            SortOrder::from_setting("alphabetical"),
+            Some(SortOrder::Alphabetical)
+        );
+        assert_eq!(SortOrder::from_setting("random"), None);
     }
 
     #[test]
     fn sort_already_sorted() {
         let doc = Document::new("{\n  \"a\": 1,\n  \"b\": 2\n}\n".into(), 0);
-        let edits = sort_document(&doc);
+        let edits = sort_document(&doc, None, &SchemaStore::new(), &FormatSettings::default());
         assert!(edits.is_empty());
     }
 
     #[test]
     fn sort_skips_syntax_errors() {
         let doc = Document::new(r#"{"b": , "a": 1}"#.into(), 0);
-        let edits = sort_document(&doc);
+        let edits = sort_document(&doc, None, &SchemaStore::new(), &FormatSettings::default());
         assert!(edits.is_empty());
     }
 
     #[test]
     fn format_preserves_string_escapes() {
         let doc = Document::new(r#"{"msg":"hello\nworld"}"#.into(), 0);
-        let edits = format_document(&doc, &make_options(2, true));
+        let edits = format_document(&doc, &make_options(2, true), &FormatSettings::default());
         assert_eq!(edits.len(), 1);
         assert!(edits[0].new_text.contains(r#"\n"#));
     }
 
+    fn formatted(src: &str) -> String {
+        let doc = Document::new(src.into(), 0);
+        format_document(&doc, &make_options(2, true), &FormatSettings::default())
+            .pop()
+            .map_or_else(|| src.to_string(), |e| e.new_text)
+    }
+
+    #[test]
+    fn format_keeps_leading_and_trailing_comments() {
+        let src = "// header\n{\n// first\n\"a\":1, // one\n  /* block */ \"b\":[2, /* two */ 3] // last\n}";
+        assert_eq!(
+            formatted(src),
+            "// header\n{\n  // first\n  \"a\": 1, // one\n  /* block */\n  \"b\": [\n    2, /* two */\n    3\n  ] // last\n}\n"
+        );
+    }
+
+    #[test]
+    fn format_keeps_comments_in_empty_containers() {
+        assert_eq!(formatted("{ // nothing yet\n}"), "{ // nothing yet\n}\n");
+        assert_eq!(formatted("[ /* none */ ]"), "[\n  /* none */\n]\n");
+        assert_eq!(
+            formatted("{\"a\": {\n// todo\n}}"),
+            "{\n  \"a\": {\n    // todo\n  }\n}\n"
+        );
+    }
+
+    #[test]
+    fn format_keeps_comments_inside_pairs() {
+        assert_eq!(
+            formatted("{\"a\": /* n */ 1, \"b\": // why\n 2}"),
+            "{\n  \"a\": /* n */ 1,\n  \"b\": // why\n    2\n}\n"
+        );
+    }
+
+    #[test]
+    fn format_keeps_blank_lines_between_groups() {
+        let src = "{\n  \"a\": 1,\n\n\n  // group two\n  \"b\": 2,\n  \"c\": 3\n}\n";
+        assert_eq!(
+            formatted(src),
+            "{\n  \"a\": 1,\n\n  // group two\n  \"b\": 2,\n  \"c\": 3\n}\n"
+        );
+        assert_eq!(formatted("{\n\n  \"a\": 1\n\n}"), "{\n  \"a\": 1\n}\n");
+    }
+
+    fn formatted_with(src: &str, settings: serde_json::Value) -> String {
+        let doc = Document::new(src.into(), 0);
+        let settings = FormatSettings::from_settings(&settings);
+        format_document(&doc, &make_options(2, true), &settings)
+            .pop()
+            .map_or_else(|| src.to_string(), |e| e.new_text)
+    }
+
+    #[test]
+    fn format_print_width_keeps_short_containers_inline() {
+        let src =
+            r#"{"point": {"x":1,"y":2}, "tags": ["a","b"], "long": [1000000, 2000000, 3000000]}"#;
+        assert_eq!(
+            formatted_with(src, serde_json::json!({"printWidth": 30})),
+            "{\n  \"point\": { \"x\": 1, \"y\": 2 },\n  \"tags\": [\"a\", \"b\"],\n  \"long\": [\n    1000000,\n    2000000,\n    3000000\n  ]\n}\n"
+        );
+        // Comments force containers onto separate lines.
+        assert_eq!(
+            formatted_with("[1, /* c */ 2]", serde_json::json!({"printWidth": 80})),
+            "[\n  1, /* c */\n  2\n]\n"
+        );
+        assert_eq!(
+            formatted_with(r#"{"a":[1,2]}"#, serde_json::json!({"printWidth": 80})),
+            "{ \"a\": [1, 2] }\n"
+        );
+        // The comma after a container counts towards its line.
+        let src = r#"{"aaaa": [1, 2, 3], "b": 1}"#;
+        assert_eq!(
+            formatted_with(src, serde_json::json!({"printWidth": 20})),
+            "{\n  \"aaaa\": [1, 2, 3],\n  \"b\": 1\n}\n"
+        );
+        assert_eq!(
+            formatted_with(src, serde_json::json!({"printWidth": 19})),
+            "{\n  \"aaaa\": [\n    1,\n    2,\n    3\n  ],\n  \"b\": 1\n}\n"
+        );
+    }
+
+    #[test]
+    fn format_keep_lines_preserves_line_breaks() {
+        let src = "{\"a\":1,\"b\":2,\n\"c\":[1,2,\n3]}";
+        assert_eq!(
+            formatted_with(src, serde_json::json!({"keepLines": true})),
+            "{ \"a\": 1, \"b\": 2,\n  \"c\": [1, 2,\n    3] }\n"
+        );
+        let src = "{\n  \"a\": 1, // one\n  \"b\": 2\n}";
+        assert_eq!(
+            formatted_with(src, serde_json::json!({"keepLines": true})),
+            "{\n  \"a\": 1, // one\n  \"b\": 2\n}\n"
+        );
+    }
+
+    #[test]
+    fn format_space_before_colon() {
+        assert_eq!(
+            formatted_with(
+                r#"{"a":{"b":1}}"#,
+                serde_json::json!({"spaceBeforeColon": true})
+            ),
+            "{\n  \"a\" : {\n    \"b\" : 1\n  }\n}\n"
+        );
+        assert_eq!(
+            formatted_with(
+                r#"{"a":1}"#,
+                serde_json::json!({"spaceBeforeColon": true, "printWidth": 80})
+            ),
+            "{ \"a\" : 1 }\n"
+        );
+    }
+
+    #[test]
+    fn format_final_newline_policy() {
+        assert_eq!(
+            formatted_with("[]\n", serde_json::json!({"insertFinalNewline": false})),
+            "[]"
+        );
+        let doc = Document::new("[]".into(), 0);
+        let options = FormattingOptions {
+            insert_final_newline: Some(false),
+            ..make_options(2, true)
+        };
+        assert!(format_document(&doc, &options, &FormatSettings::default()).is_empty());
+        let settings = FormatSettings {
+            insert_final_newline: Some(true),
+            ..FormatSettings::default()
+        };
+        assert_eq!(
+            format_document(&doc, &options, &settings)[0].new_text,
+            "[]\n"
+        );
+    }
+
+    #[test]
+    fn format_range_uses_settings() {
+        let src = "{\n  \"a\": [1,\n 2],\n  \"b\": [3,4]\n}";
+        let doc = Document::new(src.into(), 0);
+        let start = src.find('[').unwrap();
+        let settings = FormatSettings {
+            print_width: Some(40),
+            ..FormatSettings::default()
+        };
+        let edits = format_range(
+            &doc,
+            doc.range_of(start, start + 1),
+            &make_options(2, true),
+            &settings,
+        );
+        let mut text = src.to_string();
+        for e in edits.iter().rev() {
+            text.replace_range(
+                doc.offset_of(e.range.start)..doc.offset_of(e.range.end),
+                &e.new_text,
+            );
+        }
+        assert_eq!(text, "{\n  \"a\": [1, 2],\n  \"b\": [3,4]\n}");
+    }
+
     #[test]
-    fn detect_indent_2_spaces() {
-        assert_eq!(detect_indent("{\n  \"a\": 1\n}"), "  ");
+    fn sort_applies_final_newline_policy() {
+        let doc = Document::new(r#"{"b": 1, "a": 2}"#.into(), 0);
+        let settings = FormatSettings {
+            insert_final_newline: Some(true),
+            ..FormatSettings::default()
+        };
+        let edits = sort_document(&doc, None, &SchemaStore::new(), &settings);
+        assert_eq!(edits[0].new_text, "{\"a\": 2, \"b\": 1}\n");
+    }
+
+    /// Apply on-type formatting for `ch` typed just before `offset`.
+    fn on_type(src: &str, offset: usize, ch: &str) -> String {
+        let doc = Document::new(src.into(), 0);
+        let position = doc.position_of(offset);
+        let mut edits = format_on_type(
+            &doc,
+            position,
+            ch,
+            &make_options(2, true),
+            &FormatSettings::default(),
+        );
+        edits.sort_by_key(|e| std::cmp::Reverse(doc.offset_of(e.range.start)));
+        let mut text = src.to_string();
+        for e in edits {
+            let from = doc.offset_of(e.range.start);
+            let to = doc.offset_of(e.range.end);
+            text.replace_range(from..to, &e.new_text);
+        }
+        text
     }
 
     #[test]
-    fn detect_indent_4_spaces() {
-        assert_eq!(detect_indent("{\n    \"a\": 1\n}"), "    ");
+    fn on_type_closing_bracket_formats_container() {
+        let src = "{\n  \"a\": {\"x\":1,\n\"y\":[1,2]},\n  \"b\":2\n}";
+        let at = src.find("]}").unwrap() + 2;
+        assert_eq!(
+            on_type(src, at, "}"),
+            "{\n  \"a\": {\n    \"x\": 1,\n    \"y\": [\n      1,\n      2\n    ]\n  },\n  \"b\":2\n}"
+        );
+        let at = src.find(']').unwrap() + 1;
+        assert_eq!(
+            on_type(src, at, "]"),
+            "{\n  \"a\": {\"x\":1,\n\"y\":[\n  1,\n  2\n]},\n  \"b\":2\n}"
+        );
+        // A broken container is left alone.
+        let src = "{\"a\": {\"x\": }}";
+        assert_eq!(on_type(src, src.len() - 1, "}"), src);
     }
 
     #[test]
-    fn detect_indent_tabs() {
-        assert_eq!(detect_indent("{\n\t\"a\": 1\n}"), "\t");
+    fn on_type_newline_indents_line() {
+        // Enter after `{` in a document that doesn't parse yet.
+        let src = "{\n  \"a\": {\n";
+        assert_eq!(on_type(src, src.len(), "\n"), "{\n  \"a\": {\n    ");
+        let src = "{\n  \"a\": [\n1";
+        assert_eq!(on_type(src, src.len() - 1, "\n"), "{\n  \"a\": [\n    1");
+        // A line starting with the closing bracket lines up with its opener.
+        let src = "{\n  \"a\": {\n        }\n}";
+        let at = src.find("        }").unwrap();
+        assert_eq!(on_type(src, at, "\n"), "{\n  \"a\": {\n  }\n}");
+        // Brackets in strings and comments don't count.
+        let src = "{\n  \"a\": \"{[\", // ]\n\"b\"";
+        let at = src.rfind('\n').unwrap() + 1;
+        assert_eq!(on_type(src, at, "\n"), "{\n  \"a\": \"{[\", // ]\n  \"b\"");
     }
 
     #[test]
-    fn detect_indent_default() {
-        assert_eq!(detect_indent("{}"), "  ");
+    fn format_settings_from_json() {
+        let settings = FormatSettings::from_settings(&serde_json::json!({
+            "printWidth": 100,
+            "keepLines": true,
+            "spaceBeforeColon": true,
+            "insertFinalNewline": false
+        }));
+        assert_eq!(
+            settings,
+            FormatSettings {
+                print_width: Some(100),
+                keep_lines: true,
+                space_before_colon: true,
+                insert_final_newline: Some(false),
+            }
+        );
+        assert_eq!(
+            FormatSettings::from_settings(&serde_json::json!({})),
+            FormatSettings::default()
+        );
+    }
+
+    /// Apply range-formatting edits for `start..end` (byte offsets) to `src`.
+    fn format_range_text(src: &str, start: usize, end: usize) -> (usize, String) {
+        let doc = Document::new(src.into(), 0);
+        let range = doc.range_of(start, end);
+        let mut edits = format_range(
+            &doc,
+            range,
+            &make_options(2, true),
+            &FormatSettings::default(),
+        );
+        edits.sort_by_key(|e| std::cmp::Reverse(doc.offset_of(e.range.start)));
+        let count = edits.len();
+        let mut text = src.to_string();
+        for e in edits {
+            let from = doc.offset_of(e.range.start);
+            let to = doc.offset_of(e.range.end);
+            text.replace_range(from..to, &e.new_text);
+        }
+        (count, text)
+    }
+
+    #[test]
+    fn format_range_only_touches_enclosing_node() {
+        let src = "{\n  \"a\":{\"x\":1,\"y\":[1,2]},\n  \"b\":{\"z\":3}\n}";
+        let start = src.find("{\"x\"").unwrap();
+        let end = src.find("]}").unwrap() + 2;
+        let (_, text) = format_range_text(src, start, end);
+        assert_eq!(
+            text,
+            "{\n  \"a\":{\n    \"x\": 1,\n    \"y\": [\n      1,\n      2\n    ]\n  },\n  \"b\":{\"z\":3}\n}"
+        );
+    }
+
+    #[test]
+    fn format_range_selects_pair() {
+        let src = "{\n  \"a\"  :  [1,2],\n  \"b\":2\n}";
+        let start = src.find("\"a\"").unwrap();
+        let end = src.find(']').unwrap() + 1;
+        let (_, text) = format_range_text(src, start, end);
+        assert_eq!(text, "{\n  \"a\": [\n    1,\n    2\n  ],\n  \"b\":2\n}");
+    }
+
+    #[test]
+    fn format_range_ignores_errors_elsewhere() {
+        let src = "{\n  \"a\": {\"x\":1},\n  \"b\": \n}";
+        let start = src.find("{\"x\"").unwrap();
+        let (_, text) = format_range_text(src, start, start + 1);
+        assert_eq!(text, "{\n  \"a\": {\n    \"x\": 1\n  },\n  \"b\": \n}");
+
+        // The enclosing node itself is broken: leave it alone.
+        let src = "{\"a\": {\"x\": }}";
+        let at = src.find("\"x\"").unwrap();
+        assert_eq!(format_range_text(src, at, at), (0, src.to_string()));
+    }
+
+    #[test]
+    fn format_range_edits_are_minimal() {
+        let src = "[\n  1,\n  2,3\n]";
+        let at = src.find('3').unwrap();
+        let (count, text) = format_range_text(src, at, at);
+        assert_eq!(count, 1);
+        assert_eq!(text, "[\n  1,\n  2,\n  3\n]");
+
+        let src = "{\n  \"a\": [\n    1\n  ]\n}";
+        let at = src.find('1').unwrap();
+        assert_eq!(format_range_text(src, at, at).0, 0);
+    }
+
+    #[test]
+    fn format_range_keeps_block_comment_text() {
+        let src = "{\n  \"a\": {\n    /* line1\n       line2 */\n    \"x\": 1\n  }\n}";
+        let at = src.find("\"x\"").unwrap();
+        let (count, once) = format_range_text(src, at, at);
+        assert_eq!(count, 0);
+        assert_eq!(once, src);
+        let (_, twice) = format_range_text(&once, at, at);
+        assert_eq!(twice, src);
+
+        // On-type formatting of the container doesn't reindent it either.
+        let at = src.rfind("  }").unwrap() + 3;
+        assert_eq!(on_type(src, at, "}"), src);
+    }
+
+    #[test]
+    fn format_keeps_crlf_line_endings() {
+        let src = "{\r\n  \"a\": {\"x\":1,\"y\":2},\r\n  \"b\": 3\r\n}";
+        let at = src.find("{\"x\"").unwrap();
+        let (_, text) = format_range_text(src, at, at + 1);
+        assert_eq!(
+            text,
+            "{\r\n  \"a\": {\r\n    \"x\": 1,\r\n    \"y\": 2\r\n  },\r\n  \"b\": 3\r\n}"
+        );
+        assert_eq!(format_range_text(&text, at, at + 1).0, 0);
+
+        let at = src.find("},").unwrap() + 1;
+        assert_eq!(on_type(src, at, "}"), text);
+        assert_eq!(formatted(src), format!("{text}\r\n"));
     }
 }
 
-fn reformat(doc: &Document, options: &FormattingOptions) -> Option<String> {
+fn reformat(
+    doc: &Document,
+    options: &FormattingOptions,
+    settings: &FormatSettings,
+) -> Option<String> {
     let root = doc.tree.root_node();
-    let value_node = root
+    if !root
         .named_children(&mut root.walk())
-        .find(|n| crate::tree::is_value_node(n))?;
-
-    let insert_spaces = options.insert_spaces;
-    let tab_size = options.tab_size as usize;
-    let insert_final_newline = options.insert_final_newline.unwrap_or(true);
+        .any(|n| tree::is_value_node(&n))
+    {
+        return None;
+    }
 
-    let indent = if insert_spaces {
-        " ".repeat(tab_size)
-    } else {
-        "\t".to_string()
-    };
+    let insert_final_newline = settings
+        .insert_final_newline
+        .or(options.insert_final_newline)
+        .unwrap_or(true);
+    let printer = Printer::new(doc.source(), options, settings);
 
-    let source = doc.source();
     let mut out = String::with_capacity(doc.text.len());
-    format_node(value_node, source, &indent, 0, &mut out);
+    // The root value plus any comments before or after it.
+    let mut cursor = root.walk();
+    for child in root
+        .named_children(&mut cursor)
+        .filter(|n| tree::is_value_node(n) || n.kind() == kinds::COMMENT)
+    {
+        if !out.is_empty() {
+            if is_trailing_comment(child, printer.source) {
+                out.push(' ');
+            } else {
+                out.push_str(printer.newline);
+                if follows_blank_line(child) {
+                    out.push_str(printer.newline);
+                }
+            }
+        }
+        printer.node(child, 0, 0, &mut out);
+    }
 
     if insert_final_newline && !out.ends_with('\n') {
-        out.push('\n');
+        out.push_str(printer.newline);
     }
 
     Some(out)
 }
 
-/// Recursively format a tree-sitter node, reading leaf text directly from source.
-fn format_node(node: Node<'_>, source: &[u8], indent: &str, depth: usize, out: &mut String) {
-    match node.kind() {
-        kinds::OBJECT => {
-            let mut cursor = node.walk();
-            let pairs: Vec<Node<'_>> = node
-                .named_children(&mut cursor)
-                .filter(|n| n.kind() == kinds::PAIR)
-                .collect();
-            if pairs.is_empty() {
-                out.push_str("{}");
-                return;
-            }
-            out.push_str("{\n");
-            for (i, pair) in pairs.iter().enumerate() {
-                write_indent(out, indent, depth + 1);
-                // Key: copy verbatim from source (preserves escapes).
-                if let Some(key_node) = pair.child_by_field_name("key") {
-                    let key_text = &source[key_node.start_byte()..key_node.end_byte()];
-                    out.push_str(std::str::from_utf8(key_text).unwrap_or("\"\""));
-                }
-                out.push_str(": ");
-                // Value.
-                if let Some(val_node) = pair.child_by_field_name("value") {
-                    format_node(val_node, source, indent, depth + 1, out);
+/// Writes formatted CST nodes, reading leaf text directly from source.
+struct Printer<'a> {
+    source: &'a [u8],
+    indent: String,
+    tab_size: usize,
+    settings: &'a FormatSettings,
+    /// Column at which the output starts (non-zero for range formatting).
+    base_column: usize,
+    /// Indentation every following line starts with (the indentation of the
+    /// node's first line for range formatting).
+    base_indent: String,
+    /// The line ending the document uses.
+    newline: &'static str,
+}
+
+impl<'a> Printer<'a> {
+    fn new(source: &'a [u8], options: &FormattingOptions, settings: &'a FormatSettings) -> Self {
+        let indent = if options.insert_spaces {
+            " ".repeat(options.tab_size as usize)
+        } else {
+            "\t".to_string()
+        };
+        Printer {
+            source,
+            indent,
+            tab_size: options.tab_size.max(1) as usize,
+            settings,
+            base_column: 0,
+            base_indent: String::new(),
+            newline: line_ending(source),
+        }
+    }
+
+    /// Recursively format a node. `after` is the width of what follows it
+    /// on its line (a separating comma).
+    fn node(&self, node: Node<'_>, depth: usize, after: usize, out: &mut String) {
+        match node.kind() {
+            kinds::OBJECT => self.container(node, depth, ('{', '}'), after, out),
+            kinds::ARRAY => self.container(node, depth, ('[', ']'), after, out),
+            // Leaf nodes (and anything unexpected): copy verbatim from source.
+            // This preserves string escapes, number formats, etc. exactly.
+            _ => push_text(node, self.source, out),
+        }
+    }
+
+    /// Format an object or array, keeping comments and the blank lines that
+    /// separate groups of members. Members go one per line unless the
+    /// container fits within the print width, or `keepLines` keeps the
+    /// original layout.
+    fn container(
+        &self,
+        node: Node<'_>,
+        depth: usize,
+        (open, close): (char, char),
+        after: usize,
+        out: &mut String,
+    ) {
+        let source = self.source;
+        let mut cursor = node.walk();
+        let children: Vec<Node<'_>> = node
+            .named_children(&mut cursor)
+            .filter(|n| {
+                n.kind() == kinds::PAIR || tree::is_value_node(n) || n.kind() == kinds::COMMENT
+            })
+            .collect();
+        if children.is_empty() {
+            out.push(open);
+            out.push(close);
+            return;
+        }
+
+        let keep_lines = self.settings.keep_lines;
+        if !keep_lines
+            && let Some(width) = self.settings.print_width
+            && let Some(inline) = self.inline(node)
+            && self.column(out) + self.width(&inline) + after <= width
+        {
+            out.push_str(&inline);
+            return;
+        }
+
+        // Inside the brackets of an object kept on one line.
+        let pad = if open == '{' { " " } else { "" };
+        let mut members_left = children
+            .iter()
+            .filter(|n| n.kind() != kinds::COMMENT)
+            .count();
+        let mut line_started = false;
+        out.push(open);
+        for &child in &children {
+            if is_trailing_comment(child, source) {
+                out.push(' ');
+            } else if keep_lines && on_previous_line(child) {
+                let after_open = child
+                    .prev_sibling()
+                    .is_some_and(|p| matches!(p.kind(), "{" | "["));
+                out.push_str(if after_open { pad } else { " " });
+            } else {
+                out.push_str(self.newline);
+                if line_started && follows_blank_line(child) {
+                    out.push_str(self.newline);
                 }
-                if i < pairs.len() - 1 {
+                self.write_indent(out, depth + 1);
+                line_started = true;
+            }
+            let comma = usize::from(child.kind() != kinds::COMMENT && members_left > 1);
+            match child.kind() {
+                kinds::COMMENT => push_text(child, source, out),
+                kinds::PAIR => self.pair(child, depth + 1, comma, out),
+                _ => self.node(child, depth + 1, comma, out),
+            }
+            if child.kind() != kinds::COMMENT {
+                members_left -= 1;
+                if members_left > 0 {
                     out.push(',');
                 }
-                out.push('\n');
             }
-            write_indent(out, indent, depth);
-            out.push('}');
-        }
-        kinds::ARRAY => {
-            let mut cursor = node.walk();
-            let items: Vec<Node<'_>> = node
-                .named_children(&mut cursor)
-                .filter(|n| crate::tree::is_value_node(n))
-                .collect();
-            if items.is_empty() {
-                out.push_str("[]");
-                return;
+        }
+
+        let last = children[children.len() - 1];
+        let close_node = node.child(node.child_count() as u32 - 1);
+        let close_on_last_line =
+            close_node.is_some_and(|c| c.start_position().row == last.end_position().row);
+        if keep_lines && close_on_last_line && !is_line_comment(last, source) {
+            out.push_str(pad);
+        } else {
+            out.push_str(self.newline);
+            self.write_indent(out, depth);
+        }
+        out.push(close);
+    }
+
+    /// Format `key: value`, keeping any comments between the two.
+    fn pair(&self, pair: Node<'_>, depth: usize, after: usize, out: &mut String) {
+        let source = self.source;
+        // Key: copy verbatim from source (preserves escapes).
+        if let Some(key) = pair.child_by_field_name("key") {
+            push_text(key, source, out);
+        }
+        if self.settings.space_before_colon {
+            out.push(' ');
+        }
+        out.push(':');
+        let mut cursor = pair.walk();
+        let mut after_line_comment = false;
+        for comment in pair
+            .named_children(&mut cursor)
+            .filter(|n| n.kind() == kinds::COMMENT)
+        {
+            out.push(' ');
+            push_text(comment, source, out);
+            after_line_comment = is_line_comment(comment, source);
+            if after_line_comment {
+                out.push_str(self.newline);
+                self.write_indent(out, depth + 1);
+            }
+        }
+        if !after_line_comment {
+            out.push(' ');
+        }
+        if let Some(value) = pair.child_by_field_name("value") {
+            self.node(value, depth, after, out);
+        }
+    }
+
+    /// The node on a single line, or `None` if it contains comments.
+    fn inline(&self, node: Node<'_>) -> Option<String> {
+        let mut out = String::new();
+        self.write_inline(node, &mut out)?;
+        Some(out)
+    }
+
+    fn write_inline(&self, node: Node<'_>, out: &mut String) -> Option<()> {
+        let (open, close, pad) = match node.kind() {
+            kinds::OBJECT => ('{', '}', " "),
+            kinds::ARRAY => ('[', ']', ""),
+            _ => {
+                push_text(node, self.source, out);
+                return Some(());
+            }
+        };
+        let mut cursor = node.walk();
+        let children: Vec<Node<'_>> = node.named_children(&mut cursor).collect();
+        if children.iter().any(|n| n.kind() == kinds::COMMENT) {
+            return None;
+        }
+        let members: Vec<Node<'_>> = children
+            .into_iter()
+            .filter(|n| n.kind() == kinds::PAIR || tree::is_value_node(n))
+            .collect();
+        out.push(open);
+        if !members.is_empty() {
+            out.push_str(pad);
+        }
+        for (i, member) in members.iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
             }
-            out.push_str("[\n");
-            for (i, item) in items.iter().enumerate() {
-                write_indent(out, indent, depth + 1);
-                format_node(*item, source, indent, depth + 1, out);
-                if i < items.len() - 1 {
-                    out.push(',');
+            if member.kind() == kinds::PAIR {
+                let mut cursor = member.walk();
+                if member
+                    .named_children(&mut cursor)
+                    .any(|n| n.kind() == kinds::COMMENT)
+                {
+                    return None;
                 }
-                out.push('\n');
+                push_text(member.child_by_field_name("key")?, self.source, out);
+                out.push_str(if self.settings.space_before_colon {
+                    " : "
+                } else {
+                    ": "
+                });
+                self.write_inline(member.child_by_field_name("value")?, out)?;
+            } else {
+                self.write_inline(*member, out)?;
             }
-            write_indent(out, indent, depth);
-            out.push(']');
         }
-        // Leaf nodes: copy verbatim from source.
-        // This preserves string escapes, number formats, etc. exactly.
-        kinds::STRING | kinds::NUMBER | kinds::TRUE | kinds::FALSE | kinds::NULL => {
-            let text = &source[node.start_byte()..node.end_byte()];
-            out.push_str(std::str::from_utf8(text).unwrap_or("null"));
+        if !members.is_empty() {
+            out.push_str(pad);
         }
-        _ => {
-            // Fallback: copy raw text for unexpected node kinds.
-            let text = &source[node.start_byte()..node.end_byte()];
-            out.push_str(std::str::from_utf8(text).unwrap_or(""));
+        out.push(close);
+        Some(())
+    }
+
+    /// Indent a new line to `depth` levels below the base indentation.
+    fn write_indent(&self, out: &mut String, depth: usize) {
+        out.push_str(&self.base_indent);
+        for _ in 0..depth {
+            out.push_str(&self.indent);
+        }
+    }
+
+    /// Column at the end of `out`.
+    fn column(&self, out: &str) -> usize {
+        match out.rfind('\n') {
+            Some(i) => self.width(&out[i + 1..]),
+            None => self.base_column + self.width(out),
         }
     }
+
+    /// Display width of `text`, counting tabs as `tab_size` columns.
+    fn width(&self, text: &str) -> usize {
+        text.chars()
+            .map(|c| if c == '\t' { self.tab_size } else { 1 })
+            .sum()
+    }
 }
 
-fn write_indent(out: &mut String, indent: &str, depth: usize) {
-    for _ in 0..depth {
-        out.push_str(indent);
+/// A comment that starts on the line where the previous token ends stays
+/// on that line. Block comments right after an opening bracket move to
+/// their own line instead.
+fn is_trailing_comment(node: Node<'_>, source: &[u8]) -> bool {
+    if node.kind() != kinds::COMMENT {
+        return false;
     }
+    node.prev_sibling().is_some_and(|prev| {
+        prev.end_position().row == node.start_position().row
+            && (!matches!(prev.kind(), "{" | "[") || is_line_comment(node, source))
+    })
 }
 
-fn escape_json_string(s: &str, out: &mut String) {
-    for ch in s.chars() {
-        match ch {
-            '"' => out.push_str("\\\""),
-            '\\' => out.push_str("\\\\"),
-            '\n' => out.push_str("\\n"),
-            '\r' => out.push_str("\\r"),
-            '\t' => out.push_str("\\t"),
-            c if c < '\u{0020}' => {
-                out.push_str(&format!("\\u{:04x}", c as u32));
-            }
-            c => out.push(c),
-        }
+fn is_line_comment(node: Node<'_>, source: &[u8]) -> bool {
+    source[node.start_byte()..].starts_with(b"//")
+}
+
+/// Whether `node` starts on the line where the previous token ends.
+fn on_previous_line(node: Node<'_>) -> bool {
+    node.prev_sibling()
+        .is_some_and(|prev| node.start_position().row == prev.end_position().row)
+}
+
+/// Whether the original text has an empty line before `node`.
+fn follows_blank_line(node: Node<'_>) -> bool {
+    node.prev_sibling()
+        .is_some_and(|prev| node.start_position().row > prev.end_position().row + 1)
+}
+
+/// The line ending of the first line of `source`.
+fn line_ending(source: &[u8]) -> &'static str {
+    match source.iter().position(|&b| b == b'\n') {
+        Some(i) if i > 0 && source[i - 1] == b'\r' => "\r\n",
+        _ => "\n",
     }
 }
 
-/// serde_json-based formatter used only by sort_document (which needs
-/// reordered keys that tree-sitter can't provide).
-fn format_serde_value(val: &serde_json::Value, indent: &str, depth: usize, out: &mut String) {
-    match val {
-        serde_json::Value::Null => out.push_str("null"),
-        serde_json::Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
-        serde_json::Value::Number(n) => out.push_str(&n.to_string()),
-        serde_json::Value::String(s) => {
-            out.push('"');
-            escape_json_string(s, out);
-            out.push('"');
-        }
-        serde_json::Value::Array(arr) => {
-            if arr.is_empty() {
-                out.push_str("[]");
-                return;
-            }
-            out.push_str("[\n");
-            for (i, item) in arr.iter().enumerate() {
-                write_indent(out, indent, depth + 1);
-                format_serde_value(item, indent, depth + 1, out);
-                if i < arr.len() - 1 {
-                    out.push(',');
+fn push_text(node: Node<'_>, source: &[u8], out: &mut String) {
+    push_span(source, node.start_byte(), node.end_byte(), out);
+}
+
+// ---------------------------------------------------------------------------
+// Sorting
+// ---------------------------------------------------------------------------
+
+/// A pair together with the comments that move with it.
+struct SortMember<'a> {
+    key: String,
+    pair: Node<'a>,
+    /// Start of the comments directly above the pair, or of the pair itself.
+    start: usize,
+    /// End of the pair and of a comma on the same line.
+    after: usize,
+    /// Comments on the line the pair ends on.
+    trailing: Option<(usize, usize)>,
+}
+
+struct Sorter<'a> {
+    source: &'a [u8],
+    schema: Option<ScopedSchema>,
+    store: &'a SchemaStore,
+    cancel: &'a CancelToken,
+}
+
+impl Sorter<'_> {
+    /// Write `node` verbatim, except that the pairs of every object in it
+    /// are sorted.
+    fn write(&self, node: Node<'_>, out: &mut String) {
+        if node.kind() == kinds::OBJECT {
+            self.sort_object(node, out);
+            return;
+        }
+        let mut pos = node.start_byte();
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            push_span(self.source, pos, child.start_byte(), out);
+            self.write(child, out);
+            pos = child.end_byte();
+        }
+        push_span(self.source, pos, node.end_byte(), out);
+    }
+
+    /// Sort the pairs of an object. The object's layout (separators, blank
+    /// lines, dangling comments) stays where it is; pairs move between the
+    /// slots, taking their leading and trailing comments along.
+    fn sort_object(&self, object: Node<'_>, out: &mut String) {
+        if self.cancel.is_cancelled() {
+            return;
+        }
+        let source = self.source;
+        let mut cursor = object.walk();
+        let children: Vec<Node<'_>> = object.children(&mut cursor).collect();
+        let members: Vec<SortMember<'_>> = children
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| n.kind() == kinds::PAIR)
+            .map(|(i, &pair)| sort_member(&children, i, pair, source))
+            .collect();
+
+        let declared = self.declared_order(object);
+        let mut order: Vec<&SortMember<'_>> = members.iter().collect();
+        order.sort_by(|a, b| {
+            let rank = |m: &SortMember<'_>| declared.get(&m.key).copied().unwrap_or(usize::MAX);
+            rank(a).cmp(&rank(b)).then_with(|| a.key.cmp(&b.key))
+        });
+
+        let mut pos = object.start_byte();
+        for (slot, moved) in members.iter().zip(order) {
+            push_span(source, pos, slot.start, out);
+            push_span(source, moved.start, moved.pair.start_byte(), out);
+            self.write(moved.pair, out);
+
+            // Keep the slot's separator but swap in the moved pair's
+            // trailing comments.
+            let sep = slot.pair.end_byte();
+            pos = match slot.trailing {
+                Some((start, end)) => {
+                    let before = span_str(source, sep, start);
+                    out.push_str(if moved.trailing.is_some() {
+                        before
+                    } else {
+                        before.trim_end_matches([' ', '\t'])
+                    });
+                    end
+                }
+                None => {
+                    push_span(source, sep, slot.after, out);
+                    if moved.trailing.is_some() {
+                        out.push(' ');
+                    }
+                    slot.after
+                }
+            };
+            if let Some((start, end)) = moved.trailing {
+                push_span(source, start, end, out);
+                // A line comment must not swallow what follows on its line.
+                let next = members
+                    .iter()
+                    .find(|m| m.start > pos)
+                    .map_or(object.end_byte(), |m| m.start);
+                if source[start..].starts_with(b"//") && !source[pos..next].contains(&b'\n') {
+                    out.push_str(line_ending(source));
                 }
-                out.push('\n');
             }
-            write_indent(out, indent, depth);
-            out.push(']');
         }
-        serde_json::Value::Object(map) => {
-            if map.is_empty() {
-                out.push_str("{}");
-                return;
-            }
-            out.push_str("{\n");
-            let entries: Vec<_> = map.iter().collect();
-            for (i, (key, value)) in entries.iter().enumerate() {
-                write_indent(out, indent, depth + 1);
-                out.push('"');
-                escape_json_string(key, out);
-                out.push_str("\": ");
-                format_serde_value(value, indent, depth + 1, out);
-                if i < entries.len() - 1 {
-                    out.push(',');
-                }
-                out.push('\n');
+        push_span(source, pos, object.end_byte(), out);
+    }
+
+    /// Rank of each key the schema declares for `object`, across all of the
+    /// schema's branches.
+    fn declared_order(&self, object: Node<'_>) -> HashMap<String, usize> {
+        let mut ranks = HashMap::new();
+        let Some(root) = &self.schema else {
+            return ranks;
+        };
+        let path = tree::json_path(object, self.source);
+        let Some(scoped) = root.resolve_path(&path, self.store.registry()) else {
+            return ranks;
+        };
+        for branch in scoped.branches(self.store.registry()) {
+            for key in &branch.schema.property_order {
+                let next = ranks.len();
+                ranks.entry(key.clone()).or_insert(next);
             }
-            write_indent(out, indent, depth);
-            out.push('}');
         }
+        ranks
     }
 }
 
-fn sort_value(val: &serde_json::Value) -> serde_json::Value {
-    match val {
-        serde_json::Value::Object(map) => {
-            let mut sorted: Vec<(String, serde_json::Value)> = map
-                .iter()
-                .map(|(k, v)| (k.clone(), sort_value(v)))
-                .collect();
-            sorted.sort_by(|(a, _), (b, _)| a.cmp(b));
-            serde_json::Value::Object(sorted.into_iter().collect())
-        }
-        serde_json::Value::Array(arr) => {
-            serde_json::Value::Array(arr.iter().map(sort_value).collect())
+fn sort_member<'a>(
+    children: &[Node<'a>],
+    i: usize,
+    pair: Node<'a>,
+    source: &[u8],
+) -> SortMember<'a> {
+    let key = pair
+        .child_by_field_name("key")
+        .and_then(|k| tree::string_value(k, source))
+        .unwrap_or_default();
+
+    // Comments directly above, minus those on the previous token's line.
+    let mut first = i;
+    while first > 0 && children[first - 1].kind() == kinds::COMMENT {
+        first -= 1;
+    }
+    if first > 0 {
+        let row = children[first - 1].end_position().row;
+        while first < i && children[first].start_position().row == row {
+            first += 1;
         }
-        other => other.clone(),
     }
+
+    let row = pair.end_position().row;
+    let mut next = i + 1;
+    let mut after = pair.end_byte();
+    if let Some(comma) = children.get(next)
+        && comma.kind() == ","
+        && comma.start_position().row == row
+    {
+        after = comma.end_byte();
+        next += 1;
+    }
+    let mut last = next;
+    while children
+        .get(last)
+        .is_some_and(|n| n.kind() == kinds::COMMENT && n.start_position().row == row)
+    {
+        last += 1;
+    }
+
+    SortMember {
+        key,
+        pair,
+        start: children[first].start_byte(),
+        after,
+        trailing: (last > next)
+            .then(|| (children[next].start_byte(), children[last - 1].end_byte())),
+    }
+}
+
+fn push_span(source: &[u8], start: usize, end: usize, out: &mut String) {
+    out.push_str(span_str(source, start, end));
+}
+
+fn span_str(source: &[u8], start: usize, end: usize) -> &str {
+    std::str::from_utf8(&source[start..end]).unwrap_or("")
 }
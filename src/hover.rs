@@ -5,12 +5,18 @@
 use std::sync::Arc;
 
 use crate::document::Document;
+use crate::schema::resolver::SchemaStore;
 use crate::schema::types::*;
 use crate::tree::{self, kinds};
 use lsp_types::*;
 
 /// Produce hover information at a byte offset.
-pub fn hover(doc: &Document, offset: usize, schema: Option<&Arc<JsonSchema>>) -> Option<Hover> {
+pub fn hover(
+    doc: &Document,
+    offset: usize,
+    schema: Option<&Arc<JsonSchema>>,
+    store: &SchemaStore,
+) -> Option<Hover> {
     let node = tree::node_at_offset(&doc.tree, offset)?;
 
     let mut sections: Vec<String> = Vec::new();
@@ -32,34 +38,42 @@ pub fn hover(doc: &Document, offset: usize, schema: Option<&Arc<JsonSchema>>) ->
         sections.push(pointer);
     }
 
-    // Schema info.
+    // Schema info. A `$ref` target or composition branch may carry what the
+    // referencing schema leaves out, so take each field from the first branch
+    // that has it.
     if let Some(root_schema) = schema
-        && let Some(sub) = resolve_schema_with_path(&path_segments, root_schema)
+        && let Some(sub) =
+            ScopedSchema::new(root_schema).resolve_path(&path_segments, store.registry())
     {
-        if let Some(desc) = sub
-            .markdown_description
-            .as_deref()
-            .or(sub.description.as_deref())
-        {
+        let branches = sub.branches(store.registry());
+        let schemas = || branches.iter().map(|b| &*b.schema);
+
+        if let Some(desc) = schemas().find_map(|s| {
+            s.markdown_description
+                .as_deref()
+                .or(s.description.as_deref())
+        }) {
             sections.push(desc.to_string());
         }
 
-        if !sub.types.is_empty() {
-            let types: Vec<&str> = sub.types.iter().map(|t| t.as_str()).collect();
+        if let Some(s) = schemas().find(|s| !s.types.is_empty()) {
+            let types: Vec<&str> = s.types.iter().map(|t| t.as_str()).collect();
             sections.push(format!("Type: `{}`", types.join(" | ")));
         }
 
-        if let Some(ref def) = sub.default {
+        if let Some(def) = schemas().find_map(|s| s.default.as_ref()) {
             sections.push(format!("Default: `{def}`"));
         }
 
-        if !sub.enum_values.is_empty() && sub.enum_values.len() <= 20 {
-            let vals: Vec<String> = sub.enum_values.iter().map(|v| format!("`{v}`")).collect();
+        if let Some(s) = schemas().find(|s| !s.enum_values.is_empty())
+            && s.enum_values.len() <= 20
+        {
+            let vals: Vec<String> = s.enum_values.iter().map(|v| format!("`{v}`")).collect();
             sections.push(format!("Allowed values: {}", vals.join(", ")));
         }
 
-        if sub.deprecated {
-            let msg = sub.deprecation_message.as_deref().unwrap_or("Deprecated");
+        if let Some(s) = schemas().find(|s| s.deprecated) {
+            let msg = s.deprecation_message.as_deref().unwrap_or("Deprecated");
             sections.push(format!("**Deprecated:** {msg}"));
         }
     }
@@ -96,13 +110,36 @@ pub fn hover(doc: &Document, offset: usize, schema: Option<&Arc<JsonSchema>>) ->
     })
 }
 
-fn resolve_schema_with_path(
-    path: &[String],
-    root_schema: &Arc<JsonSchema>,
-) -> Option<Arc<JsonSchema>> {
-    let mut current = root_schema.clone();
-    for seg in path {
-        current = current.resolve_path_segment(seg)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hover_text(src: &str, offset: usize, schema: serde_json::Value) -> String {
+        let doc = Document::new(src.into(), 0);
+        let schema = JsonSchema::from_value(&schema);
+        match hover(&doc, offset, Some(&schema), &SchemaStore::new()) {
+            Some(Hover {
+                contents: HoverContents::Markup(markup),
+                ..
+            }) => markup.value,
+            other => panic!("unexpected hover: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn array_items_hover_from_item_keywords() {
+        let schema = serde_json::json!({
+            "anyOf": [
+                {"items": {"description": "Any item."}},
+                {"prefixItems": [{"description": "The first item."}]},
+                {"additionalProperties": {"description": "A property."}}
+            ]
+        });
+        let src = "[1, 2]";
+        assert!(hover_text(src, 1, schema.clone()).contains("The first item."));
+        assert!(hover_text(src, 4, schema.clone()).contains("Any item."));
+
+        let schema = serde_json::json!({"additionalProperties": {"description": "A property."}});
+        assert!(!hover_text(src, 1, schema).contains("A property."));
     }
-    Some(current)
 }
@@ -1,5 +1,14 @@
+// Tests come before the private helpers, as in the crate root's modules.
+
+pub mod catalog;
+pub mod disk_cache;
+#[allow(clippy::items_after_test_module)]
+pub mod format;
+pub mod meta;
 pub mod resolver;
+#[allow(clippy::items_after_test_module)]
 pub mod types;
+#[allow(clippy::items_after_test_module)]
 pub mod validation;
 
 pub use resolver::SchemaStore;
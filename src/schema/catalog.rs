@@ -0,0 +1,169 @@
+/// Schema catalog in the schemastore.org `catalog.json` format: a list of
+/// schema URLs with the file names they apply to. A selection of
+/// schemastore.org entries for JSON files is bundled so well-known files
+/// (package.json, tsconfig.json, ...) get a schema without any
+/// configuration; `scripts/update-catalog.py` replaces it with the full
+/// upstream catalog. A local catalog file can replace it too.
+use std::path::Path;
+use std::sync::{Arc, LazyLock};
+
+use serde::Deserialize;
+use tracing::warn;
+
+use super::resolver::FileMatch;
+
+static BUNDLED: LazyLock<Arc<Catalog>> = LazyLock::new(|| {
+    let raw = serde_json::from_str(include_str!("catalog.json"))
+        .expect("bundled catalog should be valid JSON");
+    Arc::new(Catalog::from_value(&raw).expect("bundled catalog should parse"))
+});
+
+#[derive(Deserialize)]
+struct RawCatalog {
+    schemas: Vec<RawEntry>,
+}
+
+#[derive(Deserialize)]
+struct RawEntry {
+    #[serde(default)]
+    name: String,
+    #[serde(default, rename = "fileMatch")]
+    file_match: Vec<String>,
+    url: String,
+}
+
+/// A catalog entry that applies to at least one file pattern.
+#[derive(Debug)]
+pub struct CatalogEntry {
+    pub name: String,
+    pub url: String,
+    file_match: FileMatch,
+}
+
+#[derive(Debug, Default)]
+pub struct Catalog {
+    entries: Vec<CatalogEntry>,
+}
+
+impl Catalog {
+    /// The catalog compiled into the binary.
+    pub fn bundled() -> Arc<Catalog> {
+        BUNDLED.clone()
+    }
+
+    /// Read a catalog file from disk.
+    pub fn load(path: &Path) -> Option<Catalog> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| warn!("failed to read schema catalog {}: {}", path.display(), e))
+            .ok()?;
+        let raw = serde_json::from_str(&content)
+            .map_err(|e| warn!("invalid schema catalog {}: {}", path.display(), e))
+            .ok()?;
+        Catalog::from_value(&raw)
+    }
+
+    pub fn from_value(raw: &serde_json::Value) -> Option<Catalog> {
+        let raw = RawCatalog::deserialize(raw)
+            .map_err(|e| warn!("invalid schema catalog: {}", e))
+            .ok()?;
+        let entries = raw
+            .schemas
+            .into_iter()
+            .filter_map(|entry| {
+                let file_match = FileMatch::new(&entry.file_match);
+                (!file_match.is_empty()).then_some(CatalogEntry {
+                    name: entry.name,
+                    url: entry.url,
+                    file_match,
+                })
+            })
+            .collect();
+        Some(Catalog { entries })
+    }
+
+    pub fn entries(&self) -> &[CatalogEntry] {
+        &self.entries
+    }
+
+    /// The first entry whose file patterns match a document, given its
+    /// workspace-relative path.
+    pub fn match_document(&self, path: &str) -> Option<&CatalogEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.file_match.is_match(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matched(catalog: &Catalog, path: &str) -> Option<String> {
+        catalog.match_document(path).map(|e| e.url.clone())
+    }
+
+    #[test]
+    fn bundled_catalog_has_only_json_patterns() {
+        let raw: RawCatalog = serde_json::from_str(include_str!("catalog.json")).unwrap();
+        assert_eq!(Catalog::bundled().entries().len(), raw.schemas.len());
+        for entry in &raw.schemas {
+            for pattern in &entry.file_match {
+                let pattern = pattern.to_ascii_lowercase();
+                assert!(
+                    ![".yml", ".yaml", ".toml"]
+                        .iter()
+                        .any(|ext| pattern.ends_with(ext)),
+                    "{}: {pattern}",
+                    entry.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn bundled_catalog_matches_well_known_files() {
+        let catalog = Catalog::bundled();
+        assert_eq!(
+            matched(&catalog, "/home/u/project/package.json").as_deref(),
+            Some("https://json.schemastore.org/package.json")
+        );
+        assert_eq!(
+            matched(&catalog, "/p/tsconfig.build.json").as_deref(),
+            Some("https://json.schemastore.org/tsconfig.json")
+        );
+        assert_eq!(
+            matched(&catalog, "/p/.eslintrc.json").as_deref(),
+            Some("https://json.schemastore.org/eslintrc.json")
+        );
+        assert_eq!(
+            matched(&catalog, "/p/.vscode/settings.json").as_deref(),
+            Some("https://json.schemastore.org/vscode-settings.json")
+        );
+        assert_eq!(matched(&catalog, "/p/settings.json"), None);
+        assert_eq!(matched(&catalog, "/p/data.json"), None);
+    }
+
+    #[test]
+    fn name_patterns_match_the_file_name_only() {
+        let catalog = Catalog::from_value(&serde_json::json!({
+            "schemas": [
+                {"name": "a", "fileMatch": ["*.foo.json"], "url": "https://x.com/a.json"},
+                {"name": "b", "fileMatch": ["conf/*.json", "!conf/skip.json"], "url": "https://x.com/b.json"},
+                {"name": "none", "url": "https://x.com/none.json"}
+            ]
+        }))
+        .unwrap();
+        assert_eq!(catalog.entries().len(), 2);
+        assert_eq!(
+            matched(&catalog, "/dir.foo.json/x.foo.json").as_deref(),
+            Some("https://x.com/a.json")
+        );
+        assert_eq!(matched(&catalog, "/dir.foo.json/x.json"), None);
+        assert_eq!(
+            matched(&catalog, "/p/conf/a.json").as_deref(),
+            Some("https://x.com/b.json")
+        );
+        assert_eq!(matched(&catalog, "/p/conf/skip.json"), None);
+        assert_eq!(matched(&catalog, "/p/conf/sub/a.json"), None);
+    }
+}
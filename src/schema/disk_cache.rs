@@ -0,0 +1,136 @@
+/// Persistent cache of downloaded schemas, so they are available offline
+/// and across restarts. Each schema is stored as one JSON file holding its
+/// URI, body and HTTP validators (`ETag` / `Last-Modified`) for conditional
+/// revalidation.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+/// A downloaded schema together with its HTTP validators.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedSchema {
+    pub uri: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    pub body: serde_json::Value,
+}
+
+/// Directory of cached schemas, one file per URI.
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    dir: PathBuf,
+}
+
+impl DiskCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        DiskCache { dir: dir.into() }
+    }
+
+    /// `$XDG_CACHE_HOME/light-json-lsp/schemas`, falling back to
+    /// `~/.cache/light-json-lsp/schemas`.
+    pub fn default_location() -> Option<Self> {
+        let base = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+        Some(DiskCache::new(base.join("light-json-lsp").join("schemas")))
+    }
+
+    pub fn load(&self, uri: &str) -> Option<CachedSchema> {
+        let path = self.path_for(uri);
+        let content = fs::read_to_string(&path).ok()?;
+        match serde_json::from_str::<CachedSchema>(&content) {
+            // Guard against hash collisions.
+            Ok(entry) if entry.uri == uri => Some(entry),
+            Ok(_) => None,
+            Err(e) => {
+                warn!(
+                    "ignoring corrupt schema cache entry {}: {}",
+                    path.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Write an entry, replacing any previous one atomically.
+    pub fn store(&self, entry: &CachedSchema) {
+        let path = self.path_for(&entry.uri);
+        let tmp = path.with_extension("json.tmp");
+        let result = fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&tmp, serde_json::to_vec(entry).unwrap_or_default()))
+            .and_then(|_| fs::rename(&tmp, &path));
+        match result {
+            Ok(()) => debug!("cached schema {} at {}", entry.uri, path.display()),
+            Err(e) => warn!("failed to write schema cache {}: {}", path.display(), e),
+        }
+    }
+
+    fn path_for(&self, uri: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:016x}.json", fnv1a(uri.as_bytes())))
+    }
+}
+
+/// 64-bit FNV-1a: stable across builds, unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_cache(name: &str) -> DiskCache {
+        let dir =
+            std::env::temp_dir().join(format!("light-json-lsp-test-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        DiskCache::new(dir)
+    }
+
+    #[test]
+    fn store_and_load_round_trip() {
+        let cache = temp_cache("round-trip");
+        let entry = CachedSchema {
+            uri: "https://example.com/a.json".into(),
+            etag: Some("\"v1\"".into()),
+            last_modified: None,
+            body: serde_json::json!({"type": "object"}),
+        };
+        assert_eq!(cache.load(&entry.uri), None);
+        cache.store(&entry);
+        assert_eq!(cache.load(&entry.uri), Some(entry.clone()));
+
+        let updated = CachedSchema {
+            etag: Some("\"v2\"".into()),
+            ..entry.clone()
+        };
+        cache.store(&updated);
+        assert_eq!(cache.load(&entry.uri), Some(updated));
+        assert_eq!(cache.load("https://example.com/b.json"), None);
+        let _ = fs::remove_dir_all(&cache.dir);
+    }
+
+    #[test]
+    fn corrupt_entries_are_ignored() {
+        let cache = temp_cache("corrupt");
+        let uri = "https://example.com/a.json";
+        fs::create_dir_all(&cache.dir).unwrap();
+        fs::write(cache.path_for(uri), "not json").unwrap();
+        assert_eq!(cache.load(uri), None);
+        let _ = fs::remove_dir_all(&cache.dir);
+    }
+
+    #[test]
+    fn fnv1a_is_stable() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+}
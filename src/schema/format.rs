@@ -0,0 +1,848 @@
+//! `format` keyword validators.
+//!
+//! Each validator follows the grammar of the standard the JSON Schema spec
+//! points at (RFC 3339 dates and durations, RFC 5321 mailboxes, RFC 1123
+//! hostnames, RFC 4291 IPv6, RFC 3986/3987 URIs and IRIs, RFC 6570 URI
+//! templates, RFC 6901 JSON Pointers, ECMA-262 regular expressions).
+//! Unknown formats always pass.
+
+/// How `format` failures are reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FormatMode {
+    /// Assert for schemas declaring drafts 4–7, annotate otherwise (the
+    /// 2019-09+ default, also used when no draft is declared).
+    #[default]
+    Auto,
+    /// Failures are reported as warnings and never make a value invalid.
+    Annotation,
+    /// Failures are validation errors.
+    Assertion,
+}
+
+impl FormatMode {
+    /// Parse a `json.validate.format` setting value.
+    pub fn from_setting(s: &str) -> Option<Self> {
+        match s {
+            "auto" => Some(FormatMode::Auto),
+            "annotation" => Some(FormatMode::Annotation),
+            "assertion" => Some(FormatMode::Assertion),
+            _ => None,
+        }
+    }
+}
+
+/// Check `val` against a named format. Unknown formats are accepted.
+pub fn is_valid(format: &str, val: &str) -> bool {
+    match format {
+        "date-time" => is_date_time(val),
+        "date" => is_full_date(val),
+        "time" => is_full_time(val).is_some(),
+        "duration" => is_duration(val),
+        "email" => is_email(val, false),
+        "idn-email" => is_email(val, true),
+        "hostname" => is_hostname(val),
+        "idn-hostname" => is_idn_hostname(val),
+        "ipv4" => is_ipv4(val),
+        "ipv6" => is_ipv6(val),
+        "uri" => is_uri(val, false, false),
+        "uri-reference" => is_uri(val, true, false),
+        "iri" => is_uri(val, false, true),
+        "iri-reference" => is_uri(val, true, true),
+        "uri-template" => is_uri_template(val),
+        "json-pointer" => is_json_pointer(val),
+        "relative-json-pointer" => is_relative_json_pointer(val),
+        "regex" => is_ecma_regex(val),
+        "uuid" => is_uuid(val),
+        "color-hex" => {
+            val.starts_with('#')
+                && matches!(val.len(), 4 | 5 | 7 | 9)
+                && val[1..].bytes().all(|b| b.is_ascii_hexdigit())
+        }
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_date_time_valid() {
+        assert!(is_valid("date-time", "2023-01-15T10:30:00Z"));
+    }
+
+    #[test]
+    fn format_date_time_invalid() {
+        assert!(!is_valid("date-time", "not a date"));
+    }
+
+    #[test]
+    fn format_date_valid() {
+        assert!(is_valid("date", "2023-01-15"));
+    }
+
+    #[test]
+    fn format_date_invalid() {
+        assert!(!is_valid("date", "2023/01/15"));
+    }
+
+    #[test]
+    fn format_email_valid() {
+        assert!(is_valid("email", "user@example.com"));
+    }
+
+    #[test]
+    fn format_email_invalid() {
+        assert!(!is_valid("email", "not-an-email"));
+        assert!(!is_valid("email", "@example.com"));
+        assert!(!is_valid("email", "user@"));
+    }
+
+    #[test]
+    fn format_ipv4_valid() {
+        assert!(is_valid("ipv4", "192.168.1.1"));
+    }
+
+    #[test]
+    fn format_ipv4_invalid() {
+        assert!(!is_valid("ipv4", "256.1.1.1"));
+        assert!(!is_valid("ipv4", "1.2.3"));
+        assert!(!is_valid("ipv4", "01.2.3.4")); // Leading zeros.
+    }
+
+    #[test]
+    fn format_uri_valid() {
+        assert!(is_valid("uri", "https://example.com"));
+        assert!(is_valid("uri", "urn:isbn:0451450523"));
+    }
+
+    #[test]
+    fn format_uri_invalid() {
+        assert!(!is_valid("uri", "not a uri"));
+    }
+
+    #[test]
+    fn format_hostname_valid() {
+        assert!(is_valid("hostname", "example.com"));
+        assert!(is_valid("hostname", "sub.example.com"));
+    }
+
+    #[test]
+    fn format_hostname_invalid() {
+        assert!(!is_valid("hostname", "-invalid.com"));
+        assert!(!is_valid("hostname", ""));
+    }
+
+    #[test]
+    fn format_color_hex_valid() {
+        assert!(is_valid("color-hex", "#fff"));
+        assert!(is_valid("color-hex", "#ffffff"));
+        assert!(is_valid("color-hex", "#ffffffFF"));
+    }
+
+    #[test]
+    fn format_color_hex_invalid() {
+        assert!(!is_valid("color-hex", "fff"));
+        assert!(!is_valid("color-hex", "#ff"));
+    }
+
+    #[test]
+    fn format_unknown_passes() {
+        assert!(is_valid("x-custom", "anything"));
+    }
+
+    #[test]
+    fn format_date_time_rfc3339() {
+        assert!(is_valid("date-time", "1963-06-19T08:30:06.283185+05:30"));
+        assert!(is_valid("date-time", "1998-12-31T23:59:60Z"));
+        assert!(!is_valid("date-time", "1998-12-31T22:59:60Z")); // Leap second off UTC midnight.
+        assert!(!is_valid("date-time", "2021-02-29T00:00:00Z")); // Not a leap year.
+        assert!(!is_valid("date-time", "2023-01-15T10:30:00")); // Offset is required.
+    }
+
+    #[test]
+    fn format_time() {
+        assert!(is_valid("time", "08:30:06Z"));
+        assert!(is_valid("time", "23:59:60+00:00"));
+        assert!(!is_valid("time", "08:30:06"));
+        assert!(!is_valid("time", "24:00:00Z"));
+    }
+
+    #[test]
+    fn format_duration() {
+        assert!(is_valid("duration", "P4DT12H30M5S"));
+        assert!(is_valid("duration", "P2W"));
+        assert!(is_valid("duration", "PT1M"));
+        assert!(!is_valid("duration", "P"));
+        assert!(!is_valid("duration", "PT"));
+        assert!(!is_valid("duration", "P1D2H")); // Time units need `T`.
+        assert!(!is_valid("duration", "P2W1D")); // Weeks stand alone.
+        assert!(!is_valid("duration", "P1M2Y")); // Units out of order.
+    }
+
+    #[test]
+    fn format_email_rfc5321() {
+        assert!(is_valid("email", "\"joe bloggs\"@example.com"));
+        assert!(is_valid("email", "joe@[127.0.0.1]"));
+        assert!(is_valid("email", "joe@[IPv6:::1]"));
+        assert!(!is_valid("email", ".joe@example.com"));
+        assert!(!is_valid("email", "joe..bloggs@example.com"));
+    }
+
+    #[test]
+    fn format_ipv6() {
+        assert!(is_valid("ipv6", "::1"));
+        assert!(is_valid("ipv6", "::"));
+        assert!(is_valid("ipv6", "1:2:3:4:5:6:7:8"));
+        assert!(is_valid("ipv6", "::ffff:192.168.0.1"));
+        assert!(!is_valid("ipv6", "1::2::3"));
+        assert!(!is_valid("ipv6", "12345::"));
+        assert!(!is_valid("ipv6", "1:2:3:4:5:6:7:8:9"));
+        assert!(!is_valid("ipv6", "fe80::1%eth0"));
+    }
+
+    #[test]
+    fn format_uri_reference() {
+        assert!(is_valid("uri-reference", "/relative/path"));
+        assert!(is_valid("uri-reference", "#fragment"));
+        assert!(is_valid("uri-reference", ""));
+        assert!(!is_valid("uri", "/relative/path"));
+        assert!(!is_valid("uri", "http://exa mple.com"));
+        assert!(!is_valid("uri-reference", "\\\\WINDOWS\\fileshare"));
+        assert!(is_valid("iri", "http://ƒøø.ßår/?∂éœ=πîx#πîüx"));
+        assert!(!is_valid("uri", "http://ƒøø.ßår/"));
+    }
+
+    #[test]
+    fn format_uri_template() {
+        assert!(is_valid(
+            "uri-template",
+            "http://example.com/dictionary/{term:1}/{term}"
+        ));
+        assert!(is_valid("uri-template", "/search{?q,lang}"));
+        assert!(!is_valid(
+            "uri-template",
+            "http://example.com/dictionary/{term:1}/{term"
+        ));
+    }
+
+    #[test]
+    fn format_json_pointer() {
+        assert!(is_valid("json-pointer", ""));
+        assert!(is_valid("json-pointer", "/foo/0/a~1b/m~0n"));
+        assert!(!is_valid("json-pointer", "foo"));
+        assert!(!is_valid("json-pointer", "/foo~2"));
+        assert!(is_valid("relative-json-pointer", "0/foo"));
+        assert!(is_valid("relative-json-pointer", "1#"));
+        assert!(!is_valid("relative-json-pointer", "01/a"));
+        assert!(!is_valid("relative-json-pointer", "/foo"));
+    }
+
+    #[test]
+    fn format_uuid_and_regex() {
+        assert!(is_valid("uuid", "2eb8aa08-aa98-11ea-b4aa-73b441d16380"));
+        assert!(!is_valid("uuid", "2eb8aa08aa9811eab4aa73b441d16380"));
+        assert!(is_valid("regex", "^[a-z]+$"));
+        assert!(!is_valid("regex", "^(abc"));
+        // ECMA-262 syntax, not Rust's.
+        assert!(is_valid("regex", "(?<year>\\d{4})\\k<year>"));
+        assert!(is_valid("regex", "^(?=a)(a)\\1(?<!b)$"));
+        assert!(!is_valid("regex", "\\a"));
+        assert!(!is_valid("regex", "(?i)abc"));
+        assert!(!is_valid("regex", "^\\Z"));
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Dates and times (RFC 3339)
+// ---------------------------------------------------------------------------
+
+fn digits(s: &str, n: usize) -> Option<u32> {
+    if s.len() == n && s.bytes().all(|b| b.is_ascii_digit()) {
+        s.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn is_date_time(val: &str) -> bool {
+    let Some(sep) = val.find(['T', 't']) else {
+        return false;
+    };
+    is_full_date(&val[..sep]) && is_full_time(&val[sep + 1..]).is_some()
+}
+
+/// `full-date = date-fullyear "-" date-month "-" date-mday`
+fn is_full_date(val: &str) -> bool {
+    if !val.is_ascii() || val.len() != 10 || val.as_bytes()[4] != b'-' || val.as_bytes()[7] != b'-'
+    {
+        return false;
+    }
+    let (Some(year), Some(month), Some(day)) = (
+        digits(&val[..4], 4),
+        digits(&val[5..7], 2),
+        digits(&val[8..10], 2),
+    ) else {
+        return false;
+    };
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let max_day = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return false,
+    };
+    (1..=max_day).contains(&day)
+}
+
+/// `full-time = partial-time time-offset`. Returns the time converted to
+/// UTC minutes past midnight, which leap-second checks need.
+fn is_full_time(val: &str) -> Option<u32> {
+    let b = val.as_bytes();
+    if !val.is_ascii() || b.len() < 9 || b[2] != b':' || b[5] != b':' {
+        return None;
+    }
+    let hour = digits(&val[..2], 2)?;
+    let minute = digits(&val[3..5], 2)?;
+    let second = digits(&val[6..8], 2)?;
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let mut rest = &val[8..];
+    if let Some(frac) = rest.strip_prefix('.') {
+        let len = frac.bytes().take_while(u8::is_ascii_digit).count();
+        if len == 0 {
+            return None;
+        }
+        rest = &frac[len..];
+    }
+
+    let offset: i32 = match rest {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.as_bytes().first()? {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let off = &rest[1..];
+            if off.len() != 5 || off.as_bytes()[2] != b':' {
+                return None;
+            }
+            let oh = digits(&off[..2], 2)?;
+            let om = digits(&off[3..], 2)?;
+            if oh > 23 || om > 59 {
+                return None;
+            }
+            sign * (oh * 60 + om) as i32
+        }
+    };
+
+    let utc = (hour * 60 + minute) as i32 - offset;
+    let utc = utc.rem_euclid(24 * 60) as u32;
+    // A leap second can only be inserted at the end of a UTC day.
+    if second == 60 && utc != 23 * 60 + 59 {
+        return None;
+    }
+    Some(utc)
+}
+
+/// ISO 8601 durations as profiled by RFC 3339 appendix A.
+fn is_duration(val: &str) -> bool {
+    let Some(rest) = val.strip_prefix('P') else {
+        return false;
+    };
+    let (date, time) = match rest.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None => (rest, None),
+    };
+
+    // dur-week stands alone.
+    if let Some(weeks) = date.strip_suffix('W') {
+        return time.is_none() && !weeks.is_empty() && weeks.bytes().all(|b| b.is_ascii_digit());
+    }
+
+    // Units must appear in order, each at most once, and (per the ABNF)
+    // without gaps: a year is followed by months, not directly by days.
+    let date_ok = units_in_sequence(date, b"YMD");
+    let time_ok = match time {
+        Some(time) => !time.is_empty() && units_in_sequence(time, b"HMS"),
+        None => true,
+    };
+    date_ok && time_ok && (!date.is_empty() || time.is_some())
+}
+
+/// Parse `1*DIGIT unit` groups whose units form a contiguous run of `order`.
+fn units_in_sequence(s: &str, order: &[u8]) -> bool {
+    let mut expected: Option<usize> = None;
+    let mut rest = s.as_bytes();
+    while !rest.is_empty() {
+        let len = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+        if len == 0 || len == rest.len() {
+            return false;
+        }
+        let Some(pos) = order.iter().position(|&u| u == rest[len]) else {
+            return false;
+        };
+        if expected.is_some_and(|e| e != pos) {
+            return false;
+        }
+        expected = Some(pos + 1);
+        rest = &rest[len + 1..];
+    }
+    true
+}
+
+// ---------------------------------------------------------------------------
+// Email (RFC 5321 / RFC 6531)
+// ---------------------------------------------------------------------------
+
+fn is_email(val: &str, international: bool) -> bool {
+    let Some(at) = val.rfind('@') else {
+        return false;
+    };
+    let (local, domain) = (&val[..at], &val[at + 1..]);
+    if local.is_empty() || local.len() > 64 {
+        return false;
+    }
+
+    let local_ok = if let Some(quoted) = local.strip_prefix('"').and_then(|l| l.strip_suffix('"')) {
+        // Quoted-string: printable ASCII except `"` and `\`, which must be
+        // escaped with a backslash.
+        let mut chars = quoted.chars();
+        let mut ok = true;
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => ok &= chars.next().is_some_and(|c| (' '..='~').contains(&c)),
+                '"' => ok = false,
+                c => ok &= (' '..='~').contains(&c) || (international && !c.is_ascii()),
+            }
+        }
+        ok
+    } else {
+        // Dot-string: atoms separated by single dots.
+        local.split('.').all(|atom| {
+            !atom.is_empty()
+                && atom.chars().all(|c| {
+                    c.is_ascii_alphanumeric()
+                        || "!#$%&'*+-/=?^_`{|}~".contains(c)
+                        || (international && !c.is_ascii())
+                })
+        })
+    };
+
+    let domain_ok =
+        if let Some(literal) = domain.strip_prefix('[').and_then(|d| d.strip_suffix(']')) {
+            match literal.strip_prefix("IPv6:") {
+                Some(v6) => is_ipv6(v6),
+                None => is_ipv4(literal),
+            }
+        } else if international {
+            is_idn_hostname(domain)
+        } else {
+            is_hostname(domain)
+        };
+
+    local_ok && domain_ok
+}
+
+// ---------------------------------------------------------------------------
+// Hostnames (RFC 1123, RFC 5890)
+// ---------------------------------------------------------------------------
+
+fn is_hostname(val: &str) -> bool {
+    let val = val.strip_suffix('.').unwrap_or(val);
+    !val.is_empty()
+        && val.len() <= 253
+        && val.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                // Hyphens in positions 3-4 are reserved for A-labels.
+                && (label.get(2..4) != Some("--") || label.to_ascii_lowercase().starts_with("xn--"))
+        })
+}
+
+/// Internationalised hostnames. Full IDNA2008 needs Unicode property tables;
+/// this checks the structural rules: label separators, lengths, hyphen
+/// placement, and that labels hold only letters, digits, marks and hyphens.
+fn is_idn_hostname(val: &str) -> bool {
+    let is_sep = |c: char| matches!(c, '.' | '\u{3002}' | '\u{ff0e}' | '\u{ff61}');
+    let val = val.strip_suffix(is_sep).unwrap_or(val);
+    !val.is_empty()
+        && val.chars().count() <= 253
+        && val.split(is_sep).all(|label| {
+            let chars: Vec<char> = label.chars().collect();
+            !chars.is_empty()
+                && chars.len() <= 63
+                && chars[0] != '-'
+                && chars[chars.len() - 1] != '-'
+                && !(chars.len() >= 4
+                    && chars[2] == '-'
+                    && chars[3] == '-'
+                    && !label.to_ascii_lowercase().starts_with("xn--"))
+                && chars.iter().all(|&c| {
+                    c == '-'
+                        || c.is_ascii_alphanumeric()
+                        || (!c.is_ascii() && (c.is_alphanumeric() || is_combining_mark(c)))
+                })
+                && !is_combining_mark(chars[0])
+        })
+}
+
+/// The combining-mark blocks that matter for IDN label checks.
+fn is_combining_mark(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036f}'
+        | '\u{1ab0}'..='\u{1aff}'
+        | '\u{1dc0}'..='\u{1dff}'
+        | '\u{20d0}'..='\u{20ff}'
+        | '\u{fe20}'..='\u{fe2f}'
+        | '\u{0903}' | '\u{093e}'..='\u{094c}')
+}
+
+// ---------------------------------------------------------------------------
+// IP addresses (RFC 2673, RFC 4291)
+// ---------------------------------------------------------------------------
+
+fn is_ipv4(val: &str) -> bool {
+    let parts: Vec<&str> = val.split('.').collect();
+    parts.len() == 4
+        && parts.iter().all(|p| {
+            !p.is_empty()
+                && p.len() <= 3
+                && p.bytes().all(|b| b.is_ascii_digit())
+                && (p.len() == 1 || !p.starts_with('0'))
+                && p.parse::<u16>().is_ok_and(|n| n <= 255)
+        })
+}
+
+fn is_ipv6(val: &str) -> bool {
+    // An embedded IPv4 address may replace the last two groups.
+    let (hex_part, v4_groups) = match val.rfind(':') {
+        Some(idx) if val[idx + 1..].contains('.') => {
+            if !is_ipv4(&val[idx + 1..]) {
+                return false;
+            }
+            // Keep the colon so a trailing `::` stays intact.
+            (&val[..=idx], 2)
+        }
+        _ => (val, 0),
+    };
+
+    let (head, tail, compressed) = match hex_part.find("::") {
+        Some(idx) => (&hex_part[..idx], &hex_part[idx + 2..], true),
+        None => (hex_part, "", false),
+    };
+    if compressed && tail.contains("::") {
+        return false;
+    }
+
+    let groups = |s: &str, trailing_colon_ok: bool| -> Option<usize> {
+        if s.is_empty() {
+            return Some(0);
+        }
+        let s = if trailing_colon_ok {
+            s.strip_suffix(':').unwrap_or(s)
+        } else {
+            s
+        };
+        let mut count = 0;
+        for group in s.split(':') {
+            if group.is_empty() || group.len() > 4 || !group.bytes().all(|b| b.is_ascii_hexdigit())
+            {
+                return None;
+            }
+            count += 1;
+        }
+        Some(count)
+    };
+
+    let embedded = v4_groups > 0;
+    if compressed {
+        let (Some(h), Some(t)) = (groups(head, false), groups(tail, embedded)) else {
+            return false;
+        };
+        // An embedded IPv4 after `::` needs no separating colon.
+        if embedded && !tail.is_empty() && !tail.ends_with(':') {
+            return false;
+        }
+        h + t + v4_groups < 8
+    } else {
+        if embedded && !head.ends_with(':') {
+            return false;
+        }
+        groups(head, embedded) == Some(8 - v4_groups)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// URIs and IRIs (RFC 3986, RFC 3987)
+// ---------------------------------------------------------------------------
+
+fn is_unreserved(c: char, iri: bool) -> bool {
+    c.is_ascii_alphanumeric() || "-._~".contains(c) || (iri && is_ucschar(c))
+}
+
+fn is_sub_delim(c: char) -> bool {
+    "!$&'()*+,;=".contains(c)
+}
+
+/// RFC 3987 `ucschar` (plus `iprivate`, which is only allowed in queries but
+/// is rare enough to accept anywhere).
+fn is_ucschar(c: char) -> bool {
+    !c.is_ascii() && !c.is_control() && !c.is_whitespace()
+}
+
+/// Check that every character of `s` is unreserved, a sub-delim, one of
+/// `extra`, or part of a `%XX` escape.
+fn all_pchars(s: &str, extra: &str, iri: bool) -> bool {
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '%' {
+            let hex = (chars.next(), chars.next());
+            if !matches!(hex, (Some(a), Some(b)) if a.is_ascii_hexdigit() && b.is_ascii_hexdigit())
+            {
+                return false;
+            }
+        } else if !(is_unreserved(c, iri) || is_sub_delim(c) || extra.contains(c)) {
+            return false;
+        }
+    }
+    true
+}
+
+fn is_scheme(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic())
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+fn is_authority(s: &str, iri: bool) -> bool {
+    let (userinfo, hostport) = match s.rfind('@') {
+        Some(idx) => (Some(&s[..idx]), &s[idx + 1..]),
+        None => (None, s),
+    };
+    if let Some(userinfo) = userinfo
+        && !all_pchars(userinfo, ":", iri)
+    {
+        return false;
+    }
+    let (host, port) = if let Some(rest) = hostport.strip_prefix('[') {
+        let Some(end) = rest.find(']') else {
+            return false;
+        };
+        let literal = &rest[..end];
+        let ok = is_ipv6(literal)
+            || (literal.starts_with(['v', 'V'])
+                && literal.contains('.')
+                && all_pchars(literal, ":", false));
+        let after = &rest[end + 1..];
+        if !ok || !(after.is_empty() || after.starts_with(':')) {
+            return false;
+        }
+        (None, after.strip_prefix(':'))
+    } else {
+        match hostport.rfind(':') {
+            Some(idx) => (Some(&hostport[..idx]), Some(&hostport[idx + 1..])),
+            None => (Some(hostport), None),
+        }
+    };
+    if let Some(host) = host
+        && !all_pchars(host, "", iri)
+    {
+        return false;
+    }
+    port.is_none_or(|p| p.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Validate a URI (`reference == false`) or URI-reference, optionally with
+/// IRI characters.
+fn is_uri(val: &str, reference: bool, iri: bool) -> bool {
+    let (rest, fragment) = match val.split_once('#') {
+        Some((rest, f)) => (rest, Some(f)),
+        None => (val, None),
+    };
+    let (rest, query) = match rest.split_once('?') {
+        Some((rest, q)) => (rest, Some(q)),
+        None => (rest, None),
+    };
+    if !fragment.is_none_or(|f| all_pchars(f, ":@/?", iri))
+        || !query.is_none_or(|q| all_pchars(q, ":@/?", iri))
+    {
+        return false;
+    }
+
+    let scheme_end = rest.find(':').filter(|&idx| !rest[..idx].contains('/'));
+    let hier = match scheme_end {
+        Some(idx) if is_scheme(&rest[..idx]) => &rest[idx + 1..],
+        Some(_) => return false,
+        None if reference => {
+            // path-noscheme: a first segment containing ':' would have been
+            // taken as a scheme above.
+            rest
+        }
+        None => return false,
+    };
+
+    let path = match hier.strip_prefix("//") {
+        Some(after) => {
+            let end = after.find('/').unwrap_or(after.len());
+            if !is_authority(&after[..end], iri) {
+                return false;
+            }
+            &after[end..]
+        }
+        None => hier,
+    };
+    all_pchars(path, ":@/", iri)
+}
+
+/// RFC 6570 URI templates (level 4).
+fn is_uri_template(val: &str) -> bool {
+    let mut rest = val;
+    while !rest.is_empty() {
+        match rest.find(['{', '}']) {
+            Some(idx) if rest.as_bytes()[idx] == b'}' => return false,
+            Some(idx) => {
+                if !is_template_literal(&rest[..idx]) {
+                    return false;
+                }
+                let Some(end) = rest[idx..].find('}') else {
+                    return false;
+                };
+                if !is_template_expression(&rest[idx + 1..idx + end]) {
+                    return false;
+                }
+                rest = &rest[idx + end + 1..];
+            }
+            None => return is_template_literal(rest),
+        }
+    }
+    true
+}
+
+fn is_template_literal(s: &str) -> bool {
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '%' if !(chars.next().is_some_and(|c| c.is_ascii_hexdigit())
+                && chars.next().is_some_and(|c| c.is_ascii_hexdigit())) =>
+            {
+                return false;
+            }
+            '%' => {}
+            c if c.is_control() || " \"'<>\\^`{|}".contains(c) => return false,
+            _ => {}
+        }
+    }
+    true
+}
+
+fn is_template_expression(expr: &str) -> bool {
+    let vars = expr
+        .strip_prefix(|c: char| "+#./;?&=,!@|".contains(c))
+        .unwrap_or(expr);
+    !vars.is_empty()
+        && vars.split(',').all(|varspec| {
+            let (name, modifier) = match varspec.find([':', '*']) {
+                Some(idx) => (&varspec[..idx], Some(&varspec[idx..])),
+                None => (varspec, None),
+            };
+            let name_ok = !name.is_empty()
+                && !name.starts_with('.')
+                && !name.ends_with('.')
+                && !name.contains("..")
+                && all_template_varchars(name);
+            let modifier_ok = match modifier {
+                None | Some("*") => true,
+                Some(m) => m.strip_prefix(':').is_some_and(|len| {
+                    (1..=4).contains(&len.len())
+                        && !len.starts_with('0')
+                        && len.bytes().all(|b| b.is_ascii_digit())
+                }),
+            };
+            name_ok && modifier_ok
+        })
+}
+
+fn all_template_varchars(name: &str) -> bool {
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '%' if !(chars.next().is_some_and(|c| c.is_ascii_hexdigit())
+                && chars.next().is_some_and(|c| c.is_ascii_hexdigit())) =>
+            {
+                return false;
+            }
+            '%' => {}
+            c if c.is_ascii_alphanumeric() || c == '_' || c == '.' => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
+// ---------------------------------------------------------------------------
+// JSON Pointers (RFC 6901, draft-bhutton-relative-json-pointer)
+// ---------------------------------------------------------------------------
+
+fn is_json_pointer(val: &str) -> bool {
+    if val.is_empty() {
+        return true;
+    }
+    if !val.starts_with('/') {
+        return false;
+    }
+    let bytes = val.as_bytes();
+    bytes
+        .iter()
+        .enumerate()
+        .all(|(i, &b)| b != b'~' || matches!(bytes.get(i + 1), Some(b'0' | b'1')))
+}
+
+fn is_relative_json_pointer(val: &str) -> bool {
+    let len = val.bytes().take_while(u8::is_ascii_digit).count();
+    if len == 0 || (len > 1 && val.starts_with('0')) {
+        return false;
+    }
+    let mut rest = &val[len..];
+    // Optional index manipulation: `+N` / `-N` with a positive integer.
+    if let Some(stripped) = rest.strip_prefix(['+', '-']) {
+        let n = stripped.bytes().take_while(u8::is_ascii_digit).count();
+        if n == 0 || stripped.starts_with('0') {
+            return false;
+        }
+        rest = &stripped[n..];
+    }
+    rest == "#" || is_json_pointer(rest)
+}
+
+// ---------------------------------------------------------------------------
+// Regular expressions (ECMA-262)
+// ---------------------------------------------------------------------------
+
+/// Whether `val` is an ECMA-262 regular expression. It is parsed in Unicode
+/// mode, which rejects the identity escapes (`\a`) that only the legacy
+/// web-compatibility grammar allows. Compiling is not free, so validation
+/// goes through `RegexCache::is_ecma_regex`.
+pub fn is_ecma_regex(val: &str) -> bool {
+    regress::Regex::with_flags(val, "u").is_ok()
+}
+
+// ---------------------------------------------------------------------------
+// UUID (RFC 4122)
+// ---------------------------------------------------------------------------
+
+fn is_uuid(val: &str) -> bool {
+    let b = val.as_bytes();
+    b.len() == 36
+        && b.iter().enumerate().all(|(i, &c)| match i {
+            8 | 13 | 18 | 23 => c == b'-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
@@ -0,0 +1,106 @@
+/// Bundled meta-schemas for drafts 4, 6, 7, 2019-09 and 2020-12, so schema
+/// files get validation, completion and hover without network access.
+use std::sync::{Arc, LazyLock};
+
+use super::resolver::compile_schema;
+use super::types::JsonSchema;
+
+/// Canonical URI (without fragment) and source of every bundled document.
+const SOURCES: &[(&str, &str)] = &[
+    (
+        "http://json-schema.org/draft-04/schema",
+        include_str!("meta/draft-04.json"),
+    ),
+    (
+        "http://json-schema.org/draft-06/schema",
+        include_str!("meta/draft-06.json"),
+    ),
+    (
+        "http://json-schema.org/draft-07/schema",
+        include_str!("meta/draft-07.json"),
+    ),
+    (
+        "https://json-schema.org/draft/2019-09/schema",
+        include_str!("meta/draft2019-09/schema.json"),
+    ),
+    (
+        "https://json-schema.org/draft/2019-09/meta/core",
+        include_str!("meta/draft2019-09/meta/core.json"),
+    ),
+    (
+        "https://json-schema.org/draft/2019-09/meta/applicator",
+        include_str!("meta/draft2019-09/meta/applicator.json"),
+    ),
+    (
+        "https://json-schema.org/draft/2019-09/meta/validation",
+        include_str!("meta/draft2019-09/meta/validation.json"),
+    ),
+    (
+        "https://json-schema.org/draft/2019-09/meta/meta-data",
+        include_str!("meta/draft2019-09/meta/meta-data.json"),
+    ),
+    (
+        "https://json-schema.org/draft/2019-09/meta/format",
+        include_str!("meta/draft2019-09/meta/format.json"),
+    ),
+    (
+        "https://json-schema.org/draft/2019-09/meta/content",
+        include_str!("meta/draft2019-09/meta/content.json"),
+    ),
+    (
+        "https://json-schema.org/draft/2020-12/schema",
+        include_str!("meta/draft2020-12/schema.json"),
+    ),
+    (
+        "https://json-schema.org/draft/2020-12/meta/core",
+        include_str!("meta/draft2020-12/meta/core.json"),
+    ),
+    (
+        "https://json-schema.org/draft/2020-12/meta/applicator",
+        include_str!("meta/draft2020-12/meta/applicator.json"),
+    ),
+    (
+        "https://json-schema.org/draft/2020-12/meta/unevaluated",
+        include_str!("meta/draft2020-12/meta/unevaluated.json"),
+    ),
+    (
+        "https://json-schema.org/draft/2020-12/meta/validation",
+        include_str!("meta/draft2020-12/meta/validation.json"),
+    ),
+    (
+        "https://json-schema.org/draft/2020-12/meta/meta-data",
+        include_str!("meta/draft2020-12/meta/meta-data.json"),
+    ),
+    (
+        "https://json-schema.org/draft/2020-12/meta/format-annotation",
+        include_str!("meta/draft2020-12/meta/format-annotation.json"),
+    ),
+    (
+        "https://json-schema.org/draft/2020-12/meta/content",
+        include_str!("meta/draft2020-12/meta/content.json"),
+    ),
+];
+
+/// Compiled once per process and shared by every `SchemaStore`.
+static BUNDLED: LazyLock<Vec<(&'static str, Arc<JsonSchema>)>> = LazyLock::new(|| {
+    SOURCES
+        .iter()
+        .map(|(uri, source)| {
+            let raw: serde_json::Value =
+                serde_json::from_str(source).expect("bundled meta-schema is valid JSON");
+            (*uri, compile_schema(uri, &raw))
+        })
+        .collect()
+});
+
+/// All bundled meta-schema documents with their canonical URIs.
+pub fn bundled() -> &'static [(&'static str, Arc<JsonSchema>)] {
+    &BUNDLED
+}
+
+/// Look up a bundled meta-schema by URI. An empty fragment (as in
+/// `http://json-schema.org/draft-07/schema#`) is ignored.
+pub fn get(uri: &str) -> Option<&'static Arc<JsonSchema>> {
+    let uri = uri.strip_suffix('#').unwrap_or(uri);
+    BUNDLED.iter().find(|(u, _)| *u == uri).map(|(_, s)| s)
+}
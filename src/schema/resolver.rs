@@ -1,12 +1,17 @@
 /// Schema resolver: maps document URIs to schemas, fetches remote schemas,
 /// resolves $ref, and caches results.
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use globset::{Glob, GlobMatcher};
+use globset::{GlobBuilder, GlobMatcher};
 use tracing::{debug, warn};
 
-use super::types::JsonSchema;
+use super::catalog::Catalog;
+use super::disk_cache::CachedSchema;
+use super::meta;
+use super::types::{Dependency, JsonSchema, SchemaOrBool};
 use crate::document::Document;
 use crate::tree::{self, kinds};
 
@@ -16,24 +21,133 @@ pub struct SchemaAssociation {
     pub file_match: Vec<String>,
     pub uri: String,
     pub schema: Option<Arc<JsonSchema>>,
+    /// Restricts the association to documents inside this folder; patterns
+    /// are then matched relative to it.
+    pub folder_uri: Option<String>,
 }
 
-const MAX_SCHEMA_CACHE: usize = 32;
+/// A compiled association.
+struct Association {
+    file_match: FileMatch,
+    folder: Option<String>,
+    uri: String,
+    schema: Option<Arc<JsonSchema>>,
+}
+
+/// Compiled `fileMatch` patterns, matched VS Code style against a path
+/// relative to the workspace folder (`/config/a.json`): a leading `/`
+/// anchors a pattern to the folder, other patterns match at any depth,
+/// and `!pattern` excludes files the other patterns match.
+#[derive(Debug)]
+pub(crate) struct FileMatch {
+    include: Vec<GlobMatcher>,
+    exclude: Vec<GlobMatcher>,
+}
+
+impl FileMatch {
+    pub(crate) fn new(patterns: &[String]) -> Self {
+        let mut file_match = FileMatch {
+            include: Vec::new(),
+            exclude: Vec::new(),
+        };
+        for pattern in patterns {
+            let (negated, pat) = match pattern.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, pattern.as_str()),
+            };
+            let glob = if pat.starts_with('/') || pat.starts_with("**/") {
+                pat.to_string()
+            } else {
+                format!("**/{pat}")
+            };
+            let matcher = match GlobBuilder::new(&glob).literal_separator(true).build() {
+                Ok(glob) => glob.compile_matcher(),
+                Err(e) => {
+                    warn!("invalid glob pattern '{}': {}", pattern, e);
+                    continue;
+                }
+            };
+            if negated {
+                file_match.exclude.push(matcher);
+            } else {
+                file_match.include.push(matcher);
+            }
+        }
+        file_match
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.include.is_empty()
+    }
+
+    /// Whether a `/`-prefixed relative path matches.
+    pub(crate) fn is_match(&self, path: &str) -> bool {
+        self.include.iter().any(|m| m.is_match(path))
+            && !self.exclude.iter().any(|m| m.is_match(path))
+    }
+}
+
+/// Default memory budget for compiled schemas in the cache. Schemas pinned
+/// by open documents may push the cache over it.
+const SCHEMA_CACHE_BUDGET: usize = 64 * 1024 * 1024;
+
+/// A compiled schema in the cache, with its approximate size and the tick
+/// of its last use for LRU eviction.
+struct CacheEntry {
+    schema: Arc<JsonSchema>,
+    size: usize,
+    last_used: AtomicU64,
+}
 
 /// Manages schema associations, fetching, and caching.
 pub struct SchemaStore {
-    associations: Vec<(Vec<GlobMatcher>, String, Option<Arc<JsonSchema>>)>,
-    cache: HashMap<String, Arc<JsonSchema>>,
+    associations: Vec<Association>,
+    /// Workspace folder URIs (without trailing `/`); document paths are
+    /// matched relative to the folder containing them.
+    workspace_folders: Vec<String>,
+    /// Cached schemas and the resource registry, shared with validation
+    /// passes that run on a snapshot.
+    registry: SchemaRegistry,
+    /// Total approximate size of the cached schemas.
+    cache_size: usize,
+    cache_budget: usize,
+    /// Schema URI used by each open document. Pinned schemas, and the
+    /// schemas they reference, are never evicted.
+    pins: HashMap<String, String>,
+    /// Fallback associations for documents no configured pattern matches.
+    catalog: Option<Arc<Catalog>>,
     http: Option<ureq::Agent>,
 }
 
+/// The schemas `$ref`s resolve against. Cloning it is cheap, so a
+/// validation pass can take a snapshot and run without holding the lock on
+/// the store; its uses still count towards the store's LRU order.
+#[derive(Clone, Default)]
+pub struct SchemaRegistry {
+    cache: Arc<HashMap<String, Arc<CacheEntry>>>,
+    /// Every schema resource known by absolute URI (without fragment): cached
+    /// documents under their retrieval URI and `$id`, plus each embedded
+    /// subschema that declares its own `$id`. Bundled meta-schemas are
+    /// always registered.
+    resources: Arc<HashMap<String, Arc<JsonSchema>>>,
+    /// Monotonic counter stamped on cache entries when they are used.
+    clock: Arc<AtomicU64>,
+}
+
 impl SchemaStore {
     pub fn new() -> Self {
-        SchemaStore {
+        let mut store = SchemaStore {
             associations: Vec::new(),
-            cache: HashMap::new(),
+            workspace_folders: Vec::new(),
+            registry: SchemaRegistry::default(),
+            cache_size: 0,
+            cache_budget: SCHEMA_CACHE_BUDGET,
+            pins: HashMap::new(),
+            catalog: Some(Catalog::bundled()),
             http: None,
-        }
+        };
+        store.reindex();
+        store
     }
 
     /// Get or lazily create the HTTP agent (cheap clone via Arc internals).
@@ -51,26 +165,114 @@ impl SchemaStore {
     pub fn set_associations(&mut self, assocs: Vec<SchemaAssociation>) {
         self.associations.clear();
         for assoc in assocs {
-            let matchers: Vec<GlobMatcher> = assoc
-                .file_match
-                .iter()
-                .filter_map(|pat| {
-                    Glob::new(pat)
-                        .map(|g| g.compile_matcher())
-                        .map_err(|e| warn!("invalid glob pattern '{}': {}", pat, e))
-                        .ok()
-                })
-                .collect();
-            self.associations.push((matchers, assoc.uri, assoc.schema));
+            if let Some(ref schema) = assoc.schema {
+                index_resources(
+                    &assoc.uri,
+                    schema,
+                    Arc::make_mut(&mut self.registry.resources),
+                );
+            }
+            self.associations.push(Association {
+                file_match: FileMatch::new(&assoc.file_match),
+                folder: assoc
+                    .folder_uri
+                    .map(|uri| uri.trim_end_matches('/').to_string()),
+                uri: assoc.uri,
+                schema: assoc.schema,
+            });
         }
+        // Inline schemas of replaced associations must leave the registry.
+        self.reindex();
+    }
+
+    pub fn set_workspace_folders(&mut self, folders: Vec<String>) {
+        self.workspace_folders = folders
+            .into_iter()
+            .map(|uri| uri.trim_end_matches('/').to_string())
+            .collect();
+    }
+
+    pub fn workspace_folders(&self) -> &[String] {
+        &self.workspace_folders
+    }
+
+    /// Replace the schema catalog consulted after the configured
+    /// associations; `None` disables catalog matching.
+    pub fn set_catalog(&mut self, catalog: Option<Arc<Catalog>>) {
+        self.catalog = catalog;
     }
 
     pub fn reset_schema(&mut self, uri: &str) {
-        self.cache.remove(uri);
+        if let Some(entry) = Arc::make_mut(&mut self.registry.cache).remove(uri) {
+            self.cache_size -= entry.size;
+            self.reindex();
+        }
     }
 
     pub fn clear_cache(&mut self) {
-        self.cache.clear();
+        self.registry.cache = Arc::default();
+        self.cache_size = 0;
+        self.reindex();
+    }
+
+    /// Change the memory budget of the cache, evicting if now over it.
+    pub fn set_cache_budget(&mut self, bytes: usize) {
+        self.cache_budget = bytes;
+        if self.evict(None) {
+            self.reindex();
+        }
+    }
+
+    /// Approximate memory used by cached schemas, in bytes.
+    pub fn cache_size(&self) -> usize {
+        self.cache_size
+    }
+
+    /// Record that `doc_uri` is validated against `schema_uri` (or against
+    /// nothing), keeping that schema cached while the document is open.
+    pub fn pin_document(&mut self, doc_uri: &str, schema_uri: Option<&str>) {
+        match schema_uri {
+            Some(schema_uri) => {
+                self.pins
+                    .insert(doc_uri.to_string(), schema_uri.to_string());
+            }
+            None => {
+                self.pins.remove(doc_uri);
+            }
+        }
+    }
+
+    pub fn unpin_document(&mut self, doc_uri: &str) {
+        self.pins.remove(doc_uri);
+    }
+
+    /// The registry `$ref`s resolve against. Clone it for a snapshot that
+    /// outlives a borrow of the store.
+    pub fn registry(&self) -> &SchemaRegistry {
+        &self.registry
+    }
+
+    /// The schema URI pinned by an open document.
+    pub fn pinned_schema(&self, doc_uri: &str) -> Option<&str> {
+        self.pins.get(doc_uri).map(String::as_str)
+    }
+
+    /// Rebuild the resource registry from the bundled meta-schemas, the
+    /// cache and inline associations.
+    fn reindex(&mut self) {
+        let mut resources = HashMap::new();
+        for (uri, schema) in meta::bundled() {
+            index_resources(uri, schema, &mut resources);
+        }
+        for (uri, entry) in self.registry.cache.iter() {
+            index_resources(uri, &entry.schema, &mut resources);
+        }
+        for assoc in &self.associations {
+            if let Some(schema) = &assoc.schema {
+                index_resources(&assoc.uri, schema, &mut resources);
+            }
+        }
+        self.registry.resources = Arc::new(resources);
     }
 
     /// Determine the schema URI for a document (sync, no fetching).
@@ -81,8 +283,8 @@ impl SchemaStore {
     ) -> SchemaLookup {
         // 1. Check for $schema property.
         if let Some(schema_uri) = inline_schema_uri {
-            if let Some(cached) = self.cache.get(schema_uri) {
-                return SchemaLookup::Resolved(cached.clone());
+            if let Some(schema) = self.lookup(schema_uri) {
+                return SchemaLookup::Resolved(schema);
             }
             return SchemaLookup::NeedsFetch(schema_uri.to_string());
         }
@@ -92,8 +294,8 @@ impl SchemaStore {
             if let Some(schema) = inline {
                 return SchemaLookup::Resolved(schema);
             }
-            if let Some(cached) = self.cache.get(&uri) {
-                return SchemaLookup::Resolved(cached.clone());
+            if let Some(schema) = self.lookup(&uri) {
+                return SchemaLookup::Resolved(schema);
             }
             return SchemaLookup::NeedsFetch(uri);
         }
@@ -101,58 +303,214 @@ impl SchemaStore {
         SchemaLookup::None
     }
 
+    /// The URI of the schema a document is validated against, if any: its
+    /// `$schema` property, else the first matching association.
+    pub fn schema_uri_for_document(
+        &self,
+        doc_uri: &str,
+        inline_schema_uri: Option<&str>,
+    ) -> Option<String> {
+        inline_schema_uri
+            .map(str::to_string)
+            .or_else(|| self.match_association(doc_uri).map(|(uri, _)| uri))
+    }
+
+    /// A cached schema, or a bundled meta-schema, for a schema URI.
+    fn lookup(&self, uri: &str) -> Option<Arc<JsonSchema>> {
+        match self.registry.cache.get(uri) {
+            Some(entry) => {
+                self.registry.touch(entry);
+                Some(entry.schema.clone())
+            }
+            None => meta::get(uri).cloned(),
+        }
+    }
+
+    /// Cache a compiled schema under its retrieval URI, then evict the least
+    /// recently used unpinned schemas while the cache is over budget.
     pub fn insert_cache(&mut self, uri: String, schema: Arc<JsonSchema>) {
-        if self.cache.len() >= MAX_SCHEMA_CACHE && !self.cache.contains_key(&uri) {
-            self.cache.clear();
+        let entry = CacheEntry {
+            size: schema.approx_size(),
+            schema,
+            last_used: AtomicU64::new(0),
+        };
+        self.registry.touch(&entry);
+        self.cache_size += entry.size;
+        index_resources(
+            &uri,
+            &entry.schema,
+            Arc::make_mut(&mut self.registry.resources),
+        );
+        let replaced = Arc::make_mut(&mut self.registry.cache).insert(uri.clone(), Arc::new(entry));
+        if let Some(old) = &replaced {
+            self.cache_size -= old.size;
+        }
+        // The old schema's embedded resources go with it.
+        if self.evict(Some(&uri)) || replaced.is_some() {
+            self.reindex();
         }
-        self.cache.insert(uri, schema);
+    }
+
+    /// Evict least recently used entries until the cache fits its budget.
+    /// Pinned schemas, everything they reference, and `keep` are spared.
+    /// Returns whether anything was evicted.
+    fn evict(&mut self, keep: Option<&str>) -> bool {
+        if self.cache_size <= self.cache_budget {
+            return false;
+        }
+        let pinned = self.pinned_closure();
+        let mut candidates: Vec<(u64, String)> = self
+            .registry
+            .cache
+            .iter()
+            .filter(|(uri, _)| Some(uri.as_str()) != keep && !pinned.contains(uri.as_str()))
+            .map(|(uri, entry)| (entry.last_used.load(Ordering::Relaxed), uri.clone()))
+            .collect();
+        candidates.sort_unstable();
+
+        let mut evicted = false;
+        for (_, uri) in candidates {
+            if self.cache_size <= self.cache_budget {
+                break;
+            }
+            if let Some(entry) = Arc::make_mut(&mut self.registry.cache).remove(&uri) {
+                debug!("evicting schema {} ({} bytes)", uri, entry.size);
+                self.cache_size -= entry.size;
+                evicted = true;
+            }
+        }
+        evicted
+    }
+
+    /// Cached schemas reachable from the pinned ones through external refs.
+    fn pinned_closure(&self) -> HashSet<String> {
+        self.reachable(self.pins.values().cloned().collect())
+    }
+
+    /// Whether validating against `schema_uri` involves any of `uris`: it is
+    /// one of them, or references one directly or through other schemas.
+    pub fn depends_on(&self, schema_uri: &str, uris: &HashSet<String>) -> bool {
+        self.reachable(vec![schema_uri.to_string()])
+            .iter()
+            .any(|uri| uris.contains(uri))
+    }
+
+    /// The URIs of `roots` and of every schema they reference, following
+    /// refs through the schemas that are known.
+    fn reachable(&self, mut stack: Vec<String>) -> HashSet<String> {
+        let mut seen = HashSet::new();
+        while let Some(uri) = stack.pop() {
+            if !seen.insert(uri.clone()) {
+                continue;
+            }
+            let schema = match self.registry.cache.get(&uri) {
+                Some(entry) => &entry.schema,
+                None => match self.registry.resources.get(&uri) {
+                    Some(schema) => schema,
+                    None => continue,
+                },
+            };
+            let base = schema.id.as_deref().unwrap_or(&uri);
+            collect_external_refs(schema, base, &mut |found| stack.push(found));
+        }
+        seen
     }
 
     fn match_association(&self, doc_uri: &str) -> Option<(String, Option<Arc<JsonSchema>>)> {
-        for (matchers, uri, inline) in &self.associations {
-            for matcher in matchers {
-                if matcher.is_match(doc_uri) {
-                    return Some((uri.clone(), inline.clone()));
-                }
+        let workspace_path = self.workspace_path(doc_uri);
+        for assoc in &self.associations {
+            let path = match &assoc.folder {
+                Some(folder) => match relative_to(doc_uri, folder) {
+                    Some(path) => path,
+                    None => continue,
+                },
+                None => workspace_path.clone(),
+            };
+            if assoc.file_match.is_match(&path) {
+                return Some((assoc.uri.clone(), assoc.schema.clone()));
             }
         }
-        None
+        let entry = self.catalog.as_ref()?.match_document(&workspace_path)?;
+        Some((entry.url.clone(), None))
+    }
+
+    /// A document's path relative to the innermost workspace folder that
+    /// contains it, or its absolute path outside any folder. The path is
+    /// percent-decoded so that globs see the names as they are on disk.
+    fn workspace_path(&self, doc_uri: &str) -> String {
+        self.workspace_folders
+            .iter()
+            .filter_map(|folder| relative_to(doc_uri, folder))
+            .min_by_key(|path| path.len())
+            .unwrap_or_else(|| uri_path(doc_uri))
+    }
+
+    /// Collect the URIs of external schemas referenced (directly or through
+    /// other cached schemas) from `schema` that are not cached yet.
+    pub fn missing_refs(&self, schema: &Arc<JsonSchema>) -> Vec<String> {
+        let base = schema.id.as_deref().unwrap_or("");
+        let mut missing = Vec::new();
+        collect_external_refs(schema, base, &mut |uri| {
+            if !self.registry.resources.contains_key(&uri)
+                && !self.registry.cache.contains_key(&uri)
+                && !missing.contains(&uri)
+            {
+                missing.push(uri);
+            }
+        });
+        missing
     }
+}
 
+impl SchemaRegistry {
     /// Resolve a `$ref` within a schema.
+    ///
+    /// `current_root` is the root of the schema resource the ref appears in
+    /// and `base_uri` its base URI. The ref is resolved against `base_uri`
+    /// (RFC 3986); if the result names another resource it is looked up in
+    /// the registry. The fragment is either a JSON Pointer or an anchor.
     pub fn resolve_ref(
         &self,
         reference: &str,
-        current_schema: &Arc<JsonSchema>,
+        current_root: &Arc<JsonSchema>,
         base_uri: &str,
     ) -> RefLookup {
-        let (external, fragment) = match reference.find('#') {
-            Some(idx) => (&reference[..idx], &reference[idx + 1..]),
-            None => (reference, ""),
-        };
+        let target = resolve_uri(base_uri, reference);
+        let (uri, fragment) = target.split_once('#').unwrap_or((&target, ""));
 
-        if external.is_empty() {
-            let schema = current_schema.clone();
-            if fragment.is_empty() || fragment == "/" {
-                return RefLookup::Resolved(Some(schema));
-            }
-            return RefLookup::Resolved(resolve_pointer(&schema, fragment));
+        if uri == strip_fragment(base_uri) {
+            return RefLookup::Resolved(resolve_fragment(current_root, fragment).map(|schema| {
+                ResolvedRef {
+                    schema,
+                    resource: None,
+                }
+            }));
         }
 
-        let resolved_uri = resolve_relative_uri(base_uri, external);
-        if let Some(cached) = self.cache.get(&resolved_uri) {
-            let schema = cached.clone();
-            if fragment.is_empty() || fragment == "/" {
-                return RefLookup::Resolved(Some(schema));
-            }
-            return RefLookup::Resolved(resolve_pointer(&schema, fragment));
+        let cached = self.cache.get(uri).inspect(|entry| self.touch(entry));
+        if let Some(root) = self
+            .resources
+            .get(uri)
+            .or_else(|| cached.map(|entry| &entry.schema))
+        {
+            return RefLookup::Resolved(resolve_fragment(root, fragment).map(|schema| {
+                ResolvedRef {
+                    schema,
+                    resource: Some((root.clone(), uri.to_string())),
+                }
+            }));
         }
 
         RefLookup::NeedsFetch {
-            uri: resolved_uri,
+            uri: uri.to_string(),
             fragment: fragment.to_string(),
         }
     }
+
+    fn touch(&self, entry: &CacheEntry) {
+        let now = self.clock.fetch_add(1, Ordering::Relaxed) + 1;
+        entry.last_used.store(now, Ordering::Relaxed);
+    }
 }
 
 /// Result of a synchronous schema lookup.
@@ -164,10 +522,19 @@ pub enum SchemaLookup {
 
 /// Result of a synchronous $ref resolution.
 pub enum RefLookup {
-    Resolved(Option<Arc<JsonSchema>>),
+    Resolved(Option<ResolvedRef>),
     NeedsFetch { uri: String, fragment: String },
 }
 
+/// The target of a resolved `$ref`.
+pub struct ResolvedRef {
+    pub schema: Arc<JsonSchema>,
+    /// Root and base URI of the schema resource the target lives in, when
+    /// the ref left the resource it appeared in. Refs inside the target
+    /// must then be resolved against this resource instead.
+    pub resource: Option<(Arc<JsonSchema>, String)>,
+}
+
 impl Default for SchemaStore {
     fn default() -> Self {
         Self::new()
@@ -176,19 +543,10 @@ impl Default for SchemaStore {
 
 /// Fetch a schema from HTTP or file (blocking).
 pub fn fetch_schema(agent: &ureq::Agent, uri: &str) -> Option<serde_json::Value> {
-    if uri.starts_with("http://") || uri.starts_with("https://") {
-        match agent.get(uri).call() {
-            Ok(resp) => match resp.into_body().read_json() {
-                Ok(val) => Some(val),
-                Err(e) => {
-                    warn!("failed to parse schema from {}: {}", uri, e);
-                    None
-                }
-            },
-            Err(e) => {
-                warn!("failed to fetch schema {}: {}", uri, e);
-                None
-            }
+    if is_http(uri) {
+        match fetch_remote(agent, uri, None)? {
+            Fetched::Modified(entry) => Some(entry.body),
+            Fetched::NotModified => None,
         }
     } else if uri.starts_with("file://") {
         let path = uri.strip_prefix("file://").unwrap_or(uri);
@@ -205,6 +563,68 @@ pub fn fetch_schema(agent: &ureq::Agent, uri: &str) -> Option<serde_json::Value>
     }
 }
 
+pub fn is_http(uri: &str) -> bool {
+    uri.starts_with("http://") || uri.starts_with("https://")
+}
+
+/// Outcome of fetching a remote schema.
+#[derive(Debug)]
+pub enum Fetched {
+    /// A new body, with the validators the server sent.
+    Modified(CachedSchema),
+    /// The cached copy is still current (HTTP 304).
+    NotModified,
+}
+
+/// Fetch an HTTP(S) schema (blocking). With a `cached` copy, the request is
+/// conditional on its `ETag` / `Last-Modified`.
+pub fn fetch_remote(
+    agent: &ureq::Agent,
+    uri: &str,
+    cached: Option<&CachedSchema>,
+) -> Option<Fetched> {
+    let mut request = agent.get(uri);
+    if let Some(cached) = cached {
+        if let Some(etag) = &cached.etag {
+            request = request.header("If-None-Match", etag.as_str());
+        }
+        if let Some(modified) = &cached.last_modified {
+            request = request.header("If-Modified-Since", modified.as_str());
+        }
+    }
+    let resp = match request.call() {
+        Ok(resp) => resp,
+        Err(e) => {
+            warn!("failed to fetch schema {}: {}", uri, e);
+            return None;
+        }
+    };
+    if resp.status() == 304 {
+        return Some(Fetched::NotModified);
+    }
+
+    let header = |name: &str| {
+        resp.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from)
+    };
+    let etag = header("etag");
+    let last_modified = header("last-modified");
+    match resp.into_body().read_json() {
+        Ok(body) => Some(Fetched::Modified(CachedSchema {
+            uri: uri.to_string(),
+            etag,
+            last_modified,
+            body,
+        })),
+        Err(e) => {
+            warn!("failed to parse schema from {}: {}", uri, e);
+            None
+        }
+    }
+}
+
 /// Extract `$schema` property from the root object.
 pub fn extract_schema_property(doc: &Document) -> Option<String> {
     let root = tree::root_value(&doc.tree)?;
@@ -227,39 +647,800 @@ pub fn extract_schema_property(doc: &Document) -> Option<String> {
     None
 }
 
-/// Resolve a JSON Pointer fragment within a compiled schema.
+/// Compile a fetched schema document. Its `$id` is resolved against the
+/// retrieval URI, which itself becomes the base URI when there is no `$id`.
+pub fn compile_schema(uri: &str, raw: &serde_json::Value) -> Arc<JsonSchema> {
+    let mut schema = JsonSchema::from_value(raw);
+    let base = match schema.id {
+        Some(ref id) => resolve_uri(uri, id),
+        None => strip_fragment(uri).to_string(),
+    };
+    if let Some(s) = Arc::get_mut(&mut schema) {
+        s.id = Some(base);
+    }
+    schema
+}
+
+/// Register `schema` under `uri` and its `$id`, and every embedded resource
+/// under the absolute form of its `$id`.
+fn index_resources(
+    uri: &str,
+    schema: &Arc<JsonSchema>,
+    resources: &mut HashMap<String, Arc<JsonSchema>>,
+) {
+    fn walk(
+        schema: &Arc<JsonSchema>,
+        base: &str,
+        resources: &mut HashMap<String, Arc<JsonSchema>>,
+    ) {
+        for sub in schema.subschemas() {
+            match sub.id {
+                Some(ref id) => {
+                    let sub_base = resolve_uri(base, id);
+                    resources.insert(sub_base.clone(), sub.clone());
+                    walk(sub, &sub_base, resources);
+                }
+                None => walk(sub, base, resources),
+            }
+        }
+    }
+
+    let uri = strip_fragment(uri);
+    let base = match schema.id {
+        Some(ref id) => resolve_uri(uri, id),
+        None => uri.to_string(),
+    };
+    resources.insert(uri.to_string(), schema.clone());
+    resources.insert(base.clone(), schema.clone());
+    walk(schema, &base, resources);
+}
+
+/// Resolve the fragment part of a `$ref` (without `#`) against the root of a
+/// schema resource: either a JSON Pointer or a plain-name anchor.
+fn resolve_fragment(root: &Arc<JsonSchema>, fragment: &str) -> Option<Arc<JsonSchema>> {
+    if fragment.is_empty() || fragment == "/" {
+        return Some(root.clone());
+    }
+    if !fragment.starts_with('/') {
+        let anchor = percent_encoding::percent_decode_str(fragment)
+            .decode_utf8()
+            .ok()?;
+        if root.anchor.as_deref() == Some(&*anchor)
+            || root.dynamic_anchor.as_deref() == Some(&*anchor)
+        {
+            return Some(root.clone());
+        }
+        return root.anchors.get(&*anchor).cloned();
+    }
+    resolve_pointer(root, fragment)
+}
+
+/// Resolve a JSON Pointer fragment within a compiled schema. Each segment is
+/// interpreted as the schema keyword (or keyword argument) it names, so any
+/// pointer into a subschema position works, not just `definitions`.
 fn resolve_pointer(schema: &Arc<JsonSchema>, pointer: &str) -> Option<Arc<JsonSchema>> {
     let path = pointer.strip_prefix('/').unwrap_or(pointer);
-    let segments: Vec<&str> = path.split('/').collect();
-
-    let mut current = schema.clone();
-    for segment in &segments {
+    let mut segments = path.split('/').map(|segment| {
         let decoded = percent_encoding::percent_decode_str(segment)
             .decode_utf8()
             .ok()?;
-        let key = decoded.replace("~1", "/").replace("~0", "~");
-
-        if let Some(found) = current.definitions.get(&*key) {
-            current = found.clone();
-        } else if let Some(found) = current.defs.get(&*key) {
-            current = found.clone();
-        } else if let Some(found) = current.properties.get(&*key) {
-            current = found.clone();
-        } else {
-            return None;
-        }
+        Some(decoded.replace("~1", "/").replace("~0", "~"))
+    });
+
+    let mut current = schema.clone();
+    while let Some(segment) = segments.next() {
+        let key = segment?;
+        let mut arg = || segments.next().flatten();
+        let next = match key.as_str() {
+            "properties" => current.properties.get(&arg()?).cloned(),
+            "definitions" => current.definitions.get(&arg()?).cloned(),
+            "$defs" => current.defs.get(&arg()?).cloned(),
+            "dependentSchemas" => current.dependent_schemas.get(&arg()?).cloned(),
+            "dependencies" => match current.dependencies.get(&arg()?) {
+                Some(Dependency::Schema(s)) => Some(s.clone()),
+                _ => None,
+            },
+            "patternProperties" => {
+                let pattern = arg()?;
+                current
+                    .pattern_properties
+                    .iter()
+                    .find(|(p, _)| *p == pattern)
+                    .map(|(_, s)| s.clone())
+            }
+            "allOf" => index_into(&current.all_of, &arg()?),
+            "anyOf" => index_into(&current.any_of, &arg()?),
+            "oneOf" => index_into(&current.one_of, &arg()?),
+            "prefixItems" => index_into(&current.prefix_items, &arg()?),
+            "items" if current.items_array => index_into(&current.prefix_items, &arg()?),
+            "items" => current.items.as_deref().map(schema_or_bool),
+            "additionalItems" => current.additional_items.as_deref().map(schema_or_bool),
+            "additionalProperties" => current.additional_properties.as_deref().map(schema_or_bool),
+            "unevaluatedProperties" => current
+                .unevaluated_properties
+                .as_deref()
+                .map(schema_or_bool),
+            "unevaluatedItems" => current.unevaluated_items.as_deref().map(schema_or_bool),
+            "not" => current.not.clone(),
+            "if" => current.if_schema.clone(),
+            "then" => current.then_schema.clone(),
+            "else" => current.else_schema.clone(),
+            "contains" => current.contains.clone(),
+            "propertyNames" => current.property_names.clone(),
+            other => current.extra.get(other).cloned(),
+        };
+        current = next?;
     }
 
     Some(current)
 }
 
-fn resolve_relative_uri(base: &str, relative: &str) -> String {
-    if relative.contains("://") {
-        return relative.to_string();
+fn index_into(list: &[Arc<JsonSchema>], segment: &str) -> Option<Arc<JsonSchema>> {
+    list.get(segment.parse::<usize>().ok()?).cloned()
+}
+
+fn schema_or_bool(s: &SchemaOrBool) -> Arc<JsonSchema> {
+    match s {
+        SchemaOrBool::Schema(s) => s.clone(),
+        SchemaOrBool::Bool(b) => JsonSchema::from_value(&serde_json::Value::Bool(*b)),
+    }
+}
+
+/// Walk every subschema of `schema` and report the absolute URI of each
+/// `$ref` that points outside the resource it appears in. Embedded `$id`s
+/// update the base URI on the way down.
+fn collect_external_refs(schema: &JsonSchema, base: &str, found: &mut impl FnMut(String)) {
+    let resolved_base;
+    let base = match schema.id {
+        Some(ref id) => {
+            resolved_base = resolve_uri(base, id);
+            resolved_base.as_str()
+        }
+        None => base,
+    };
+    let references = [
+        &schema.reference,
+        &schema.dynamic_ref,
+        &schema.recursive_ref,
+    ];
+    for reference in references.into_iter().flatten() {
+        let target = resolve_uri(base, reference);
+        let uri = strip_fragment(&target);
+        if !uri.is_empty() && uri != base {
+            found(uri.to_string());
+        }
     }
-    if let Some(base_dir) = base.rfind('/') {
-        format!("{}/{}", &base[..base_dir], relative)
+    for sub in schema.subschemas().chain(schema.extra.values()) {
+        collect_external_refs(sub, base, found);
+    }
+}
+
+/// The decoded, `/`-prefixed path of `uri` below `folder`, if it is inside
+/// it. Both sides are decoded since clients differ in what they escape
+/// (`%40` vs `@`).
+fn relative_to(uri: &str, folder: &str) -> Option<String> {
+    let uri = percent_decode(uri);
+    let folder = percent_decode(folder);
+    uri.strip_prefix(&*folder)
+        .filter(|rest| rest.starts_with('/'))
+        .map(str::to_string)
+}
+
+/// The decoded path component of a URI (`file:///a%20b/c.json` ->
+/// `/a b/c.json`).
+fn uri_path(uri: &str) -> String {
+    let Some((_, rest)) = uri.split_once("://") else {
+        return percent_decode(uri).into_owned();
+    };
+    let path = rest.find('/').map_or("", |i| &rest[i..]);
+    let path = path.split(['?', '#']).next().unwrap_or(path);
+    percent_decode(path).into_owned()
+}
+
+fn percent_decode(s: &str) -> Cow<'_, str> {
+    percent_encoding::percent_decode_str(s).decode_utf8_lossy()
+}
+
+fn strip_fragment(uri: &str) -> &str {
+    uri.split_once('#').map_or(uri, |(uri, _)| uri)
+}
+
+// ---------------------------------------------------------------------------
+// URI references (RFC 3986)
+// ---------------------------------------------------------------------------
+
+/// The five components of a URI reference (RFC 3986 §3). Absent components
+/// are `None`, which differs from present-but-empty.
+struct UriParts<'a> {
+    scheme: Option<&'a str>,
+    authority: Option<&'a str>,
+    path: &'a str,
+    query: Option<&'a str>,
+    fragment: Option<&'a str>,
+}
+
+impl<'a> UriParts<'a> {
+    fn parse(uri: &'a str) -> Self {
+        let (rest, fragment) = match uri.split_once('#') {
+            Some((rest, f)) => (rest, Some(f)),
+            None => (uri, None),
+        };
+        let (rest, query) = match rest.split_once('?') {
+            Some((rest, q)) => (rest, Some(q)),
+            None => (rest, None),
+        };
+        let (scheme, rest) = match rest.find(':') {
+            Some(idx)
+                if !rest[..idx].contains('/')
+                    && rest[..idx].starts_with(|c: char| c.is_ascii_alphabetic())
+                    && rest[..idx]
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.')) =>
+            {
+                (Some(&rest[..idx]), &rest[idx + 1..])
+            }
+            _ => (None, rest),
+        };
+        let (authority, path) = match rest.strip_prefix("//") {
+            Some(rest) => {
+                let end = rest.find('/').unwrap_or(rest.len());
+                (Some(&rest[..end]), &rest[end..])
+            }
+            None => (None, rest),
+        };
+        UriParts {
+            scheme,
+            authority,
+            path,
+            query,
+            fragment,
+        }
+    }
+}
+
+/// Resolve a URI reference against a base URI (RFC 3986 §5.2). A relative
+/// base yields a relative result, which still serves as a registry key.
+pub fn resolve_uri(base: &str, reference: &str) -> String {
+    let r = UriParts::parse(reference);
+    let b = UriParts::parse(base);
+
+    let (scheme, authority, path, query);
+    if r.scheme.is_some() {
+        scheme = r.scheme;
+        authority = r.authority;
+        path = remove_dot_segments(r.path);
+        query = r.query;
     } else {
-        relative.to_string()
+        scheme = b.scheme;
+        if r.authority.is_some() {
+            authority = r.authority;
+            path = remove_dot_segments(r.path);
+            query = r.query;
+        } else {
+            authority = b.authority;
+            if r.path.is_empty() {
+                path = b.path.to_string();
+                query = r.query.or(b.query);
+            } else {
+                path = if r.path.starts_with('/') {
+                    remove_dot_segments(r.path)
+                } else if b.authority.is_some() && b.path.is_empty() {
+                    remove_dot_segments(&format!("/{}", r.path))
+                } else {
+                    let dir = b.path.rfind('/').map_or("", |idx| &b.path[..=idx]);
+                    remove_dot_segments(&format!("{dir}{}", r.path))
+                };
+                query = r.query;
+            }
+        }
+    }
+
+    let mut out = String::with_capacity(base.len() + reference.len());
+    if let Some(scheme) = scheme {
+        out.push_str(scheme);
+        out.push(':');
+    }
+    if let Some(authority) = authority {
+        out.push_str("//");
+        out.push_str(authority);
+    }
+    out.push_str(&path);
+    if let Some(query) = query {
+        out.push('?');
+        out.push_str(query);
+    }
+    if let Some(fragment) = r.fragment.filter(|f| !f.is_empty()) {
+        out.push('#');
+        out.push_str(fragment);
+    }
+    out
+}
+
+/// RFC 3986 §5.2.4.
+fn remove_dot_segments(path: &str) -> String {
+    let mut input = path;
+    let mut output: Vec<&str> = Vec::new();
+    while !input.is_empty() {
+        if let Some(rest) = input.strip_prefix("../") {
+            input = rest;
+        } else if let Some(rest) = input.strip_prefix("./") {
+            input = rest;
+        } else if input.starts_with("/./") {
+            input = &input[2..];
+        } else if input == "/." {
+            input = "/";
+        } else if input.starts_with("/../") || input == "/.." {
+            input = if input == "/.." { "/" } else { &input[3..] };
+            output.pop();
+        } else if input == "." || input == ".." {
+            input = "";
+        } else {
+            // Move the first segment, including its leading '/', to output.
+            let start = usize::from(input.starts_with('/'));
+            let end = input[start..].find('/').map_or(input.len(), |i| i + start);
+            output.push(&input[..end]);
+            input = &input[end..];
+        }
+    }
+    output.concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "http://a/b/c/d;p?q";
+
+    /// Serve one HTTP response per entry of `responses` on a local port,
+    /// returning the base URL and the request heads received.
+    fn serve(responses: Vec<String>) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        use std::io::{BufRead, BufReader, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut heads = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut head = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    head.push_str(&line.to_ascii_lowercase());
+                }
+                stream.write_all(response.as_bytes()).unwrap();
+                heads.push(head);
+            }
+            heads
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn fetch_remote_revalidates_with_validators() {
+        let body = r#"{"type": "object"}"#;
+        let (url, server) = serve(vec![
+            format!(
+                "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nLast-Modified: Tue, 01 Sep 2026 00:00:00 GMT\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            ),
+            "HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n".into(),
+        ]);
+        let uri = format!("{url}/schema.json");
+        let agent = SchemaStore::new().http_agent();
+
+        let Some(Fetched::Modified(entry)) = fetch_remote(&agent, &uri, None) else {
+            panic!("expected a body");
+        };
+        assert_eq!(entry.uri, uri);
+        assert_eq!(entry.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(
+            entry.last_modified.as_deref(),
+            Some("Tue, 01 Sep 2026 00:00:00 GMT")
+        );
+        assert_eq!(entry.body, serde_json::json!({"type": "object"}));
+
+        assert!(matches!(
+            fetch_remote(&agent, &uri, Some(&entry)),
+            Some(Fetched::NotModified)
+        ));
+        let heads = server.join().unwrap();
+        assert!(!heads[0].contains("if-none-match"));
+        assert!(heads[1].contains("if-none-match: \"v1\""));
+        assert!(heads[1].contains("if-modified-since: tue, 01 sep 2026 00:00:00 gmt"));
+    }
+
+    #[test]
+    fn resolve_uri_normal_examples() {
+        // RFC 3986 §5.4.1.
+        let cases = [
+            ("g:h", "g:h"),
+            ("g", "http://a/b/c/g"),
+            ("./g", "http://a/b/c/g"),
+            ("g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            ("//g", "http://g"),
+            ("?y", "http://a/b/c/d;p?y"),
+            ("g?y", "http://a/b/c/g?y"),
+            ("#s", "http://a/b/c/d;p?q#s"),
+            ("g#s", "http://a/b/c/g#s"),
+            ("g?y#s", "http://a/b/c/g?y#s"),
+            (";x", "http://a/b/c/;x"),
+            ("g;x", "http://a/b/c/g;x"),
+            ("", "http://a/b/c/d;p?q"),
+            (".", "http://a/b/c/"),
+            ("./", "http://a/b/c/"),
+            ("..", "http://a/b/"),
+            ("../", "http://a/b/"),
+            ("../g", "http://a/b/g"),
+            ("../..", "http://a/"),
+            ("../../g", "http://a/g"),
+        ];
+        for (reference, expected) in cases {
+            assert_eq!(resolve_uri(BASE, reference), expected, "ref {reference:?}");
+        }
+    }
+
+    #[test]
+    fn resolve_uri_abnormal_examples() {
+        // RFC 3986 §5.4.2.
+        let cases = [
+            ("../../../g", "http://a/g"),
+            ("/./g", "http://a/g"),
+            ("/../g", "http://a/g"),
+            ("g.", "http://a/b/c/g."),
+            ("..g", "http://a/b/c/..g"),
+            ("./../g", "http://a/b/g"),
+            ("g/./h", "http://a/b/c/g/h"),
+            ("g/../h", "http://a/b/c/h"),
+            ("g;x=1/../y", "http://a/b/c/y"),
+        ];
+        for (reference, expected) in cases {
+            assert_eq!(resolve_uri(BASE, reference), expected, "ref {reference:?}");
+        }
+    }
+
+    #[test]
+    fn resolve_uri_urn_and_relative_bases() {
+        assert_eq!(
+            resolve_uri("urn:uuid:deadbeef-1234", "#foo"),
+            "urn:uuid:deadbeef-1234#foo"
+        );
+        assert_eq!(resolve_uri("", "other.json#/a"), "other.json#/a");
+        assert_eq!(
+            resolve_uri("http://x.com/", "a.json"),
+            "http://x.com/a.json"
+        );
+        assert_eq!(resolve_uri("http://x.com", "a.json"), "http://x.com/a.json");
+    }
+
+    #[test]
+    fn registry_indexes_embedded_ids_and_anchors() {
+        let raw: serde_json::Value = serde_json::from_str(
+            r##"{
+                "$id": "http://example.com/root.json",
+                "$defs": {
+                    "A": {"$id": "nested/a.json", "$defs": {"B": {"$anchor": "bee", "type": "string"}}}
+                }
+            }"##,
+        )
+        .unwrap();
+        let mut store = SchemaStore::new();
+        let root = compile_schema("http://example.com/root.json", &raw);
+        store.insert_cache("http://example.com/root.json".into(), root.clone());
+
+        let RefLookup::Resolved(Some(resolved)) = store.registry().resolve_ref(
+            "nested/a.json#bee",
+            &root,
+            "http://example.com/root.json",
+        ) else {
+            panic!("embedded anchor did not resolve");
+        };
+        assert_eq!(resolved.schema.anchor.as_deref(), Some("bee"));
+        let (resource, uri) = resolved.resource.unwrap();
+        assert_eq!(uri, "http://example.com/nested/a.json");
+        assert_eq!(resource.id.as_deref(), Some("nested/a.json"));
+    }
+
+    #[test]
+    fn registry_reports_unknown_resource() {
+        let root = JsonSchema::from_value(&serde_json::json!({"$id": "http://example.com/a.json"}));
+        let store = SchemaStore::new();
+        assert!(matches!(
+            store.registry().resolve_ref("b.json#/x", &root, "http://example.com/a.json"),
+            RefLookup::NeedsFetch { ref uri, .. } if uri == "http://example.com/b.json"
+        ));
+    }
+
+    #[test]
+    fn meta_schemas_are_bundled() {
+        let store = SchemaStore::new();
+        for uri in [
+            "http://json-schema.org/draft-04/schema#",
+            "http://json-schema.org/draft-06/schema#",
+            "http://json-schema.org/draft-07/schema#",
+            "https://json-schema.org/draft/2019-09/schema",
+            "https://json-schema.org/draft/2020-12/schema",
+        ] {
+            let SchemaLookup::Resolved(schema) =
+                store.schema_for_document("file:///a.schema.json", Some(uri))
+            else {
+                panic!("{uri} should resolve without fetching");
+            };
+            assert!(store.missing_refs(&schema).is_empty(), "{uri}");
+        }
+    }
+
+    fn small_schema(uri: &str) -> Arc<JsonSchema> {
+        compile_schema(uri, &serde_json::json!({"type": "object"}))
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used() {
+        let mut store = SchemaStore::new();
+        let size = small_schema("http://x.com/a.json").approx_size();
+        store.set_cache_budget(2 * size);
+        store.insert_cache(
+            "http://x.com/a.json".into(),
+            small_schema("http://x.com/a.json"),
+        );
+        store.insert_cache(
+            "http://x.com/b.json".into(),
+            small_schema("http://x.com/b.json"),
+        );
+        assert_eq!(store.cache_size(), 2 * size);
+
+        // Using `a` makes `b` the eviction candidate.
+        assert!(store.lookup("http://x.com/a.json").is_some());
+        store.insert_cache(
+            "http://x.com/c.json".into(),
+            small_schema("http://x.com/c.json"),
+        );
+        assert!(store.lookup("http://x.com/a.json").is_some());
+        assert!(store.lookup("http://x.com/b.json").is_none());
+        assert!(store.lookup("http://x.com/c.json").is_some());
+        assert_eq!(store.cache_size(), 2 * size);
+
+        // Evicted schemas leave the resource registry too.
+        let root = JsonSchema::from_value(&serde_json::json!({}));
+        assert!(matches!(
+            store
+                .registry()
+                .resolve_ref("http://x.com/b.json", &root, "file:///doc.json"),
+            RefLookup::NeedsFetch { .. }
+        ));
+    }
+
+    #[test]
+    fn cache_keeps_pinned_schemas_and_their_refs() {
+        let mut store = SchemaStore::new();
+        let main = compile_schema(
+            "http://x.com/main.json",
+            &serde_json::json!({"$ref": "dep.json"}),
+        );
+        store.set_cache_budget(0);
+        store.pin_document("file:///doc.json", Some("http://x.com/main.json"));
+        store.insert_cache(
+            "http://x.com/dep.json".into(),
+            small_schema("http://x.com/dep.json"),
+        );
+        store.insert_cache("http://x.com/main.json".into(), main);
+        store.insert_cache(
+            "http://x.com/other.json".into(),
+            small_schema("http://x.com/other.json"),
+        );
+        assert!(store.lookup("http://x.com/main.json").is_some());
+        assert!(store.lookup("http://x.com/dep.json").is_some());
+
+        // The most recent insert survives until something else is cached.
+        store.insert_cache(
+            "http://x.com/new.json".into(),
+            small_schema("http://x.com/new.json"),
+        );
+        assert!(store.lookup("http://x.com/other.json").is_none());
+
+        store.unpin_document("file:///doc.json");
+        store.set_cache_budget(0);
+        assert_eq!(store.cache_size(), 0);
+        assert!(store.lookup("http://x.com/main.json").is_none());
+    }
+
+    #[test]
+    fn schemas_depend_on_what_they_reference() {
+        let mut store = SchemaStore::new();
+        for (uri, raw) in [
+            ("http://x.com/a.json", serde_json::json!({"$ref": "b.json"})),
+            ("http://x.com/b.json", serde_json::json!({"$ref": "c.json"})),
+            ("http://x.com/other.json", serde_json::json!({})),
+        ] {
+            store.insert_cache(uri.into(), compile_schema(uri, &raw));
+        }
+        // `c.json` isn't cached yet, which is when its arrival matters.
+        let fetched = HashSet::from(["http://x.com/c.json".to_string()]);
+        assert!(store.depends_on("http://x.com/a.json", &fetched));
+        assert!(store.depends_on("http://x.com/c.json", &fetched));
+        assert!(!store.depends_on("http://x.com/other.json", &fetched));
+        assert!(!store.depends_on("http://x.com/missing.json", &fetched));
+    }
+
+    #[test]
+    fn replacing_a_cached_schema_updates_its_size() {
+        let mut store = SchemaStore::new();
+        let uri = "http://x.com/a.json";
+        store.insert_cache(uri.into(), small_schema(uri));
+        let before = store.cache_size();
+        store.insert_cache(uri.into(), small_schema(uri));
+        assert_eq!(store.cache_size(), before);
+        store.reset_schema(uri);
+        assert_eq!(store.cache_size(), 0);
+    }
+
+    #[test]
+    fn replacing_a_cached_schema_drops_its_old_resources() {
+        let mut store = SchemaStore::new();
+        let uri = "http://x.com/a.json";
+        let embedded = "http://x.com/embedded.json";
+        let raw = serde_json::json!({"$defs": {"e": {"$id": embedded}}});
+        store.insert_cache(uri.into(), compile_schema(uri, &raw));
+        let root = JsonSchema::from_value(&serde_json::json!({}));
+        let lookup = || {
+            store
+                .registry()
+                .resolve_ref(embedded, &root, "file:///doc.json")
+        };
+        assert!(matches!(lookup(), RefLookup::Resolved(Some(_))));
+
+        store.insert_cache(uri.into(), small_schema(uri));
+        let lookup = || {
+            store
+                .registry()
+                .resolve_ref(embedded, &root, "file:///doc.json")
+        };
+        assert!(matches!(lookup(), RefLookup::NeedsFetch { .. }));
+    }
+
+    fn association(file_match: &[&str], uri: &str) -> SchemaAssociation {
+        SchemaAssociation {
+            file_match: file_match.iter().map(|p| p.to_string()).collect(),
+            uri: uri.into(),
+            schema: None,
+            folder_uri: None,
+        }
+    }
+
+    fn matched_uri(store: &SchemaStore, doc_uri: &str) -> Option<String> {
+        store.schema_uri_for_document(doc_uri, None)
+    }
+
+    #[test]
+    fn patterns_match_workspace_relative_paths() {
+        let mut store = SchemaStore::new();
+        store.set_catalog(None);
+        store.set_workspace_folders(vec!["file:///ws/".into()]);
+        store.set_associations(vec![
+            association(
+                &["/config/*.json", "!/config/local.json"],
+                "https://x.com/config.json",
+            ),
+            association(&["*.data.json"], "https://x.com/data.json"),
+        ]);
+        let config = Some("https://x.com/config.json".to_string());
+        assert_eq!(matched_uri(&store, "file:///ws/config/app.json"), config);
+        assert_eq!(matched_uri(&store, "file:///ws/config/local.json"), None);
+        assert_eq!(matched_uri(&store, "file:///ws/config/sub/app.json"), None);
+        assert_eq!(matched_uri(&store, "file:///ws/src/config/app.json"), None);
+        // Outside any workspace folder the absolute path is used.
+        assert_eq!(matched_uri(&store, "file:///config/app.json"), config);
+        assert_eq!(
+            matched_uri(&store, "file:///ws/a/b/x.data.json").as_deref(),
+            Some("https://x.com/data.json")
+        );
+    }
+
+    #[test]
+    fn folder_scoped_associations() {
+        let mut store = SchemaStore::new();
+        store.set_catalog(None);
+        store.set_workspace_folders(vec!["file:///ws".into()]);
+        store.set_associations(vec![SchemaAssociation {
+            folder_uri: Some("file:///ws/pkg-a".into()),
+            ..association(&["/settings.json"], "https://x.com/a.json")
+        }]);
+        assert_eq!(
+            matched_uri(&store, "file:///ws/pkg-a/settings.json").as_deref(),
+            Some("https://x.com/a.json")
+        );
+        assert_eq!(matched_uri(&store, "file:///ws/settings.json"), None);
+        assert_eq!(matched_uri(&store, "file:///ws/pkg-ab/settings.json"), None);
+    }
+
+    #[test]
+    fn patterns_match_decoded_paths() {
+        let mut store = SchemaStore::new();
+        store.set_catalog(None);
+        store.set_workspace_folders(vec!["file:///home/me/my%20project".into()]);
+        store.set_associations(vec![
+            association(&["/my config/*.json"], "https://x.com/spaces.json"),
+            association(&["**/@scope/*/package.json"], "https://x.com/scoped.json"),
+            association(&["/données.json"], "https://x.com/accents.json"),
+        ]);
+        assert_eq!(
+            matched_uri(&store, "file:///home/me/my%20project/my%20config/a.json").as_deref(),
+            Some("https://x.com/spaces.json")
+        );
+        // Clients differ in whether they escape `@`; both forms must match.
+        for uri in [
+            "file:///home/me/my%20project/node_modules/@scope/pkg/package.json",
+            "file:///home/me/my%20project/node_modules/%40scope/pkg/package.json",
+        ] {
+            assert_eq!(
+                matched_uri(&store, uri).as_deref(),
+                Some("https://x.com/scoped.json"),
+                "{uri}"
+            );
+        }
+        assert_eq!(
+            matched_uri(&store, "file:///home/me/my%20project/donn%C3%A9es.json").as_deref(),
+            Some("https://x.com/accents.json")
+        );
+        // The folder itself may be given unescaped.
+        store.set_workspace_folders(vec!["file:///home/me/my project".into()]);
+        assert_eq!(
+            matched_uri(&store, "file:///home/me/my%20project/my%20config/a.json").as_deref(),
+            Some("https://x.com/spaces.json")
+        );
+    }
+
+    #[test]
+    fn inline_association_schemas_resolve_without_fetching() {
+        let mut store = SchemaStore::new();
+        let uri = "inmemory://schemas/settings/0";
+        store.set_associations(vec![SchemaAssociation {
+            schema: Some(compile_schema(uri, &serde_json::json!({"type": "object"}))),
+            ..association(&["*.json"], uri)
+        }]);
+        assert!(matches!(
+            store.schema_for_document("file:///ws/a.json", None),
+            SchemaLookup::Resolved(_)
+        ));
+    }
+
+    #[test]
+    fn associations_take_precedence_over_the_catalog() {
+        let mut store = SchemaStore::new();
+        let doc = "file:///p/package.json";
+        assert_eq!(
+            store.schema_uri_for_document(doc, None).as_deref(),
+            Some("https://json.schemastore.org/package.json")
+        );
+        store.set_associations(vec![association(
+            &["package.json"],
+            "https://x.com/custom.json",
+        )]);
+        assert_eq!(
+            store.schema_uri_for_document(doc, None).as_deref(),
+            Some("https://x.com/custom.json")
+        );
+        store.set_associations(Vec::new());
+        store.set_catalog(None);
+        assert_eq!(store.schema_uri_for_document(doc, None), None);
+    }
+
+    #[test]
+    fn meta_schemas_survive_cache_clear() {
+        let mut store = SchemaStore::new();
+        store.clear_cache();
+        let root = JsonSchema::from_value(&serde_json::json!({}));
+        assert!(matches!(
+            store.registry().resolve_ref(
+                "https://json-schema.org/draft/2020-12/meta/core#/$defs/anchorString",
+                &root,
+                "file:///a.json",
+            ),
+            RefLookup::Resolved(Some(_))
+        ));
     }
 }
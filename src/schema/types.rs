@@ -3,11 +3,13 @@
 /// Parsed from `serde_json::Value` into compact Rust structs. Uses `Arc` for
 /// shared ownership of sub-schemas, enabling cheap cloning during validation
 /// and completion walking.
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 use serde::Deserialize;
 
+use super::resolver::{RefLookup, ResolvedRef, SchemaRegistry, resolve_uri};
+
 // ---------------------------------------------------------------------------
 // Draft detection
 // ---------------------------------------------------------------------------
@@ -151,6 +153,9 @@ pub struct JsonSchema {
     // -- Array --
     pub items: Option<Box<SchemaOrBool>>,
     pub prefix_items: Vec<Arc<JsonSchema>>,
+    /// `items` was given in array (tuple) form, as drafts 4–2019-09 allow.
+    /// The tuple is stored in `prefix_items` and `additionalItems` in `items`.
+    pub items_array: bool,
     pub additional_items: Option<Box<SchemaOrBool>>,
     pub min_items: Option<u64>,
     pub max_items: Option<u64>,
@@ -158,9 +163,12 @@ pub struct JsonSchema {
     pub contains: Option<Arc<JsonSchema>>,
     pub min_contains: Option<u64>,
     pub max_contains: Option<u64>,
+    pub unevaluated_items: Option<Box<SchemaOrBool>>,
 
     // -- Object --
     pub properties: HashMap<String, Arc<JsonSchema>>,
+    /// Keys of `properties` in declaration order.
+    pub property_order: Vec<String>,
     pub required: Vec<String>,
     pub additional_properties: Option<Box<SchemaOrBool>>,
     pub pattern_properties: Vec<(String, Arc<JsonSchema>)>,
@@ -170,6 +178,7 @@ pub struct JsonSchema {
     pub dependencies: HashMap<String, Dependency>,
     pub dependent_required: HashMap<String, Vec<String>>,
     pub dependent_schemas: HashMap<String, Arc<JsonSchema>>,
+    pub unevaluated_properties: Option<Box<SchemaOrBool>>,
 
     // -- Composition --
     pub all_of: Vec<Arc<JsonSchema>>,
@@ -184,6 +193,18 @@ pub struct JsonSchema {
 
     // -- References --
     pub reference: Option<String>,
+    /// `$anchor`, or a plain-name fragment `$id` (`"#foo"`) in older drafts.
+    pub anchor: Option<String>,
+    /// Anchors (plain and dynamic) declared by subschemas in the same schema
+    /// resource, i.e. not below a nested `$id`. Only meaningful on resource
+    /// roots.
+    pub anchors: HashMap<String, Arc<JsonSchema>>,
+    /// 2020-12 `$dynamicRef`/`$dynamicAnchor`.
+    pub dynamic_ref: Option<String>,
+    pub dynamic_anchor: Option<String>,
+    /// 2019-09 `$recursiveRef`/`$recursiveAnchor`.
+    pub recursive_ref: Option<String>,
+    pub recursive_anchor: bool,
 
     // -- Definitions --
     pub definitions: HashMap<String, Arc<JsonSchema>>,
@@ -191,6 +212,12 @@ pub struct JsonSchema {
 
     // -- VS Code extensions --
     pub default_snippets: Vec<DefaultSnippet>,
+
+    // -- Unrecognised keywords --
+    /// Object-valued keywords this struct has no field for, compiled as
+    /// schemas so JSON Pointers into them (e.g. `#/components/schemas/Foo`)
+    /// still resolve.
+    pub extra: HashMap<String, Arc<JsonSchema>>,
 }
 
 /// Draft 4 uses boolean exclusiveMinimum/Maximum, draft 6+ uses numbers.
@@ -206,60 +233,143 @@ pub enum ExclusiveLimit {
 // Schema path resolution
 // ---------------------------------------------------------------------------
 
-impl JsonSchema {
-    /// Resolve a single path segment (property name or array index) against this
-    /// schema. Walks into allOf/anyOf/oneOf and if/then/else to find the sub-schema.
-    /// Used by both completion and hover to navigate from root schema to cursor position.
-    pub fn resolve_path_segment(self: &Arc<Self>, seg: &str) -> Option<Arc<JsonSchema>> {
-        // Direct property.
-        if let Some(prop) = self.properties.get(seg) {
-            return Some(prop.clone());
+/// A schema together with the schema resource (root schema and base URI)
+/// that its `$ref`s resolve against.
+#[derive(Debug, Clone)]
+pub struct ScopedSchema {
+    pub schema: Arc<JsonSchema>,
+    pub root: Arc<JsonSchema>,
+    pub base_uri: Arc<str>,
+}
+
+impl ScopedSchema {
+    /// Scope a document's root schema. Its `$id`, if any, is the base URI.
+    pub fn new(root: &Arc<JsonSchema>) -> Self {
+        ScopedSchema {
+            schema: root.clone(),
+            root: root.clone(),
+            base_uri: root.id.as_deref().unwrap_or("").into(),
         }
+    }
 
-        // Array index.
-        if let Ok(idx) = seg.parse::<usize>() {
-            if let Some(ps) = self.prefix_items.get(idx) {
-                return Some(ps.clone());
-            }
-            if let Some(ref items) = self.items
-                && let Some(s) = items.as_schema()
-            {
-                return Some(s.clone());
-            }
+    /// A subschema of `self`. It stays in the same resource unless it
+    /// declares its own `$id`, which starts an embedded one.
+    pub fn with_schema(&self, schema: &Arc<JsonSchema>) -> Self {
+        match schema.id {
+            Some(ref id) if !Arc::ptr_eq(schema, &self.root) => ScopedSchema {
+                schema: schema.clone(),
+                root: schema.clone(),
+                base_uri: resolve_uri(&self.base_uri, id).into(),
+            },
+            _ => ScopedSchema {
+                schema: schema.clone(),
+                root: self.root.clone(),
+                base_uri: self.base_uri.clone(),
+            },
         }
+    }
 
-        // Walk into composition schemas.
-        for sub in self
+    /// Follow this schema's `$ref`, local or into a cached external schema.
+    /// Without a `$ref`, `$dynamicRef`/`$recursiveRef` are followed to their
+    /// static target; navigation has no dynamic scope to consult.
+    pub fn deref(&self, registry: &SchemaRegistry) -> Option<Self> {
+        let reference = self
+            .schema
+            .reference
+            .as_deref()
+            .or(self.schema.dynamic_ref.as_deref())
+            .or(self.schema.recursive_ref.as_deref())?;
+        match registry.resolve_ref(reference, &self.root, &self.base_uri) {
+            RefLookup::Resolved(Some(ResolvedRef {
+                schema,
+                resource: Some((root, base_uri)),
+            })) => Some(ScopedSchema {
+                schema,
+                root,
+                base_uri: base_uri.into(),
+            }),
+            RefLookup::Resolved(Some(ResolvedRef {
+                schema,
+                resource: None,
+            })) => Some(self.with_schema(&schema)),
+            RefLookup::Resolved(None) | RefLookup::NeedsFetch { .. } => None,
+        }
+    }
+
+    /// This schema followed by every schema that applies to the same value:
+    /// `$ref` targets, allOf/anyOf/oneOf branches and then/else. Each schema
+    /// is listed once, which also cuts `$ref` cycles.
+    pub fn branches(&self, registry: &SchemaRegistry) -> Vec<ScopedSchema> {
+        let mut out = Vec::new();
+        let mut seen = HashSet::new();
+        self.collect_branches(registry, &mut out, &mut seen);
+        out
+    }
+
+    fn collect_branches(
+        &self,
+        registry: &SchemaRegistry,
+        out: &mut Vec<ScopedSchema>,
+        seen: &mut HashSet<*const JsonSchema>,
+    ) {
+        if !seen.insert(Arc::as_ptr(&self.schema)) {
+            return;
+        }
+        out.push(self.clone());
+        if let Some(target) = self.deref(registry) {
+            target.collect_branches(registry, out, seen);
+        }
+        let schema = &self.schema;
+        for sub in schema
             .all_of
             .iter()
-            .chain(self.any_of.iter())
-            .chain(self.one_of.iter())
+            .chain(schema.any_of.iter())
+            .chain(schema.one_of.iter())
+            .chain(schema.then_schema.iter())
+            .chain(schema.else_schema.iter())
         {
-            if let Some(result) = sub.resolve_path_segment(seg) {
-                return Some(result);
-            }
+            self.with_schema(sub).collect_branches(registry, out, seen);
         }
+    }
 
-        // if/then/else
-        if let Some(ref then_schema) = self.then_schema
-            && let Some(result) = then_schema.resolve_path_segment(seg)
-        {
-            return Some(result);
+    /// Resolve a single path segment (property name or array index) against
+    /// this schema and all of its branches, dereferencing `$ref`s on the way.
+    /// Used by both completion and hover to navigate from root schema to
+    /// cursor position.
+    pub fn resolve_path_segment(&self, seg: &str, registry: &SchemaRegistry) -> Option<Self> {
+        fn schema(s: &Option<Box<SchemaOrBool>>) -> Option<&Arc<JsonSchema>> {
+            s.as_deref().and_then(SchemaOrBool::as_schema)
         }
-        if let Some(ref else_schema) = self.else_schema
-            && let Some(result) = else_schema.resolve_path_segment(seg)
-        {
-            return Some(result);
+        let branches = self.branches(registry);
+        // The first branch with a subschema for the segment, in keyword
+        // order: a tuple position in any branch beats another's `items`.
+        let find = |keyword: &dyn Fn(&JsonSchema) -> Option<&Arc<JsonSchema>>| {
+            branches
+                .iter()
+                .find_map(|branch| Some(branch.with_schema(keyword(&branch.schema)?)))
+        };
+
+        match seg.parse::<usize>() {
+            // An array index. `items` also holds the `additionalItems` of an
+            // `items` tuple. A property named by digits is taken as a last
+            // resort, but never `additionalProperties`.
+            Ok(idx) => find(&|s| s.prefix_items.get(idx))
+                .or_else(|| find(&|s| schema(&s.items)))
+                .or_else(|| find(&|s| schema(&s.unevaluated_items)))
+                .or_else(|| find(&|s| s.properties.get(seg))),
+            Err(_) => find(&|s| s.properties.get(seg)).or_else(|| {
+                find(&|s| schema(&s.additional_properties).or(schema(&s.unevaluated_properties)))
+            }),
         }
+    }
 
-        // Additional properties.
-        if let Some(ref ap) = self.additional_properties
-            && let Some(s) = ap.as_schema()
-        {
-            return Some(s.clone());
+    /// Resolve a whole path of segments from this schema.
+    pub fn resolve_path(&self, path: &[String], registry: &SchemaRegistry) -> Option<Self> {
+        let mut current = self.clone();
+        for seg in path {
+            current = current.resolve_path_segment(seg, registry)?;
         }
-
-        None
+        Some(current)
     }
 }
 
@@ -282,6 +392,113 @@ impl JsonSchema {
             _ => Arc::new(JsonSchema::default()),
         }
     }
+
+    /// Iterate the direct subschemas in keyword positions, in no particular
+    /// order. Schemas compiled from unrecognised keywords (`extra`) are not
+    /// included.
+    pub fn subschemas(&self) -> impl Iterator<Item = &Arc<JsonSchema>> {
+        fn boxed(s: &Option<Box<SchemaOrBool>>) -> Option<&Arc<JsonSchema>> {
+            s.as_deref().and_then(SchemaOrBool::as_schema)
+        }
+        self.properties
+            .values()
+            .chain(self.pattern_properties.iter().map(|(_, s)| s))
+            .chain(self.definitions.values())
+            .chain(self.defs.values())
+            .chain(self.dependent_schemas.values())
+            .chain(self.dependencies.values().filter_map(|d| match d {
+                Dependency::Schema(s) => Some(s),
+                Dependency::Properties(_) => None,
+            }))
+            .chain(&self.all_of)
+            .chain(&self.any_of)
+            .chain(&self.one_of)
+            .chain(&self.prefix_items)
+            .chain(boxed(&self.items))
+            .chain(boxed(&self.additional_items))
+            .chain(boxed(&self.additional_properties))
+            .chain(boxed(&self.unevaluated_properties))
+            .chain(boxed(&self.unevaluated_items))
+            .chain(&self.not)
+            .chain(&self.if_schema)
+            .chain(&self.then_schema)
+            .chain(&self.else_schema)
+            .chain(&self.contains)
+            .chain(&self.property_names)
+    }
+
+    /// Approximate memory footprint in bytes: the struct itself plus owned
+    /// strings, JSON values and subschemas. Used to budget the schema cache.
+    pub fn approx_size(&self) -> usize {
+        let mut seen = HashSet::new();
+        self.size_with(&mut seen)
+    }
+
+    /// `approx_size`, counting each subschema in `seen` only once: `items`
+    /// and `additionalItems` can share one.
+    fn size_with(&self, seen: &mut HashSet<*const JsonSchema>) -> usize {
+        fn json_size(v: &serde_json::Value) -> usize {
+            std::mem::size_of::<serde_json::Value>()
+                + match v {
+                    serde_json::Value::String(s) => s.len(),
+                    serde_json::Value::Array(a) => a.iter().map(json_size).sum(),
+                    serde_json::Value::Object(m) => {
+                        m.iter().map(|(k, v)| k.len() + json_size(v)).sum()
+                    }
+                    _ => 0,
+                }
+        }
+
+        let strings: usize = [
+            &self.id,
+            &self.schema_draft,
+            &self.title,
+            &self.description,
+            &self.markdown_description,
+            &self.deprecation_message,
+            &self.error_message,
+            &self.pattern_error_message,
+            &self.pattern,
+            &self.format,
+            &self.reference,
+            &self.anchor,
+            &self.dynamic_ref,
+            &self.dynamic_anchor,
+            &self.recursive_ref,
+        ]
+        .into_iter()
+        .flatten()
+        .map(String::len)
+        .sum();
+        let names: usize = self
+            .property_order
+            .iter()
+            .chain(&self.required)
+            .chain(&self.enum_descriptions)
+            .chain(&self.markdown_enum_descriptions)
+            .map(|s| 2 * s.len())
+            .sum();
+        let values: usize = self
+            .default
+            .iter()
+            .chain(&self.examples)
+            .chain(&self.enum_values)
+            .chain(&self.const_value)
+            .map(json_size)
+            .sum();
+        let children: usize = self
+            .subschemas()
+            .chain(self.extra.values())
+            .map(|s| {
+                if seen.insert(Arc::as_ptr(s)) {
+                    s.size_with(seen)
+                } else {
+                    0
+                }
+            })
+            .sum();
+        std::mem::size_of::<Self>() + strings + names + values + children
+    }
 }
 
 fn parse_schema_object(map: &serde_json::Map<String, serde_json::Value>) -> JsonSchema {
@@ -294,9 +511,17 @@ fn parse_schema_object(map: &serde_json::Map<String, serde_json::Value>) -> Json
     }
 
     // -- Metadata --
-    s.id = str_field(map, "$id")
-        .or_else(|| str_field(map, "id"))
-        .map(String::from);
+    // An `$id` fragment is either empty or, in older drafts, a plain-name
+    // anchor; keep the two apart so `id` is always a fragment-free URI.
+    if let Some(id) = str_field(map, "$id").or_else(|| str_field(map, "id")) {
+        let (uri, fragment) = id.split_once('#').unwrap_or((id, ""));
+        if !uri.is_empty() {
+            s.id = Some(uri.to_string());
+        }
+        if !fragment.is_empty() && !fragment.starts_with('/') {
+            s.anchor = Some(fragment.to_string());
+        }
+    }
     s.title = str_field(map, "title").map(String::from);
     s.description = str_field(map, "description").map(String::from);
     s.markdown_description = str_field(map, "markdownDescription").map(String::from);
@@ -370,15 +595,20 @@ fn parse_schema_object(map: &serde_json::Map<String, serde_json::Value>) -> Json
     s.format = str_field(map, "format").map(String::from);
 
     // -- array --
-    s.items = map.get("items").map(|v| Box::new(parse_schema_or_bool(v)));
-    s.prefix_items = map
-        .get("prefixItems")
-        .and_then(|v| v.as_array())
-        .map(|arr| arr.iter().map(JsonSchema::from_value).collect())
-        .unwrap_or_default();
     s.additional_items = map
         .get("additionalItems")
         .map(|v| Box::new(parse_schema_or_bool(v)));
+    match map.get("items") {
+        Some(serde_json::Value::Array(arr)) => {
+            s.items_array = true;
+            s.prefix_items = arr.iter().map(JsonSchema::from_value).collect();
+            s.items = s.additional_items.clone();
+        }
+        items => {
+            s.items = items.map(|v| Box::new(parse_schema_or_bool(v)));
+            s.prefix_items = schema_array_field(map, "prefixItems");
+        }
+    }
     s.min_items = map.get("minItems").and_then(|v| v.as_u64());
     s.max_items = map.get("maxItems").and_then(|v| v.as_u64());
     s.unique_items = map
@@ -388,17 +618,18 @@ fn parse_schema_object(map: &serde_json::Map<String, serde_json::Value>) -> Json
     s.contains = map.get("contains").map(JsonSchema::from_value);
     s.min_contains = map.get("minContains").and_then(|v| v.as_u64());
     s.max_contains = map.get("maxContains").and_then(|v| v.as_u64());
+    s.unevaluated_items = map
+        .get("unevaluatedItems")
+        .map(|v| Box::new(parse_schema_or_bool(v)));
 
     // -- object --
-    s.properties = map
-        .get("properties")
-        .and_then(|v| v.as_object())
-        .map(|obj| {
-            obj.iter()
-                .map(|(k, v)| (k.clone(), JsonSchema::from_value(v)))
-                .collect()
-        })
-        .unwrap_or_default();
+    if let Some(obj) = map.get("properties").and_then(|v| v.as_object()) {
+        s.properties = obj
+            .iter()
+            .map(|(k, v)| (k.clone(), JsonSchema::from_value(v)))
+            .collect();
+        s.property_order = obj.keys().cloned().collect();
+    }
     s.required = str_array_field(map, "required");
     s.additional_properties = map
         .get("additionalProperties")
@@ -413,6 +644,9 @@ fn parse_schema_object(map: &serde_json::Map<String, serde_json::Value>) -> Json
         })
         .unwrap_or_default();
     s.property_names = map.get("propertyNames").map(JsonSchema::from_value);
+    s.unevaluated_properties = map
+        .get("unevaluatedProperties")
+        .map(|v| Box::new(parse_schema_or_bool(v)));
     s.min_properties = map.get("minProperties").and_then(|v| v.as_u64());
     s.max_properties = map.get("maxProperties").and_then(|v| v.as_u64());
 
@@ -460,8 +694,18 @@ fn parse_schema_object(map: &serde_json::Map<String, serde_json::Value>) -> Json
     s.then_schema = map.get("then").map(JsonSchema::from_value);
     s.else_schema = map.get("else").map(JsonSchema::from_value);
 
-    // -- $ref --
+    // -- $ref / $anchor --
     s.reference = str_field(map, "$ref").map(String::from);
+    if let Some(anchor) = str_field(map, "$anchor") {
+        s.anchor = Some(anchor.to_string());
+    }
+    s.dynamic_ref = str_field(map, "$dynamicRef").map(String::from);
+    s.dynamic_anchor = str_field(map, "$dynamicAnchor").map(String::from);
+    s.recursive_ref = str_field(map, "$recursiveRef").map(String::from);
+    s.recursive_anchor = map
+        .get("$recursiveAnchor")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
 
     // -- definitions --
     s.definitions = schema_object_field(map, "definitions");
@@ -485,9 +729,98 @@ fn parse_schema_object(map: &serde_json::Map<String, serde_json::Value>) -> Json
                 .collect()
         })
         .unwrap_or_default();
+
+    // -- unrecognised keywords --
+    s.extra = map
+        .iter()
+        .filter(|(k, v)| v.is_object() && !KEYWORDS.contains(&k.as_str()))
+        .map(|(k, v)| (k.clone(), JsonSchema::from_value(v)))
+        .collect();
+
+    // -- anchors of this resource --
+    // Subschemas with their own `$id` start a new resource and keep their
+    // anchors to themselves.
+    let mut anchors = HashMap::new();
+    for sub in s.subschemas().filter(|sub| sub.id.is_none()) {
+        for anchor in sub.anchor.iter().chain(&sub.dynamic_anchor) {
+            anchors.insert(anchor.clone(), sub.clone());
+        }
+        anchors.extend(sub.anchors.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+    s.anchors = anchors;
     s
 }
 
+/// Every keyword `parse_schema_object` understands. Anything else that holds
+/// an object ends up in `JsonSchema::extra`.
+const KEYWORDS: &[&str] = &[
+    "$schema",
+    "$id",
+    "id",
+    "$ref",
+    "$anchor",
+    "$dynamicRef",
+    "$dynamicAnchor",
+    "$recursiveRef",
+    "$recursiveAnchor",
+    "$comment",
+    "title",
+    "description",
+    "markdownDescription",
+    "default",
+    "examples",
+    "deprecated",
+    "deprecationMessage",
+    "errorMessage",
+    "patternErrorMessage",
+    "doNotSuggest",
+    "enumDescriptions",
+    "markdownEnumDescriptions",
+    "type",
+    "enum",
+    "const",
+    "minimum",
+    "maximum",
+    "exclusiveMinimum",
+    "exclusiveMaximum",
+    "multipleOf",
+    "minLength",
+    "maxLength",
+    "pattern",
+    "format",
+    "items",
+    "prefixItems",
+    "additionalItems",
+    "minItems",
+    "maxItems",
+    "uniqueItems",
+    "contains",
+    "minContains",
+    "maxContains",
+    "unevaluatedItems",
+    "properties",
+    "required",
+    "additionalProperties",
+    "patternProperties",
+    "propertyNames",
+    "minProperties",
+    "maxProperties",
+    "dependencies",
+    "dependentRequired",
+    "dependentSchemas",
+    "unevaluatedProperties",
+    "allOf",
+    "anyOf",
+    "oneOf",
+    "not",
+    "if",
+    "then",
+    "else",
+    "definitions",
+    "$defs",
+    "defaultSnippets",
+];
+
 // -- Helpers --
 
 fn str_field<'a>(
@@ -542,6 +875,7 @@ fn parse_schema_or_bool(val: &serde_json::Value) -> SchemaOrBool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::schema::resolver::SchemaStore;
 
     #[test]
     fn draft_detection() {
@@ -610,6 +944,7 @@ mod tests {
         assert_eq!(schema.properties.len(), 2);
         assert!(schema.properties.contains_key("name"));
         assert!(schema.properties.contains_key("age"));
+        assert_eq!(schema.property_order, ["name", "age"]);
     }
 
     #[test]
@@ -794,6 +1129,47 @@ mod tests {
         assert_eq!(schema.reference.as_deref(), Some("#/definitions/Foo"));
     }
 
+    #[test]
+    fn parse_anchor_and_id_fragment() {
+        let val: serde_json::Value =
+            serde_json::from_str(r##"{"$id": "http://x.com/a.json#", "$anchor": "top"}"##).unwrap();
+        let schema = JsonSchema::from_value(&val);
+        assert_eq!(schema.id.as_deref(), Some("http://x.com/a.json"));
+        assert_eq!(schema.anchor.as_deref(), Some("top"));
+
+        let val: serde_json::Value = serde_json::from_str(r##"{"id": "#foo"}"##).unwrap();
+        let schema = JsonSchema::from_value(&val);
+        assert!(schema.id.is_none());
+        assert_eq!(schema.anchor.as_deref(), Some("foo"));
+    }
+
+    #[test]
+    fn anchors_collected_per_resource() {
+        let val: serde_json::Value = serde_json::from_str(
+            r##"{
+                "properties": {"a": {"items": {"$anchor": "deep"}}},
+                "$defs": {"b": {"$id": "b.json", "properties": {"c": {"$anchor": "inner"}}}}
+            }"##,
+        )
+        .unwrap();
+        let schema = JsonSchema::from_value(&val);
+        assert!(schema.anchors.contains_key("deep"));
+        assert!(!schema.anchors.contains_key("inner"));
+        assert!(schema.defs["b"].anchors.contains_key("inner"));
+    }
+
+    #[test]
+    fn parse_tuple_items() {
+        let val: serde_json::Value = serde_json::from_str(
+            r#"{"items": [{"type": "string"}, {"type": "number"}], "additionalItems": false}"#,
+        )
+        .unwrap();
+        let schema = JsonSchema::from_value(&val);
+        assert!(schema.items_array);
+        assert_eq!(schema.prefix_items.len(), 2);
+        assert!(schema.items.as_ref().unwrap().is_false());
+    }
+
     #[test]
     fn parse_default_snippets() {
         let val: serde_json::Value =
@@ -803,6 +1179,179 @@ mod tests {
         assert_eq!(schema.default_snippets.len(), 1);
         assert_eq!(schema.default_snippets[0].label.as_deref(), Some("empty"));
     }
+
+    fn path(segs: &[&str]) -> Vec<String> {
+        segs.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn path_follows_local_ref() {
+        let val: serde_json::Value = serde_json::from_str(
+            r##"{
+                "properties": {"server": {"$ref": "#/definitions/Server"}},
+                "definitions": {"Server": {"properties": {"port": {"type": "integer"}}}}
+            }"##,
+        )
+        .unwrap();
+        let schema = JsonSchema::from_value(&val);
+        let store = SchemaStore::new();
+        let port = ScopedSchema::new(&schema)
+            .resolve_path(&path(&["server", "port"]), store.registry())
+            .unwrap();
+        assert_eq!(port.schema.types, vec![SchemaType::Integer]);
+    }
+
+    #[test]
+    fn path_follows_ref_inside_all_of() {
+        let val: serde_json::Value = serde_json::from_str(
+            r##"{
+                "allOf": [{"$ref": "#/definitions/A"}],
+                "definitions": {"A": {"properties": {"items": {"items": {"type": "string"}}}}}
+            }"##,
+        )
+        .unwrap();
+        let schema = JsonSchema::from_value(&val);
+        let store = SchemaStore::new();
+        let item = ScopedSchema::new(&schema)
+            .resolve_path(&path(&["items", "0"]), store.registry())
+            .unwrap();
+        assert_eq!(item.schema.types, vec![SchemaType::String]);
+    }
+
+    #[test]
+    fn path_follows_cached_external_ref() {
+        let mut store = SchemaStore::new();
+        let other: serde_json::Value = serde_json::from_str(
+            r##"{
+                "definitions": {
+                    "Port": {"$ref": "#/definitions/Number"},
+                    "Number": {"type": "integer"}
+                }
+            }"##,
+        )
+        .unwrap();
+        store.insert_cache(
+            "https://example.com/common.json".into(),
+            JsonSchema::from_value(&other),
+        );
+        let val: serde_json::Value = serde_json::from_str(
+            r##"{
+                "$id": "https://example.com/main.json",
+                "properties": {"port": {"$ref": "common.json#/definitions/Port"}}
+            }"##,
+        )
+        .unwrap();
+        let schema = JsonSchema::from_value(&val);
+        let port = ScopedSchema::new(&schema)
+            .resolve_path(&path(&["port"]), store.registry())
+            .unwrap();
+        // The second hop is local to common.json, not main.json.
+        let branches = port.branches(store.registry());
+        assert_eq!(branches.len(), 3);
+        assert_eq!(branches[2].schema.types, vec![SchemaType::Integer]);
+        assert_eq!(&*branches[2].base_uri, "https://example.com/common.json");
+    }
+
+    #[test]
+    fn path_ref_cycle_terminates() {
+        let val: serde_json::Value = serde_json::from_str(
+            r##"{
+                "$ref": "#/definitions/A",
+                "definitions": {
+                    "A": {"$ref": "#/definitions/B"},
+                    "B": {"$ref": "#/definitions/A"}
+                }
+            }"##,
+        )
+        .unwrap();
+        let schema = JsonSchema::from_value(&val);
+        let store = SchemaStore::new();
+        let root = ScopedSchema::new(&schema);
+        assert_eq!(root.branches(store.registry()).len(), 3);
+        assert!(root.resolve_path(&path(&["x"]), store.registry()).is_none());
+    }
+
+    #[test]
+    fn path_recursive_ref() {
+        let val: serde_json::Value = serde_json::from_str(
+            r##"{
+                "properties": {
+                    "name": {"type": "string"},
+                    "children": {"type": "array", "items": {"$ref": "#"}}
+                }
+            }"##,
+        )
+        .unwrap();
+        let schema = JsonSchema::from_value(&val);
+        let store = SchemaStore::new();
+        let name = ScopedSchema::new(&schema)
+            .resolve_path(
+                &path(&["children", "0", "children", "1", "name"]),
+                store.registry(),
+            )
+            .unwrap();
+        assert_eq!(name.schema.types, vec![SchemaType::String]);
+    }
+
+    #[test]
+    fn path_index_uses_item_keywords_only() {
+        let val: serde_json::Value = serde_json::from_str(
+            r##"{
+                "anyOf": [
+                    {"items": {"type": "string"}},
+                    {"prefixItems": [{"type": "integer"}]},
+                    {"additionalProperties": {"type": "null"}}
+                ]
+            }"##,
+        )
+        .unwrap();
+        let schema = JsonSchema::from_value(&val);
+        let store = SchemaStore::new();
+        let root = ScopedSchema::new(&schema);
+        let types = |seg: &str| {
+            root.resolve_path_segment(seg, store.registry())
+                .map(|s| s.schema.types.clone())
+        };
+        // The second branch's tuple position beats the first's `items`.
+        assert_eq!(types("0"), Some(vec![SchemaType::Integer]));
+        assert_eq!(types("1"), Some(vec![SchemaType::String]));
+        assert_eq!(types("x"), Some(vec![SchemaType::Null]));
+
+        let val: serde_json::Value = serde_json::from_str(
+            r##"{
+                "additionalProperties": {"type": "null"},
+                "unevaluatedProperties": {"type": "null"},
+                "unevaluatedItems": {"type": "boolean"}
+            }"##,
+        )
+        .unwrap();
+        let schema = JsonSchema::from_value(&val);
+        let item = ScopedSchema::new(&schema)
+            .resolve_path_segment("0", store.registry())
+            .unwrap();
+        assert_eq!(item.schema.types, vec![SchemaType::Boolean]);
+
+        let val = serde_json::json!({"additionalProperties": {"type": "null"}});
+        let schema = JsonSchema::from_value(&val);
+        let root = ScopedSchema::new(&schema);
+        assert!(root.resolve_path_segment("0", store.registry()).is_none());
+    }
+
+    #[test]
+    fn approx_size_counts_shared_subschemas_once() {
+        let rest = serde_json::json!({"description": "x".repeat(1000)});
+        // The tuple form keeps `additionalItems` in both `items` and
+        // `additional_items`.
+        let tuple = JsonSchema::from_value(&serde_json::json!({
+            "items": [{}],
+            "additionalItems": rest,
+        }));
+        let prefix = JsonSchema::from_value(&serde_json::json!({
+            "prefixItems": [{}],
+            "items": rest,
+        }));
+        assert_eq!(tuple.approx_size(), prefix.approx_size());
+    }
 }
 
 fn parse_schema_type(s: &str) -> Option<SchemaType> {
@@ -1,33 +1,73 @@
 /// Schema validation engine: validates tree-sitter nodes against a compiled
 /// JsonSchema, producing validation errors with byte-offset locations.
 use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, RandomState};
 use std::sync::Arc;
 
+use parking_lot::Mutex;
 use regex::Regex;
 use tree_sitter::Node;
 
+const REGEX_CACHE_SHARDS: usize = 16;
+
 /// Server-wide regex cache to avoid recompiling the same pattern repeatedly
-/// across validation passes. Persists for the lifetime of the server.
+/// across validation passes. Persists for the lifetime of the server. The
+/// cache is split into independently locked shards so concurrent
+/// validations rarely contend; a `Regex` clone shares the compiled program.
+/// Values checked against the `regex` format are cached the same way.
 pub struct RegexCache {
-    cache: HashMap<String, Option<Regex>>,
+    shards: [Mutex<HashMap<String, Option<Regex>>>; REGEX_CACHE_SHARDS],
+    ecma: [Mutex<HashMap<String, bool>>; REGEX_CACHE_SHARDS],
+    hasher: RandomState,
 }
 
 impl RegexCache {
     pub fn new() -> Self {
         RegexCache {
-            cache: HashMap::new(),
+            shards: std::array::from_fn(|_| Mutex::new(HashMap::new())),
+            ecma: std::array::from_fn(|_| Mutex::new(HashMap::new())),
+            hasher: RandomState::new(),
+        }
+    }
+
+    pub fn get(&self, pattern: &str) -> Option<Regex> {
+        let shard = &self.shards[self.shard(pattern)];
+        if let Some(re) = shard.lock().get(pattern) {
+            return re.clone();
         }
+        // Compiling can be slow, so it happens without the lock. Two passes
+        // may both compile a new pattern; the result is the same.
+        let re = Regex::new(pattern).ok();
+        shard.lock().insert(pattern.to_string(), re.clone());
+        re
+    }
+
+    /// Whether `pattern` is a valid ECMA-262 regular expression.
+    pub fn is_ecma_regex(&self, pattern: &str) -> bool {
+        let shard = &self.ecma[self.shard(pattern)];
+        if let Some(&valid) = shard.lock().get(pattern) {
+            return valid;
+        }
+        let valid = format::is_ecma_regex(pattern);
+        shard.lock().insert(pattern.to_string(), valid);
+        valid
+    }
+
+    fn shard(&self, pattern: &str) -> usize {
+        self.hasher.hash_one(pattern) as usize % REGEX_CACHE_SHARDS
     }
+}
 
-    pub fn get(&mut self, pattern: &str) -> Option<&Regex> {
-        self.cache
-            .entry(pattern.to_string())
-            .or_insert_with(|| Regex::new(pattern).ok())
-            .as_ref()
+impl Default for RegexCache {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
+use super::format::{self, FormatMode};
+use super::resolver::{RefLookup, ResolvedRef, SchemaRegistry, resolve_uri};
 use super::types::*;
+use crate::cancel::{CancelToken, Cancelled};
 use crate::tree::{self, kinds};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -36,55 +76,461 @@ pub enum Severity {
     Warning,
 }
 
+/// Machine-readable reason for a validation error, published as the LSP
+/// diagnostic `code` so code actions can offer fixes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Deprecated,
+    TypeMismatch,
+    EnumMismatch,
+    ConstMismatch,
+    StringLength,
+    Pattern,
+    Format,
+    OutOfRange,
+    MultipleOf,
+    PropertyCount,
+    MissingProperty,
+    MissingDependency,
+    PropertyNotAllowed,
+    UnknownProperty,
+    ItemCount,
+    DuplicateItem,
+    ItemNotAllowed,
+    Contains,
+    NoMatch,
+    MultipleMatches,
+    MatchesNot,
+}
+
+impl ErrorCode {
+    const ALL: [ErrorCode; 21] = [
+        ErrorCode::Deprecated,
+        ErrorCode::TypeMismatch,
+        ErrorCode::EnumMismatch,
+        ErrorCode::ConstMismatch,
+        ErrorCode::StringLength,
+        ErrorCode::Pattern,
+        ErrorCode::Format,
+        ErrorCode::OutOfRange,
+        ErrorCode::MultipleOf,
+        ErrorCode::PropertyCount,
+        ErrorCode::MissingProperty,
+        ErrorCode::MissingDependency,
+        ErrorCode::PropertyNotAllowed,
+        ErrorCode::UnknownProperty,
+        ErrorCode::ItemCount,
+        ErrorCode::DuplicateItem,
+        ErrorCode::ItemNotAllowed,
+        ErrorCode::Contains,
+        ErrorCode::NoMatch,
+        ErrorCode::MultipleMatches,
+        ErrorCode::MatchesNot,
+    ];
+
+    /// The code for a published diagnostic code string.
+    pub fn parse(code: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.as_str() == code)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::Deprecated => "deprecated",
+            ErrorCode::TypeMismatch => "type-mismatch",
+            ErrorCode::EnumMismatch => "enum-mismatch",
+            ErrorCode::ConstMismatch => "const-mismatch",
+            ErrorCode::StringLength => "string-length",
+            ErrorCode::Pattern => "pattern",
+            ErrorCode::Format => "format",
+            ErrorCode::OutOfRange => "out-of-range",
+            ErrorCode::MultipleOf => "multiple-of",
+            ErrorCode::PropertyCount => "property-count",
+            ErrorCode::MissingProperty => "missing-property",
+            ErrorCode::MissingDependency => "missing-dependency",
+            ErrorCode::PropertyNotAllowed => "property-not-allowed",
+            ErrorCode::UnknownProperty => "unknown-property",
+            ErrorCode::ItemCount => "item-count",
+            ErrorCode::DuplicateItem => "duplicate-item",
+            ErrorCode::ItemNotAllowed => "item-not-allowed",
+            ErrorCode::Contains => "contains",
+            ErrorCode::NoMatch => "no-match",
+            ErrorCode::MultipleMatches => "multiple-matches",
+            ErrorCode::MatchesNot => "matches-not",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ValidationError {
     pub start_byte: usize,
     pub end_byte: usize,
     pub message: String,
     pub severity: Severity,
+    pub code: ErrorCode,
+    /// Extra detail for quick fixes, published as the diagnostic `data`:
+    /// `{"property", "value"}` for missing properties and `{"values"}` for
+    /// enum/const mismatches.
+    pub data: Option<serde_json::Value>,
+}
+
+/// User-configurable validation behaviour.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidationOptions {
+    pub format_mode: FormatMode,
+    /// Report trailing commas and comments (plain JSON rather than JSONC).
+    pub strict: bool,
+}
+
+/// State threaded through a single validation pass.
+struct Ctx<'a> {
+    source: &'a [u8],
+    registry: &'a SchemaRegistry,
+    regex_cache: &'a RegexCache,
+    options: ValidationOptions,
+    cancel: &'a CancelToken,
+    /// Root of the schema resource that relative `$ref`s resolve against,
+    /// and its base URI. Swapped when validation enters an embedded `$id`
+    /// or a ref crosses into another resource.
+    root: Arc<JsonSchema>,
+    base_uri: String,
+    /// Draft declared by the nearest resource that has a `$schema`.
+    draft: Option<SchemaDraft>,
+    /// Schema resources entered so far, outermost first, with their base
+    /// URIs. `$dynamicRef`/`$recursiveRef` can resolve into any of them.
+    dynamic_scope: Vec<(Arc<JsonSchema>, String)>,
+    /// `(node id, referencing schema)` pairs for the `$ref`s currently being
+    /// followed. Re-entering one on the same node would never terminate.
+    ref_stack: Vec<(usize, usize)>,
+    /// Id of the node currently validated by an in-place applicator
+    /// (`allOf`, `$ref`, ...). Unknown-property warnings for that node are
+    /// left to the outermost schema, which sees every branch.
+    in_place: Option<usize>,
+    /// Properties and items of the current node evaluated by the schema
+    /// being applied, for `unevaluatedProperties`/`unevaluatedItems`.
+    evaluated: Evaluated,
+    /// Whether a schema applied to the current node (possibly through
+    /// in-place applicators) has an `unevaluated*` keyword, i.e. whether
+    /// `evaluated` needs to be filled in at all.
+    annotate: bool,
+}
+
+/// Evaluated-location annotations for one node.
+#[derive(Default)]
+struct Evaluated {
+    properties: HashSet<String>,
+    /// Items below this index were evaluated (`prefixItems`, `items`).
+    items: usize,
+    /// Items matched by `contains`.
+    contained: HashSet<usize>,
+}
+
+impl Evaluated {
+    fn merge(&mut self, other: Evaluated) {
+        self.properties.extend(other.properties);
+        self.items = self.items.max(other.items);
+        self.contained.extend(other.contained);
+    }
 }
 
-/// Validate a tree-sitter node against a schema.
+/// Validate a tree-sitter node against a schema. `$ref`s are resolved
+/// against `schema` itself and, for other documents, against `registry`.
 pub fn validate<'a>(
     node: Node<'a>,
     source: &[u8],
     schema: &Arc<JsonSchema>,
-    regex_cache: &mut RegexCache,
+    registry: &SchemaRegistry,
+    regex_cache: &RegexCache,
+    options: ValidationOptions,
 ) -> Vec<ValidationError> {
+    validate_with_cancel(
+        node,
+        source,
+        schema,
+        registry,
+        regex_cache,
+        options,
+        &CancelToken::new(),
+    )
+    .unwrap_or_default()
+}
+
+/// Like [`validate`], but stops early once `cancel` is cancelled.
+pub fn validate_with_cancel<'a>(
+    node: Node<'a>,
+    source: &[u8],
+    schema: &Arc<JsonSchema>,
+    registry: &SchemaRegistry,
+    regex_cache: &RegexCache,
+    options: ValidationOptions,
+    cancel: &CancelToken,
+) -> Result<Vec<ValidationError>, Cancelled> {
     let mut errors = Vec::new();
-    let ref_chain: HashSet<String> = HashSet::new();
-    validate_node(node, source, schema, &mut errors, &ref_chain, regex_cache);
-    errors
+    let mut ctx = Ctx {
+        source,
+        registry,
+        regex_cache,
+        options,
+        cancel,
+        root: schema.clone(),
+        base_uri: schema.id.clone().unwrap_or_default(),
+        draft: schema.schema_draft.as_ref().map(|_| schema.draft),
+        dynamic_scope: vec![(schema.clone(), schema.id.clone().unwrap_or_default())],
+        ref_stack: Vec::new(),
+        in_place: None,
+        evaluated: Evaluated::default(),
+        annotate: false,
+    };
+    validate_node(node, schema, &mut errors, &mut ctx);
+    cancel.check()?;
+    Ok(errors)
 }
 
+/// Validate a node that is not the one the caller's schema applies to
+/// (a property value, an array item): evaluated locations start afresh.
 fn validate_node(
     node: Node<'_>,
-    source: &[u8],
     schema: &Arc<JsonSchema>,
     errors: &mut Vec<ValidationError>,
-    ref_chain: &HashSet<String>,
-    regex_cache: &mut RegexCache,
+    ctx: &mut Ctx<'_>,
+) {
+    // Checked per node: the result is discarded once cancelled.
+    if ctx.cancel.is_cancelled() {
+        return;
+    }
+    let outer_evaluated = std::mem::take(&mut ctx.evaluated);
+    let outer_annotate = std::mem::replace(&mut ctx.annotate, false);
+    evaluate(node, schema, errors, ctx);
+    ctx.evaluated = outer_evaluated;
+    ctx.annotate = outer_annotate;
+}
+
+fn evaluate(
+    node: Node<'_>,
+    schema: &Arc<JsonSchema>,
+    errors: &mut Vec<ValidationError>,
+    ctx: &mut Ctx<'_>,
+) {
+    let outer_annotate = ctx.annotate;
+    ctx.annotate |= schema.unevaluated_properties.is_some() || schema.unevaluated_items.is_some();
+    evaluate_resource(node, schema, errors, ctx);
+    ctx.annotate = outer_annotate;
+}
+
+fn evaluate_resource(
+    node: Node<'_>,
+    schema: &Arc<JsonSchema>,
+    errors: &mut Vec<ValidationError>,
+    ctx: &mut Ctx<'_>,
+) {
+    // A subschema with its own `$id` is an embedded resource: refs inside it
+    // resolve against its URI. Sibling `$id`s of a legacy `$ref` are ignored.
+    if let Some(ref id) = schema.id
+        && !Arc::ptr_eq(schema, &ctx.root)
+        && !(schema.reference.is_some() && ctx.ref_overrides_siblings())
+    {
+        let base_uri = resolve_uri(&ctx.base_uri, id);
+        ctx.enter_resource(schema.clone(), base_uri, |ctx| {
+            validate_node_at(node, schema, errors, ctx)
+        });
+        return;
+    }
+    validate_node_at(node, schema, errors, ctx);
+}
+
+fn validate_node_at(
+    node: Node<'_>,
+    schema: &Arc<JsonSchema>,
+    errors: &mut Vec<ValidationError>,
+    ctx: &mut Ctx<'_>,
 ) {
-    // Handle $ref: only clone the chain when we actually encounter a $ref.
-    if let Some(ref reference) = schema.reference {
-        if ref_chain.contains(reference) {
+    if schema.reference.is_some() {
+        if ctx.ref_overrides_siblings() {
+            with_ref(node, schema, RefKeyword::Ref, ctx, |target, ctx| {
+                evaluate(node, target, errors, ctx)
+            });
             return;
         }
-        let mut chain = ref_chain.clone();
-        chain.insert(reference.clone());
-        validate_node_inner(node, source, schema, errors, &chain, regex_cache);
-        return;
+        with_ref(node, schema, RefKeyword::Ref, ctx, |target, ctx| {
+            validate_in_place(node, target, errors, ctx)
+        });
+    }
+    for keyword in [RefKeyword::Dynamic, RefKeyword::Recursive] {
+        with_ref(node, schema, keyword, ctx, |target, ctx| {
+            validate_in_place(node, target, errors, ctx)
+        });
+    }
+    validate_node_inner(node, schema, errors, ctx);
+}
+
+/// Apply a schema to the same node its parent schema is validating. The
+/// locations it evaluates count for the parent only if it succeeds.
+fn validate_in_place(
+    node: Node<'_>,
+    schema: &Arc<JsonSchema>,
+    errors: &mut Vec<ValidationError>,
+    ctx: &mut Ctx<'_>,
+) {
+    let outer = ctx.in_place.replace(node.id());
+    let outer_evaluated = std::mem::take(&mut ctx.evaluated);
+    let start = errors.len();
+    evaluate(node, schema, errors, ctx);
+    let evaluated = std::mem::replace(&mut ctx.evaluated, outer_evaluated);
+    if ctx.annotate && !has_errors(&errors[start..]) {
+        ctx.evaluated.merge(evaluated);
+    }
+    ctx.in_place = outer;
+}
+
+/// The reference keywords a schema can carry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RefKeyword {
+    Ref,
+    Dynamic,
+    Recursive,
+}
+
+impl RefKeyword {
+    const ALL: [RefKeyword; 3] = [RefKeyword::Ref, RefKeyword::Dynamic, RefKeyword::Recursive];
+}
+
+/// Resolve one of `schema`'s reference keywords and run `f` on the target,
+/// with the target's schema resource active. Returns `None` without calling
+/// `f` when the schema lacks the keyword, the ref cannot be resolved, or it
+/// is already being followed for this node.
+fn with_ref<R>(
+    node: Node<'_>,
+    schema: &Arc<JsonSchema>,
+    keyword: RefKeyword,
+    ctx: &mut Ctx<'_>,
+    f: impl FnOnce(&Arc<JsonSchema>, &mut Ctx<'_>) -> R,
+) -> Option<R> {
+    let reference = match keyword {
+        RefKeyword::Ref => schema.reference.as_deref(),
+        RefKeyword::Dynamic => schema.dynamic_ref.as_deref(),
+        RefKeyword::Recursive => schema.recursive_ref.as_deref(),
+    }?;
+    let key = (node.id(), Arc::as_ptr(schema) as usize);
+    if ctx.ref_stack.contains(&key) {
+        return None;
+    }
+    let mut resolved = match ctx
+        .registry
+        .resolve_ref(reference, &ctx.root, &ctx.base_uri)
+    {
+        RefLookup::Resolved(Some(resolved)) => resolved,
+        RefLookup::Resolved(None) => {
+            tracing::debug!("unresolvable $ref '{}'", reference);
+            return None;
+        }
+        RefLookup::NeedsFetch { uri, .. } => {
+            tracing::debug!("$ref '{}' needs {} which is not cached", reference, uri);
+            return None;
+        }
+    };
+    match keyword {
+        RefKeyword::Ref => {}
+        // A `$dynamicRef` whose static target is a matching `$dynamicAnchor`
+        // resolves to the outermost resource in scope declaring that anchor.
+        RefKeyword::Dynamic => {
+            if let Some((_, name)) = reference.split_once('#')
+                && resolved.schema.dynamic_anchor.as_deref() == Some(name)
+                && let Some(dynamic) = ctx.dynamic_scope.iter().find_map(|(root, base_uri)| {
+                    let target = if root.dynamic_anchor.as_deref() == Some(name) {
+                        root
+                    } else {
+                        root.anchors
+                            .get(name)
+                            .filter(|s| s.dynamic_anchor.as_deref() == Some(name))?
+                    };
+                    Some(ResolvedRef {
+                        schema: target.clone(),
+                        resource: Some((root.clone(), base_uri.clone())),
+                    })
+                })
+            {
+                resolved = dynamic;
+            }
+        }
+        // A `$recursiveRef` whose static target has `$recursiveAnchor: true`
+        // resolves to the outermost resource in scope that has one too.
+        RefKeyword::Recursive => {
+            if resolved.schema.recursive_anchor
+                && let Some((root, base_uri)) = ctx
+                    .dynamic_scope
+                    .iter()
+                    .find(|(root, _)| root.recursive_anchor)
+            {
+                resolved = ResolvedRef {
+                    schema: root.clone(),
+                    resource: Some((root.clone(), base_uri.clone())),
+                };
+            }
+        }
+    }
+
+    ctx.ref_stack.push(key);
+    let result = match resolved.resource {
+        Some((root, base_uri)) => {
+            ctx.enter_resource(root, base_uri, |ctx| f(&resolved.schema, ctx))
+        }
+        None => f(&resolved.schema, ctx),
+    };
+    ctx.ref_stack.pop();
+    Some(result)
+}
+
+impl Ctx<'_> {
+    /// Whether `format` failures are errors rather than annotations.
+    fn format_asserts(&self) -> bool {
+        match self.options.format_mode {
+            FormatMode::Assertion => true,
+            FormatMode::Annotation => false,
+            FormatMode::Auto => matches!(
+                self.draft,
+                Some(SchemaDraft::Draft4 | SchemaDraft::Draft6 | SchemaDraft::Draft7)
+            ),
+        }
+    }
+
+    /// Before 2019-09, `$ref` overrides its sibling keywords. Only honour
+    /// that when the schema explicitly declares such a draft; undeclared
+    /// schemas in the wild routinely rely on siblings being applied.
+    fn ref_overrides_siblings(&self) -> bool {
+        matches!(
+            self.draft,
+            Some(SchemaDraft::Draft4 | SchemaDraft::Draft6 | SchemaDraft::Draft7)
+        )
+    }
+
+    /// Run `f` with another schema resource active.
+    fn enter_resource<R>(
+        &mut self,
+        root: Arc<JsonSchema>,
+        base_uri: String,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R {
+        let draft = match root.schema_draft {
+            Some(_) => Some(root.draft),
+            None => self.draft,
+        };
+        self.dynamic_scope.push((root.clone(), base_uri.clone()));
+        let outer_root = std::mem::replace(&mut self.root, root);
+        let outer_base = std::mem::replace(&mut self.base_uri, base_uri);
+        let outer_draft = std::mem::replace(&mut self.draft, draft);
+        let result = f(self);
+        self.root = outer_root;
+        self.base_uri = outer_base;
+        self.draft = outer_draft;
+        self.dynamic_scope.pop();
+        result
     }
-    validate_node_inner(node, source, schema, errors, ref_chain, regex_cache);
 }
 
 fn validate_node_inner(
     node: Node<'_>,
-    source: &[u8],
     schema: &Arc<JsonSchema>,
     errors: &mut Vec<ValidationError>,
-    ref_chain: &HashSet<String>,
-    regex_cache: &mut RegexCache,
+    ctx: &mut Ctx<'_>,
 ) {
     // Deprecation warning.
     if schema.deprecated {
@@ -97,12 +543,14 @@ fn validate_node_inner(
             end_byte: node.end_byte(),
             message: msg.to_string(),
             severity: Severity::Warning,
+            code: ErrorCode::Deprecated,
+            data: None,
         });
     }
 
     // -- type --
     if !schema.types.is_empty() {
-        let node_type = node_to_schema_type(node, source);
+        let node_type = node_to_schema_type(node, ctx.source);
         // Integer is a subtype of Number in JSON Schema, so accept integers
         // when the schema expects "number".
         let matches = schema.types.iter().any(|t| {
@@ -112,6 +560,7 @@ fn validate_node_inner(
             let expected: Vec<&str> = schema.types.iter().map(|t| t.as_str()).collect();
             errors.push(err(
                 node,
+                ErrorCode::TypeMismatch,
                 format!("Incorrect type. Expected {}.", expected.join(" | ")),
             ));
             return;
@@ -119,44 +568,61 @@ fn validate_node_inner(
     }
 
     // -- enum --
-    if !schema.enum_values.is_empty() {
-        if !schema
+    if !schema.enum_values.is_empty()
+        && !schema
             .enum_values
             .iter()
-            .any(|e| node_matches_json_value(node, source, e))
-        {
-            let allowed: Vec<String> = schema.enum_values.iter().map(|v| format!("{v}")).collect();
-            errors.push(err(
+            .any(|e| node_matches_json_value(node, ctx.source, e))
+    {
+        let allowed: Vec<String> = schema.enum_values.iter().map(|v| format!("{v}")).collect();
+        errors.push(ValidationError {
+            data: Some(serde_json::json!({ "values": schema.enum_values })),
+            ..err(
                 node,
+                ErrorCode::EnumMismatch,
                 format!(
                     "Value is not accepted. Valid values: {}.",
                     allowed.join(", ")
                 ),
-            ));
-        }
+            )
+        });
     }
 
     // -- const --
-    if let Some(ref const_val) = schema.const_value {
-        if !node_matches_json_value(node, source, const_val) {
-            errors.push(err(node, format!("Value must be {const_val}.")));
-        }
+    if let Some(ref const_val) = schema.const_value
+        && !node_matches_json_value(node, ctx.source, const_val)
+    {
+        errors.push(ValidationError {
+            data: Some(serde_json::json!({ "values": [const_val] })),
+            ..err(
+                node,
+                ErrorCode::ConstMismatch,
+                format!("Value must be {const_val}."),
+            )
+        });
     }
 
     // -- Kind-specific --
     match node.kind() {
-        kinds::STRING => validate_string(node, source, schema, errors, regex_cache),
-        kinds::NUMBER => validate_number(node, source, schema, errors),
-        kinds::OBJECT => validate_object(node, source, schema, errors, ref_chain, regex_cache),
-        kinds::ARRAY => validate_array(node, source, schema, errors, ref_chain, regex_cache),
+        kinds::STRING => validate_string(node, schema, errors, ctx),
+        kinds::NUMBER => validate_number(node, ctx.source, schema, errors),
+        kinds::OBJECT => validate_object(node, schema, errors, ctx),
+        kinds::ARRAY => validate_array(node, schema, errors, ctx),
         _ => {}
     }
 
     // -- Composition --
-    validate_composition(node, source, schema, errors, ref_chain, regex_cache);
+    validate_composition(node, schema, errors, ctx);
 
     // -- Conditional --
-    validate_conditional(node, source, schema, errors, ref_chain, regex_cache);
+    validate_conditional(node, schema, errors, ctx);
+
+    // -- Unevaluated -- (after every in-place applicator has annotated)
+    match node.kind() {
+        kinds::OBJECT => validate_unevaluated_properties(node, schema, errors, ctx),
+        kinds::ARRAY => validate_unevaluated_items(node, schema, errors, ctx),
+        _ => {}
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -165,12 +631,11 @@ fn validate_node_inner(
 
 fn validate_string(
     node: Node<'_>,
-    source: &[u8],
     schema: &JsonSchema,
     errors: &mut Vec<ValidationError>,
-    regex_cache: &mut RegexCache,
+    ctx: &mut Ctx<'_>,
 ) {
-    let val = match tree::string_value(node, source) {
+    let val = match tree::string_value(node, ctx.source) {
         Some(s) => s,
         None => return,
     };
@@ -180,6 +645,7 @@ fn validate_string(
     {
         errors.push(err(
             node,
+            ErrorCode::StringLength,
             format!("String is shorter than the minimum length of {min}."),
         ));
     }
@@ -188,108 +654,40 @@ fn validate_string(
     {
         errors.push(err(
             node,
+            ErrorCode::StringLength,
             format!("String is longer than the maximum length of {max}."),
         ));
     }
     if let Some(ref pat) = schema.pattern
-        && let Some(re) = regex_cache.get(pat)
+        && let Some(re) = ctx.regex_cache.get(pat)
         && !re.is_match(&val)
     {
         let msg = schema
             .pattern_error_message
             .clone()
             .unwrap_or_else(|| format!("String does not match the pattern '{pat}'."));
-        errors.push(err(node, msg));
-    }
-    if let Some(ref fmt) = schema.format
-        && !validate_format(&val, fmt)
-    {
-        errors.push(ValidationError {
-            start_byte: node.start_byte(),
-            end_byte: node.end_byte(),
-            message: format!("String is not a valid '{fmt}'."),
-            severity: Severity::Warning,
-        });
+        errors.push(err(node, ErrorCode::Pattern, msg));
     }
-}
-
-fn validate_format(val: &str, format: &str) -> bool {
-    match format {
-        "date-time" => {
-            // RFC 3339: YYYY-MM-DDTHH:MM:SS with optional fractional seconds and timezone.
-            val.len() >= 19
-                && val.as_bytes().get(4) == Some(&b'-')
-                && val.as_bytes().get(7) == Some(&b'-')
-                && (val.as_bytes().get(10) == Some(&b'T') || val.as_bytes().get(10) == Some(&b't'))
-                && val.as_bytes().get(13) == Some(&b':')
-        }
-        "date" => {
-            // YYYY-MM-DD
-            val.len() == 10
-                && val.as_bytes().get(4) == Some(&b'-')
-                && val.as_bytes().get(7) == Some(&b'-')
-                && val[..4].bytes().all(|b| b.is_ascii_digit())
-                && val[5..7].bytes().all(|b| b.is_ascii_digit())
-                && val[8..10].bytes().all(|b| b.is_ascii_digit())
-        }
-        "time" => {
-            // HH:MM:SS with optional fractional seconds and timezone.
-            val.len() >= 8
-                && val.as_bytes().get(2) == Some(&b':')
-                && val.as_bytes().get(5) == Some(&b':')
-                && val[..2].bytes().all(|b| b.is_ascii_digit())
-        }
-        "email" => {
-            let parts: Vec<&str> = val.splitn(2, '@').collect();
-            parts.len() == 2
-                && !parts[0].is_empty()
-                && parts[1].contains('.')
-                && !parts[1].starts_with('.')
-                && !parts[1].ends_with('.')
-        }
-        "hostname" => {
-            !val.is_empty()
-                && val.len() <= 253
-                && val.split('.').all(|label| {
-                    !label.is_empty()
-                        && label.len() <= 63
-                        && label
-                            .bytes()
-                            .all(|b| b.is_ascii_alphanumeric() || b == b'-')
-                        && !label.starts_with('-')
-                        && !label.ends_with('-')
-                })
-        }
-        "ipv4" => {
-            let parts: Vec<&str> = val.split('.').collect();
-            parts.len() == 4
-                && parts.iter().all(|p| {
-                    !p.is_empty()
-                        && (p.len() == 1 || !p.starts_with('0'))
-                        && p.parse::<u16>().is_ok_and(|n| n <= 255)
-                })
-        }
-        "ipv6" => {
-            // Basic check: contains colons, right number of groups or uses :: shorthand.
-            val.contains(':')
-                && val.len() >= 2
-                && val
-                    .bytes()
-                    .all(|b| b.is_ascii_hexdigit() || b == b':' || b == b'.')
-        }
-        "uri" => val.contains("://") || val.starts_with("urn:"),
-        "uri-reference" => {
-            val.contains("://")
-                || val.starts_with('/')
-                || val.starts_with('#')
-                || val.starts_with("urn:")
-        }
-        "color-hex" => {
-            val.starts_with('#')
-                && matches!(val.len(), 4 | 5 | 7 | 9)
-                && val[1..].bytes().all(|b| b.is_ascii_hexdigit())
+    if let Some(ref fmt) = schema.format {
+        // Checking a `regex` value compiles it, so the result is cached.
+        let valid = match fmt.as_str() {
+            "regex" => ctx.regex_cache.is_ecma_regex(&val),
+            _ => format::is_valid(fmt, &val),
+        };
+        if !valid {
+            errors.push(ValidationError {
+                start_byte: node.start_byte(),
+                end_byte: node.end_byte(),
+                message: format!("String is not a valid '{fmt}'."),
+                severity: if ctx.format_asserts() {
+                    Severity::Error
+                } else {
+                    Severity::Warning
+                },
+                code: ErrorCode::Format,
+                data: None,
+            });
         }
-        _ => true,
     }
 }
 
@@ -316,31 +714,55 @@ fn validate_number(
         let exclusive = matches!(schema.exclusive_minimum, Some(ExclusiveLimit::Bool(true)));
         if exclusive {
             if val <= min {
-                errors.push(err(node, format!("Value must be greater than {min}.")));
+                errors.push(err(
+                    node,
+                    ErrorCode::OutOfRange,
+                    format!("Value must be greater than {min}."),
+                ));
             }
         } else if val < min {
-            errors.push(err(node, format!("Value is below the minimum of {min}.")));
+            errors.push(err(
+                node,
+                ErrorCode::OutOfRange,
+                format!("Value is below the minimum of {min}."),
+            ));
         }
     }
     if let Some(max) = schema.maximum {
         let exclusive = matches!(schema.exclusive_maximum, Some(ExclusiveLimit::Bool(true)));
         if exclusive {
             if val >= max {
-                errors.push(err(node, format!("Value must be less than {max}.")));
+                errors.push(err(
+                    node,
+                    ErrorCode::OutOfRange,
+                    format!("Value must be less than {max}."),
+                ));
             }
         } else if val > max {
-            errors.push(err(node, format!("Value is above the maximum of {max}.")));
+            errors.push(err(
+                node,
+                ErrorCode::OutOfRange,
+                format!("Value is above the maximum of {max}."),
+            ));
         }
     }
     if let Some(ExclusiveLimit::Number(exc_min)) = schema.exclusive_minimum
         && val <= exc_min
     {
-        errors.push(err(node, format!("Value must be greater than {exc_min}.")));
+        errors.push(err(
+            node,
+            ErrorCode::OutOfRange,
+            format!("Value must be greater than {exc_min}."),
+        ));
     }
     if let Some(ExclusiveLimit::Number(exc_max)) = schema.exclusive_maximum
         && val >= exc_max
     {
-        errors.push(err(node, format!("Value must be less than {exc_max}.")));
+        errors.push(err(
+            node,
+            ErrorCode::OutOfRange,
+            format!("Value must be less than {exc_max}."),
+        ));
     }
     // multipleOf: use round-trip multiply instead of fract() to avoid
     // floating-point issues (e.g. 0.3 / 0.1 != exactly 3.0).
@@ -349,80 +771,98 @@ fn validate_number(
     {
         let remainder = (val / mult).round() * mult;
         if (val - remainder).abs() > 1e-10 {
-            errors.push(err(node, format!("Value is not a multiple of {mult}.")));
+            errors.push(err(
+                node,
+                ErrorCode::MultipleOf,
+                format!("Value is not a multiple of {mult}."),
+            ));
         }
     }
     if schema.types.contains(&SchemaType::Integer)
         && !schema.types.contains(&SchemaType::Number)
         && val.fract() != 0.0
     {
-        errors.push(err(node, "Value must be an integer.".into()));
+        errors.push(err(
+            node,
+            ErrorCode::TypeMismatch,
+            "Value must be an integer.".into(),
+        ));
     }
 }
 
 /// Check whether a property name is known anywhere in the schema tree,
-/// including composition sub-schemas (allOf/anyOf/oneOf) and conditionals.
-fn schema_knows_property(schema: &JsonSchema, key: &str, regex_cache: &mut RegexCache) -> bool {
+/// including composition sub-schemas (allOf/anyOf/oneOf), conditionals and
+/// `$ref` targets.
+fn schema_knows_property(
+    node: Node<'_>,
+    schema: &Arc<JsonSchema>,
+    key: &str,
+    ctx: &mut Ctx<'_>,
+) -> bool {
     if schema.properties.contains_key(key) {
         return true;
     }
     for (pattern, _) in &schema.pattern_properties {
-        if let Some(re) = regex_cache.get(pattern) {
-            if re.is_match(key) {
-                return true;
-            }
+        if let Some(re) = ctx.regex_cache.get(pattern)
+            && re.is_match(key)
+        {
+            return true;
         }
     }
-    if schema.additional_properties.is_some() {
+    if schema.additional_properties.is_some() || schema.unevaluated_properties.is_some() {
         return true;
     }
+    for keyword in RefKeyword::ALL {
+        if with_ref(node, schema, keyword, ctx, |target, ctx| {
+            schema_knows_property(node, target, key, ctx)
+        }) == Some(true)
+        {
+            return true;
+        }
+    }
     for sub in schema
         .all_of
         .iter()
         .chain(schema.any_of.iter())
         .chain(schema.one_of.iter())
+        .chain(schema.then_schema.iter())
+        .chain(schema.else_schema.iter())
     {
-        if schema_knows_property(sub, key, regex_cache) {
-            return true;
-        }
-    }
-    if let Some(ref then_schema) = schema.then_schema {
-        if schema_knows_property(then_schema, key, regex_cache) {
-            return true;
-        }
-    }
-    if let Some(ref else_schema) = schema.else_schema {
-        if schema_knows_property(else_schema, key, regex_cache) {
+        if schema_knows_property(node, sub, key, ctx) {
             return true;
         }
     }
     false
 }
 
-/// Check whether a schema (or any of its composition sub-schemas) defines
-/// any known properties, meaning we have enough information to warn about
-/// unknown ones.
-fn schema_has_known_properties(schema: &JsonSchema) -> bool {
+/// Check whether a schema (or any of its composition sub-schemas or `$ref`
+/// targets) defines any known properties, meaning we have enough
+/// information to warn about unknown ones.
+fn schema_has_known_properties(
+    node: Node<'_>,
+    schema: &Arc<JsonSchema>,
+    ctx: &mut Ctx<'_>,
+) -> bool {
     if !schema.properties.is_empty() || !schema.pattern_properties.is_empty() {
         return true;
     }
+    for keyword in RefKeyword::ALL {
+        if with_ref(node, schema, keyword, ctx, |target, ctx| {
+            schema_has_known_properties(node, target, ctx)
+        }) == Some(true)
+        {
+            return true;
+        }
+    }
     for sub in schema
         .all_of
         .iter()
         .chain(schema.any_of.iter())
         .chain(schema.one_of.iter())
+        .chain(schema.then_schema.iter())
+        .chain(schema.else_schema.iter())
     {
-        if schema_has_known_properties(sub) {
-            return true;
-        }
-    }
-    if let Some(ref then_schema) = schema.then_schema {
-        if schema_has_known_properties(then_schema) {
-            return true;
-        }
-    }
-    if let Some(ref else_schema) = schema.else_schema {
-        if schema_has_known_properties(else_schema) {
+        if schema_has_known_properties(node, sub, ctx) {
             return true;
         }
     }
@@ -435,12 +875,11 @@ fn schema_has_known_properties(schema: &JsonSchema) -> bool {
 
 fn validate_object(
     node: Node<'_>,
-    source: &[u8],
-    schema: &JsonSchema,
+    schema: &Arc<JsonSchema>,
     errors: &mut Vec<ValidationError>,
-    ref_chain: &HashSet<String>,
-    regex_cache: &mut RegexCache,
+    ctx: &mut Ctx<'_>,
 ) {
+    let source = ctx.source;
     let mut cursor = node.walk();
     let pairs = tree::object_pairs(node, &mut cursor);
     let prop_count = pairs.len() as u64;
@@ -450,13 +889,18 @@ fn validate_object(
     {
         errors.push(err(
             node,
+            ErrorCode::PropertyCount,
             format!("Object has fewer than {min} properties."),
         ));
     }
     if let Some(max) = schema.max_properties
         && prop_count > max
     {
-        errors.push(err(node, format!("Object has more than {max} properties.")));
+        errors.push(err(
+            node,
+            ErrorCode::PropertyCount,
+            format!("Object has more than {max} properties."),
+        ));
     }
 
     // Collect present keys.
@@ -468,7 +912,14 @@ fn validate_object(
     // Required.
     for req in &schema.required {
         if !present_keys.contains(req) {
-            errors.push(err(node, format!("Missing property \"{req}\".")));
+            errors.push(ValidationError {
+                data: Some(missing_property_data(req, schema, ctx)),
+                ..err(
+                    node,
+                    ErrorCode::MissingProperty,
+                    format!("Missing property \"{req}\"."),
+                )
+            });
         }
     }
 
@@ -489,60 +940,43 @@ fn validate_object(
 
         // Property names schema.
         if let Some(ref pn_schema) = schema.property_names {
-            validate_node(key_node, source, pn_schema, errors, ref_chain, regex_cache);
+            validate_node(key_node, pn_schema, errors, ctx);
         }
 
         let mut matched = false;
 
         // Named property.
         if let Some(prop_schema) = schema.properties.get(&key_str) {
-            validate_node(
-                value_node,
-                source,
-                prop_schema,
-                errors,
-                ref_chain,
-                regex_cache,
-            );
+            validate_node(value_node, prop_schema, errors, ctx);
             matched = true;
         }
 
         // Pattern properties.
         for (pattern, pat_schema) in &schema.pattern_properties {
-            if let Some(re) = regex_cache.get(pattern)
+            if let Some(re) = ctx.regex_cache.get(pattern)
                 && re.is_match(&key_str)
             {
-                validate_node(
-                    value_node,
-                    source,
-                    pat_schema,
-                    errors,
-                    ref_chain,
-                    regex_cache,
-                );
+                validate_node(value_node, pat_schema, errors, ctx);
                 matched = true;
             }
         }
 
         // Additional properties.
-        if !matched {
-            if let Some(ref ap) = schema.additional_properties {
-                if ap.is_false() {
-                    errors.push(err(
-                        key_node,
-                        format!("Property \"{key_str}\" is not allowed."),
-                    ));
-                } else if let Some(ap_schema) = ap.as_schema() {
-                    validate_node(
-                        value_node,
-                        source,
-                        ap_schema,
-                        errors,
-                        ref_chain,
-                        regex_cache,
-                    );
-                }
+        if !matched && let Some(ref ap) = schema.additional_properties {
+            if ap.is_false() {
+                errors.push(err(
+                    key_node,
+                    ErrorCode::PropertyNotAllowed,
+                    format!("Property \"{key_str}\" is not allowed."),
+                ));
+            } else if let Some(ap_schema) = ap.as_schema() {
+                validate_node(value_node, ap_schema, errors, ctx);
             }
+            matched = true;
+        }
+
+        if matched && ctx.annotate {
+            ctx.evaluated.properties.insert(key_str);
         }
     }
 
@@ -553,15 +987,19 @@ fn validate_object(
                 Dependency::Properties(required) => {
                     for req in required {
                         if !present_keys.contains(req) {
-                            errors.push(err(
-                                node,
-                                format!("Property \"{dep_key}\" requires \"{req}\"."),
-                            ));
+                            errors.push(ValidationError {
+                                data: Some(missing_property_data(req, schema, ctx)),
+                                ..err(
+                                    node,
+                                    ErrorCode::MissingDependency,
+                                    format!("Property \"{dep_key}\" requires \"{req}\"."),
+                                )
+                            });
                         }
                     }
                 }
                 Dependency::Schema(dep_schema) => {
-                    validate_node(node, source, dep_schema, errors, ref_chain, regex_cache);
+                    validate_in_place(node, dep_schema, errors, ctx);
                 }
             }
         }
@@ -570,24 +1008,33 @@ fn validate_object(
         if present_keys.contains(dep_key) {
             for req in required {
                 if !present_keys.contains(req) {
-                    errors.push(err(
-                        node,
-                        format!("Property \"{dep_key}\" requires \"{req}\"."),
-                    ));
+                    errors.push(ValidationError {
+                        data: Some(missing_property_data(req, schema, ctx)),
+                        ..err(
+                            node,
+                            ErrorCode::MissingDependency,
+                            format!("Property \"{dep_key}\" requires \"{req}\"."),
+                        )
+                    });
                 }
             }
         }
     }
     for (dep_key, dep_schema) in &schema.dependent_schemas {
         if present_keys.contains(dep_key) {
-            validate_node(node, source, dep_schema, errors, ref_chain, regex_cache);
+            validate_in_place(node, dep_schema, errors, ctx);
         }
     }
 
     // Warn about unknown properties when the schema declares known properties
     // but does not have an additionalProperties constraint (which is already
-    // handled above). This replaces the old separate-pass warn_unknown_properties.
-    if schema.additional_properties.is_none() && schema_has_known_properties(schema) {
+    // handled above). Skipped for schemas applied in place: the outermost
+    // schema for this node already accounts for every branch.
+    if ctx.in_place != Some(node.id())
+        && schema.additional_properties.is_none()
+        && schema.unevaluated_properties.is_none()
+        && schema_has_known_properties(node, schema, ctx)
+    {
         for pair in &pairs {
             let key_node = match pair.child_by_field_name("key") {
                 Some(k) => k,
@@ -597,12 +1044,14 @@ fn validate_object(
                 Some(k) => k,
                 None => continue,
             };
-            if !schema_knows_property(schema, &key_str, regex_cache) {
+            if !schema_knows_property(node, schema, &key_str, ctx) {
                 errors.push(ValidationError {
                     start_byte: key_node.start_byte(),
                     end_byte: key_node.end_byte(),
                     message: format!("Unknown property \"{key_str}\"."),
                     severity: Severity::Warning,
+                    code: ErrorCode::UnknownProperty,
+                    data: None,
                 });
             }
         }
@@ -615,12 +1064,11 @@ fn validate_object(
 
 fn validate_array(
     node: Node<'_>,
-    source: &[u8],
     schema: &JsonSchema,
     errors: &mut Vec<ValidationError>,
-    ref_chain: &HashSet<String>,
-    regex_cache: &mut RegexCache,
+    ctx: &mut Ctx<'_>,
 ) {
+    let source = ctx.source;
     let mut cursor = node.walk();
     let items = tree::array_items(node, &mut cursor);
     let count = items.len() as u64;
@@ -628,21 +1076,34 @@ fn validate_array(
     if let Some(min) = schema.min_items
         && count < min
     {
-        errors.push(err(node, format!("Array has fewer than {min} items.")));
+        errors.push(err(
+            node,
+            ErrorCode::ItemCount,
+            format!("Array has fewer than {min} items."),
+        ));
     }
     if let Some(max) = schema.max_items
         && count > max
     {
-        errors.push(err(node, format!("Array has more than {max} items.")));
+        errors.push(err(
+            node,
+            ErrorCode::ItemCount,
+            format!("Array has more than {max} items."),
+        ));
     }
 
-    // Unique items — use raw source text as hash key for O(n) detection.
+    // Unique items — use canonical source text as hash key for O(n) detection.
     if schema.unique_items && items.len() > 1 {
         let mut seen = HashSet::new();
         for item in &items {
-            let key = item.utf8_text(source).unwrap_or("");
+            let mut key = String::new();
+            canonical_text(*item, source, &mut key);
             if !seen.insert(key) {
-                errors.push(err(*item, "Duplicate array item.".into()));
+                errors.push(err(
+                    *item,
+                    ErrorCode::DuplicateItem,
+                    "Duplicate array item.".into(),
+                ));
             }
         }
     }
@@ -651,22 +1112,33 @@ fn validate_array(
     let mut validated_up_to = 0;
     for (i, prefix_schema) in schema.prefix_items.iter().enumerate() {
         if let Some(item) = items.get(i) {
-            validate_node(*item, source, prefix_schema, errors, ref_chain, regex_cache);
+            validate_node(*item, prefix_schema, errors, ctx);
             validated_up_to = i + 1;
         }
     }
 
+    if ctx.annotate {
+        ctx.evaluated.items = ctx.evaluated.items.max(validated_up_to);
+    }
+
     // items.
     if let Some(ref items_schema) = schema.items {
+        if ctx.annotate {
+            ctx.evaluated.items = items.len();
+        }
         match items_schema.as_ref() {
             SchemaOrBool::Schema(s) => {
                 for item in items.iter().skip(validated_up_to) {
-                    validate_node(*item, source, s, errors, ref_chain, regex_cache);
+                    validate_node(*item, s, errors, ctx);
                 }
             }
             SchemaOrBool::Bool(false) => {
                 for item in items.iter().skip(validated_up_to) {
-                    errors.push(err(*item, "Additional items are not allowed.".into()));
+                    errors.push(err(
+                        *item,
+                        ErrorCode::ItemNotAllowed,
+                        "Additional items are not allowed.".into(),
+                    ));
                 }
             }
             SchemaOrBool::Bool(true) => {}
@@ -675,25 +1147,24 @@ fn validate_array(
 
     // contains.
     if let Some(ref contains_schema) = schema.contains {
+        // `contains` only annotates matched items from 2020-12 on.
+        let annotate = ctx.annotate && ctx.draft != Some(SchemaDraft::Draft2019_09);
         let mut match_count = 0u64;
-        for item in &items {
+        for (i, item) in items.iter().enumerate() {
             let mut temp = Vec::new();
-            validate_node(
-                *item,
-                source,
-                contains_schema,
-                &mut temp,
-                ref_chain,
-                regex_cache,
-            );
-            if temp.is_empty() {
+            validate_node(*item, contains_schema, &mut temp, ctx);
+            if !has_errors(&temp) {
                 match_count += 1;
+                if annotate {
+                    ctx.evaluated.contained.insert(i);
+                }
             }
         }
         let min_contains = schema.min_contains.unwrap_or(1);
         if match_count < min_contains {
             errors.push(err(
                 node,
+                ErrorCode::Contains,
                 format!("Array must contain at least {min_contains} matching item(s)."),
             ));
         }
@@ -702,6 +1173,7 @@ fn validate_array(
         {
             errors.push(err(
                 node,
+                ErrorCode::Contains,
                 format!("Array must contain at most {max_contains} matching item(s)."),
             ));
         }
@@ -727,29 +1199,33 @@ fn type_could_match(node: Node<'_>, source: &[u8], sub: &JsonSchema) -> bool {
 
 fn validate_composition(
     node: Node<'_>,
-    source: &[u8],
     schema: &JsonSchema,
     errors: &mut Vec<ValidationError>,
-    ref_chain: &HashSet<String>,
-    regex_cache: &mut RegexCache,
+    ctx: &mut Ctx<'_>,
 ) {
     for sub in &schema.all_of {
-        validate_node(node, source, sub, errors, ref_chain, regex_cache);
+        validate_in_place(node, sub, errors, ctx);
     }
 
     if !schema.any_of.is_empty() {
-        let any_matches = schema.any_of.iter().any(|sub| {
+        let mut any_matches = false;
+        for sub in &schema.any_of {
             // Fast path: skip full trial validation when the type cannot match.
-            if !type_could_match(node, source, sub) {
-                return false;
+            if !type_could_match(node, ctx.source, sub) {
+                continue;
             }
             let mut temp = Vec::new();
-            validate_node(node, source, sub, &mut temp, ref_chain, regex_cache);
-            temp.is_empty()
-        });
+            validate_in_place(node, sub, &mut temp, ctx);
+            any_matches |= !has_errors(&temp);
+            // Every matching branch contributes evaluated locations.
+            if any_matches && !ctx.annotate {
+                break;
+            }
+        }
         if !any_matches {
             errors.push(err(
                 node,
+                ErrorCode::NoMatch,
                 "Matches none of the listed schemas (anyOf).".into(),
             ));
         }
@@ -759,12 +1235,12 @@ fn validate_composition(
         let mut match_count = 0u32;
         for sub in &schema.one_of {
             // Fast path: skip full trial validation when the type cannot match.
-            if !type_could_match(node, source, sub) {
+            if !type_could_match(node, ctx.source, sub) {
                 continue;
             }
             let mut temp = Vec::new();
-            validate_node(node, source, sub, &mut temp, ref_chain, regex_cache);
-            if temp.is_empty() {
+            validate_in_place(node, sub, &mut temp, ctx);
+            if !has_errors(&temp) {
                 match_count += 1;
                 // Short-circuit: we already know we have too many matches.
                 if match_count > 1 {
@@ -775,22 +1251,28 @@ fn validate_composition(
         if match_count == 0 {
             errors.push(err(
                 node,
+                ErrorCode::NoMatch,
                 "Matches none of the listed schemas (oneOf).".into(),
             ));
         } else if match_count > 1 {
             errors.push(err(
                 node,
+                ErrorCode::MultipleMatches,
                 format!("Matches {match_count}+ schemas but should match exactly one (oneOf)."),
             ));
         }
     }
 
     if let Some(ref not_schema) = schema.not {
+        // `not` never contributes evaluated locations.
         let mut temp = Vec::new();
-        validate_node(node, source, not_schema, &mut temp, ref_chain, regex_cache);
-        if temp.is_empty() {
+        let outer_annotate = std::mem::replace(&mut ctx.annotate, false);
+        validate_in_place(node, not_schema, &mut temp, ctx);
+        ctx.annotate = outer_annotate;
+        if !has_errors(&temp) {
             errors.push(err(
                 node,
+                ErrorCode::MatchesNot,
                 "Value matches a schema it should not (not).".into(),
             ));
         }
@@ -803,42 +1285,153 @@ fn validate_composition(
 
 fn validate_conditional(
     node: Node<'_>,
-    source: &[u8],
     schema: &JsonSchema,
     errors: &mut Vec<ValidationError>,
-    ref_chain: &HashSet<String>,
-    regex_cache: &mut RegexCache,
+    ctx: &mut Ctx<'_>,
 ) {
     if let Some(ref if_schema) = schema.if_schema {
         let mut temp = Vec::new();
-        validate_node(node, source, if_schema, &mut temp, ref_chain, regex_cache);
-        if temp.is_empty() {
+        validate_in_place(node, if_schema, &mut temp, ctx);
+        if !has_errors(&temp) {
             if let Some(ref then_schema) = schema.then_schema {
-                validate_node(node, source, then_schema, errors, ref_chain, regex_cache);
+                validate_in_place(node, then_schema, errors, ctx);
             }
         } else if let Some(ref else_schema) = schema.else_schema {
-            validate_node(node, source, else_schema, errors, ref_chain, regex_cache);
+            validate_in_place(node, else_schema, errors, ctx);
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Unevaluated
+// ---------------------------------------------------------------------------
+
+fn validate_unevaluated_properties(
+    node: Node<'_>,
+    schema: &JsonSchema,
+    errors: &mut Vec<ValidationError>,
+    ctx: &mut Ctx<'_>,
+) {
+    let Some(ref unevaluated) = schema.unevaluated_properties else {
+        return;
+    };
+    let mut cursor = node.walk();
+    for pair in tree::object_pairs(node, &mut cursor) {
+        let Some(key_node) = pair.child_by_field_name("key") else {
+            continue;
+        };
+        let Some(key_str) = tree::string_value(key_node, ctx.source) else {
+            continue;
+        };
+        if ctx.evaluated.properties.contains(&key_str) {
+            continue;
+        }
+        match unevaluated.as_ref() {
+            SchemaOrBool::Bool(false) => errors.push(err(
+                key_node,
+                ErrorCode::PropertyNotAllowed,
+                format!("Property \"{key_str}\" is not allowed."),
+            )),
+            SchemaOrBool::Schema(s) => {
+                if let Some(value_node) = tree::pair_value(pair) {
+                    validate_node(value_node, s, errors, ctx);
+                }
+            }
+            SchemaOrBool::Bool(true) => {}
+        }
+        ctx.evaluated.properties.insert(key_str);
+    }
+}
+
+fn validate_unevaluated_items(
+    node: Node<'_>,
+    schema: &JsonSchema,
+    errors: &mut Vec<ValidationError>,
+    ctx: &mut Ctx<'_>,
+) {
+    let Some(ref unevaluated) = schema.unevaluated_items else {
+        return;
+    };
+    let mut cursor = node.walk();
+    let items = tree::array_items(node, &mut cursor);
+    for (i, item) in items.iter().enumerate().skip(ctx.evaluated.items) {
+        if ctx.evaluated.contained.contains(&i) {
+            continue;
+        }
+        match unevaluated.as_ref() {
+            SchemaOrBool::Bool(false) => errors.push(err(
+                *item,
+                ErrorCode::ItemNotAllowed,
+                "Additional items are not allowed.".into(),
+            )),
+            SchemaOrBool::Schema(s) => validate_node(*item, s, errors, ctx),
+            SchemaOrBool::Bool(true) => {}
         }
     }
+    ctx.evaluated.items = items.len();
 }
 
 // ---------------------------------------------------------------------------
 // Helpers
 // ---------------------------------------------------------------------------
 
-fn err(node: Node<'_>, message: String) -> ValidationError {
+fn has_errors(errors: &[ValidationError]) -> bool {
+    errors.iter().any(|e| e.severity == Severity::Error)
+}
+
+fn err(node: Node<'_>, code: ErrorCode, message: String) -> ValidationError {
     ValidationError {
         start_byte: node.start_byte(),
         end_byte: node.end_byte(),
         message,
         severity: Severity::Error,
+        code,
+        data: None,
     }
 }
 
-#[cfg(test)]
+/// Quick-fix data for a missing property: its name and a value to insert,
+/// taken from the property schema's `default`, `const` or first `enum`
+/// value, or an empty value of its type.
+fn missing_property_data(name: &str, schema: &Arc<JsonSchema>, ctx: &Ctx<'_>) -> serde_json::Value {
+    let value = schema
+        .properties
+        .get(name)
+        .and_then(|prop| {
+            let scoped = ScopedSchema {
+                schema: prop.clone(),
+                root: ctx.root.clone(),
+                base_uri: ctx.base_uri.as_str().into(),
+            };
+            scoped.branches(ctx.registry).into_iter().find_map(|b| {
+                let s = &b.schema;
+                s.default
+                    .clone()
+                    .or_else(|| s.const_value.clone())
+                    .or_else(|| s.enum_values.first().cloned())
+                    .or_else(|| s.types.first().map(|t| empty_value(*t)))
+            })
+        })
+        .unwrap_or(serde_json::Value::Null);
+    serde_json::json!({ "property": name, "value": value })
+}
+
+fn empty_value(ty: SchemaType) -> serde_json::Value {
+    match ty {
+        SchemaType::String => serde_json::Value::String(String::new()),
+        SchemaType::Number | SchemaType::Integer => serde_json::Value::from(0),
+        SchemaType::Boolean => serde_json::Value::Bool(false),
+        SchemaType::Object => serde_json::Value::Object(Default::default()),
+        SchemaType::Array => serde_json::Value::Array(Vec::new()),
+        SchemaType::Null => serde_json::Value::Null,
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
     use crate::document::Document;
+    use crate::schema::resolver::SchemaStore;
     use crate::tree;
 
     fn validate_json(json: &str, schema_json: &str) -> Vec<ValidationError> {
@@ -846,8 +1439,59 @@ mod tests {
         let schema_val: serde_json::Value = serde_json::from_str(schema_json).unwrap();
         let schema = JsonSchema::from_value(&schema_val);
         let root = tree::root_value(&doc.tree).unwrap();
-        let mut regex_cache = RegexCache::new();
-        validate(root, doc.source(), &schema, &mut regex_cache)
+        validate(
+            root,
+            doc.source(),
+            &schema,
+            SchemaStore::new().registry(),
+            &RegexCache::new(),
+            ValidationOptions::default(),
+        )
+    }
+
+    // -- Cancellation --
+
+    #[test]
+    fn cancelled_validation_returns_no_errors() {
+        let doc = Document::new(r#"{"a": 1}"#.into(), 0);
+        let schema = JsonSchema::from_value(&serde_json::json!({"required": ["b"]}));
+        let root = tree::root_value(&doc.tree).unwrap();
+        let run = |cancel: &CancelToken| {
+            validate_with_cancel(
+                root,
+                doc.source(),
+                &schema,
+                SchemaStore::new().registry(),
+                &RegexCache::new(),
+                ValidationOptions::default(),
+                cancel,
+            )
+        };
+        let cancel = CancelToken::new();
+        assert_eq!(run(&cancel).map(|errors| errors.len()), Ok(1));
+        cancel.cancel(crate::cancel::CancelReason::ContentModified);
+        assert!(run(&cancel).is_err());
+    }
+
+    // -- Regex cache --
+
+    #[test]
+    fn regex_cache_is_shared_across_threads() {
+        let cache = RegexCache::new();
+        std::thread::scope(|scope| {
+            for i in 0..4 {
+                let cache = &cache;
+                scope.spawn(move || {
+                    for j in 0..50 {
+                        let re = cache.get(&format!("^a{{{}}}$", (i + j) % 10)).unwrap();
+                        assert!(re.is_match(&"a".repeat((i + j) % 10)));
+                    }
+                    assert!(cache.get("(").is_none());
+                });
+            }
+        });
+        let cached: usize = cache.shards.iter().map(|s| s.lock().len()).sum();
+        assert_eq!(cached, 11);
     }
 
     // -- Type validation --
@@ -1080,6 +1724,26 @@ mod tests {
         assert!(!errors.is_empty());
     }
 
+    #[test]
+    fn unique_items_ignores_key_order() {
+        let schema = r#"{"uniqueItems": true}"#;
+        let errors = validate_json(r#"[{"a": 1, "b": [2]}, {"b": [2], "a": 1}]"#, schema);
+        assert_eq!(errors.len(), 1);
+        assert!(validate_json(r#"[{"a": 1}, {"a": 2}]"#, schema).is_empty());
+        assert_eq!(validate_json(r#"["\u0041", "A"]"#, schema).len(), 1);
+        assert_eq!(validate_json("[1, 1.0]", schema).len(), 1);
+        assert_eq!(validate_json("[1.0, 1.00, 1e0]", schema).len(), 2);
+        assert!(validate_json("[1, 1.5, -0.5]", schema).is_empty());
+    }
+
+    #[test]
+    fn const_and_enum_compare_numbers_by_value() {
+        assert!(validate_json("1.0", r#"{"const": 1}"#).is_empty());
+        assert!(validate_json("1", r#"{"enum": [1.0]}"#).is_empty());
+        assert!(validate_json("[1.50]", r#"{"const": [1.5]}"#).is_empty());
+        assert_eq!(validate_json("1.5", r#"{"const": 1}"#).len(), 1);
+    }
+
     #[test]
     fn array_unique_items() {
         let errors = validate_json("[1, 2, 1]", r#"{"type": "array", "uniqueItems": true}"#);
@@ -1167,91 +1831,75 @@ mod tests {
         assert!(errors.iter().any(|e| e.message.contains("minLength")));
     }
 
-    // -- Format validation --
-
-    #[test]
-    fn format_date_time_valid() {
-        assert!(validate_format("2023-01-15T10:30:00Z", "date-time"));
-    }
-
-    #[test]
-    fn format_date_time_invalid() {
-        assert!(!validate_format("not a date", "date-time"));
-    }
-
-    #[test]
-    fn format_date_valid() {
-        assert!(validate_format("2023-01-15", "date"));
-    }
-
-    #[test]
-    fn format_date_invalid() {
-        assert!(!validate_format("2023/01/15", "date"));
-    }
-
-    #[test]
-    fn format_email_valid() {
-        assert!(validate_format("user@example.com", "email"));
-    }
-
-    #[test]
-    fn format_email_invalid() {
-        assert!(!validate_format("not-an-email", "email"));
-        assert!(!validate_format("@example.com", "email"));
-        assert!(!validate_format("user@", "email"));
-    }
-
-    #[test]
-    fn format_ipv4_valid() {
-        assert!(validate_format("192.168.1.1", "ipv4"));
-    }
-
-    #[test]
-    fn format_ipv4_invalid() {
-        assert!(!validate_format("256.1.1.1", "ipv4"));
-        assert!(!validate_format("1.2.3", "ipv4"));
-        assert!(!validate_format("01.2.3.4", "ipv4")); // Leading zeros.
-    }
-
-    #[test]
-    fn format_uri_valid() {
-        assert!(validate_format("https://example.com", "uri"));
-        assert!(validate_format("urn:isbn:0451450523", "uri"));
-    }
-
-    #[test]
-    fn format_uri_invalid() {
-        assert!(!validate_format("not a uri", "uri"));
-    }
+    // -- Format --
 
-    #[test]
-    fn format_hostname_valid() {
-        assert!(validate_format("example.com", "hostname"));
-        assert!(validate_format("sub.example.com", "hostname"));
+    fn validate_with_format_mode(
+        json: &str,
+        schema_json: &str,
+        format_mode: FormatMode,
+    ) -> Vec<ValidationError> {
+        let doc = Document::new(json.into(), 0);
+        let schema_val: serde_json::Value = serde_json::from_str(schema_json).unwrap();
+        let schema = JsonSchema::from_value(&schema_val);
+        let root = tree::root_value(&doc.tree).unwrap();
+        validate(
+            root,
+            doc.source(),
+            &schema,
+            SchemaStore::new().registry(),
+            &RegexCache::new(),
+            ValidationOptions {
+                format_mode,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn format_annotates_by_default() {
+        let errors = validate_json(r#""nope""#, r#"{"format": "ipv4"}"#);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].severity, Severity::Warning);
     }
 
     #[test]
-    fn format_hostname_invalid() {
-        assert!(!validate_format("-invalid.com", "hostname"));
-        assert!(!validate_format("", "hostname"));
+    fn format_asserts_for_draft7() {
+        let errors = validate_json(
+            r#""nope""#,
+            r#"{"$schema": "http://json-schema.org/draft-07/schema#", "format": "ipv4"}"#,
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].severity, Severity::Error);
     }
 
     #[test]
-    fn format_color_hex_valid() {
-        assert!(validate_format("#fff", "color-hex"));
-        assert!(validate_format("#ffffff", "color-hex"));
-        assert!(validate_format("#ffffffFF", "color-hex"));
+    fn format_mode_overrides_draft() {
+        let draft7 = r#"{"$schema": "http://json-schema.org/draft-07/schema#", "format": "ipv4"}"#;
+        let errors = validate_with_format_mode(r#""nope""#, draft7, FormatMode::Annotation);
+        assert_eq!(errors[0].severity, Severity::Warning);
+        let errors =
+            validate_with_format_mode(r#""nope""#, r#"{"format": "ipv4"}"#, FormatMode::Assertion);
+        assert_eq!(errors[0].severity, Severity::Error);
     }
 
     #[test]
-    fn format_color_hex_invalid() {
-        assert!(!validate_format("fff", "color-hex"));
-        assert!(!validate_format("#ff", "color-hex"));
+    fn format_annotation_does_not_fail_branches() {
+        // Annotation-only failures must not make a oneOf branch invalid.
+        let errors = validate_json(
+            r#""nope""#,
+            r#"{"oneOf": [{"format": "ipv4"}, {"type": "number"}]}"#,
+        );
+        assert!(errors.iter().all(|e| e.severity == Severity::Warning));
     }
 
     #[test]
-    fn format_unknown_passes() {
-        assert!(validate_format("anything", "x-custom"));
+    fn format_assertion_fails_branches() {
+        let errors = validate_with_format_mode(
+            r#""nope""#,
+            r#"{"anyOf": [{"format": "ipv4"}, {"format": "ipv6"}]}"#,
+            FormatMode::Assertion,
+        );
+        assert!(errors.iter().any(|e| e.severity == Severity::Error));
     }
 
     // -- Dependencies --
@@ -1355,6 +2003,556 @@ mod tests {
         );
         assert!(errors.is_empty());
     }
+
+    // -- $ref --
+
+    #[test]
+    fn ref_to_definitions() {
+        let schema = r##"{
+            "properties": {"name": {"$ref": "#/definitions/Name"}},
+            "definitions": {"Name": {"type": "string"}}
+        }"##;
+        let errors = validate_json(r#"{"name": 42}"#, schema);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("Incorrect type"));
+        assert!(validate_json(r#"{"name": "x"}"#, schema).is_empty());
+    }
+
+    #[test]
+    fn ref_to_defs() {
+        let errors = validate_json(
+            r#"{"n": "x"}"#,
+            r##"{"properties": {"n": {"$ref": "#/$defs/N"}}, "$defs": {"N": {"type": "number"}}}"##,
+        );
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn ref_arbitrary_pointer() {
+        let errors = validate_json(
+            r#"{"a": "x", "b": 1}"#,
+            r##"{"properties": {"a": {"type": "string"}, "b": {"$ref": "#/properties/a"}}}"##,
+        );
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("Incorrect type"));
+    }
+
+    #[test]
+    fn ref_into_unknown_keyword() {
+        let errors = validate_json(
+            "1",
+            r##"{"$ref": "#/components/schemas/S", "components": {"schemas": {"S": {"type": "string"}}}}"##,
+        );
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn ref_recursive_schema_validates_every_level() {
+        let schema = r##"{
+            "$ref": "#/definitions/Node",
+            "definitions": {
+                "Node": {
+                    "type": "object",
+                    "properties": {
+                        "value": {"type": "number"},
+                        "next": {"$ref": "#/definitions/Node"}
+                    }
+                }
+            }
+        }"##;
+        let errors = validate_json(
+            r#"{"value": 1, "next": {"value": 2, "next": {"value": "three"}}}"#,
+            schema,
+        );
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("Incorrect type"));
+    }
+
+    #[test]
+    fn ref_cycle_terminates() {
+        let errors = validate_json(
+            "1",
+            r##"{"$ref": "#/definitions/A", "definitions": {"A": {"$ref": "#/definitions/B"}, "B": {"$ref": "#/definitions/A"}}}"##,
+        );
+        assert!(errors.is_empty());
+        let errors = validate_json("1", r##"{"$ref": "#"}"##);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn ref_external_from_cache() {
+        let mut store = SchemaStore::new();
+        let other: serde_json::Value = serde_json::from_str(
+            r##"{"definitions": {"Port": {"type": "integer", "maximum": 65535}}}"##,
+        )
+        .unwrap();
+        store.insert_cache(
+            "https://example.com/common.json".into(),
+            JsonSchema::from_value(&other),
+        );
+        let schema_val: serde_json::Value = serde_json::from_str(
+            r##"{"$id": "https://example.com/main.json", "properties": {"port": {"$ref": "common.json#/definitions/Port"}}}"##,
+        )
+        .unwrap();
+        let schema = JsonSchema::from_value(&schema_val);
+        let doc = Document::new(r#"{"port": 70000}"#.into(), 0);
+        let root = tree::root_value(&doc.tree).unwrap();
+        let errors = validate(
+            root,
+            doc.source(),
+            &schema,
+            store.registry(),
+            &RegexCache::new(),
+            ValidationOptions::default(),
+        );
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("maximum"));
+    }
+
+    #[test]
+    fn ref_allof_no_unknown_property_warnings() {
+        let errors = validate_json(
+            r#"{"a": 1, "b": 2}"#,
+            r##"{
+                "allOf": [{"$ref": "#/definitions/A"}, {"$ref": "#/definitions/B"}],
+                "definitions": {"A": {"properties": {"a": {}}}, "B": {"properties": {"b": {}}}}
+            }"##,
+        );
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn ref_unknown_property_warning_through_ref() {
+        let errors = validate_json(
+            r#"{"a": 1, "typo": 2}"#,
+            r##"{"$ref": "#/definitions/A", "definitions": {"A": {"properties": {"a": {}}}}}"##,
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].severity, Severity::Warning);
+        assert!(errors[0].message.contains("\"typo\""));
+    }
+
+    #[test]
+    fn ref_overrides_siblings_in_draft7() {
+        let schema = r##"{
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "properties": {"a": {"$ref": "#/definitions/S", "maxLength": 1}},
+            "definitions": {"S": {"type": "string"}}
+        }"##;
+        assert!(validate_json(r#"{"a": "long"}"#, schema).is_empty());
+        let schema = schema.replace("draft-07/schema#", "draft/2020-12/schema");
+        assert_eq!(validate_json(r#"{"a": "long"}"#, &schema).len(), 1);
+    }
+
+    #[test]
+    fn ref_to_anchor() {
+        let errors = validate_json(
+            r#"{"a": 1}"#,
+            r##"{
+                "properties": {"a": {"$ref": "#str"}},
+                "$defs": {"S": {"$anchor": "str", "type": "string"}}
+            }"##,
+        );
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn ref_to_legacy_plain_name_id() {
+        let errors = validate_json(
+            r#"{"a": 1}"#,
+            r##"{
+                "$schema": "http://json-schema.org/draft-04/schema#",
+                "properties": {"a": {"$ref": "#str"}},
+                "definitions": {"S": {"id": "#str", "type": "string"}}
+            }"##,
+        );
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn ref_pointer_through_tuple_items() {
+        let errors = validate_json(
+            r#"{"a": {"x": 1}}"#,
+            r##"{
+                "properties": {"a": {"$ref": "#/items/0"}},
+                "items": [{"properties": {"x": {"type": "string"}}}]
+            }"##,
+        );
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn embedded_id_changes_base_uri() {
+        // Inside the embedded resource `#/$defs/S` means its own `$defs`.
+        let mut store = SchemaStore::new();
+        let raw: serde_json::Value = serde_json::from_str(
+            r##"{
+                "$id": "http://example.com/root.json",
+                "properties": {"a": {"$ref": "nested.json"}},
+                "$defs": {
+                    "N": {
+                        "$id": "nested.json",
+                        "$ref": "#/$defs/S",
+                        "$defs": {"S": {"type": "string"}}
+                    },
+                    "S": {"type": "number"}
+                }
+            }"##,
+        )
+        .unwrap();
+        let schema = crate::schema::resolver::compile_schema("http://example.com/root.json", &raw);
+        store.insert_cache("http://example.com/root.json".into(), schema.clone());
+        let doc = Document::new(r#"{"a": 1}"#.into(), 0);
+        let root = tree::root_value(&doc.tree).unwrap();
+        let errors = validate(
+            root,
+            doc.source(),
+            &schema,
+            store.registry(),
+            &RegexCache::new(),
+            ValidationOptions::default(),
+        );
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("string"));
+    }
+
+    // -- unevaluatedProperties / unevaluatedItems --
+
+    #[test]
+    fn unevaluated_properties_sees_all_of() {
+        let schema = r#"{
+            "allOf": [{"properties": {"a": {}}}],
+            "properties": {"b": {}},
+            "unevaluatedProperties": false
+        }"#;
+        assert!(validate_json(r#"{"a": 1, "b": 2}"#, schema).is_empty());
+        let errors = validate_json(r#"{"a": 1, "c": 3}"#, schema);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("\"c\""));
+    }
+
+    #[test]
+    fn unevaluated_properties_through_ref() {
+        let schema = r##"{
+            "$ref": "#/$defs/Base",
+            "properties": {"extra": {}},
+            "unevaluatedProperties": false,
+            "$defs": {"Base": {"properties": {"name": {}}}}
+        }"##;
+        assert!(validate_json(r#"{"name": "x", "extra": 1}"#, schema).is_empty());
+        assert_eq!(
+            validate_json(r#"{"name": "x", "other": 1}"#, schema).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn unevaluated_properties_ignores_failed_branches() {
+        let schema = r#"{
+            "anyOf": [
+                {"properties": {"a": {"type": "string"}}, "required": ["a"]},
+                {"properties": {"b": {}}, "required": ["b"]}
+            ],
+            "unevaluatedProperties": false
+        }"#;
+        assert!(validate_json(r#"{"b": 1}"#, schema).is_empty());
+        // "a" is only declared by the failing branch.
+        let errors = validate_json(r#"{"a": 1, "b": 1}"#, schema);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("\"a\""));
+    }
+
+    #[test]
+    fn unevaluated_properties_all_matching_any_of_branches() {
+        let schema = r#"{
+            "anyOf": [{"properties": {"a": {}}}, {"properties": {"b": {}}}],
+            "unevaluatedProperties": false
+        }"#;
+        assert!(validate_json(r#"{"a": 1, "b": 2}"#, schema).is_empty());
+    }
+
+    #[test]
+    fn unevaluated_properties_conditional() {
+        let schema = r#"{
+            "if": {"properties": {"kind": {"const": "a"}}},
+            "then": {"properties": {"alpha": {}}},
+            "else": {"properties": {"beta": {}}},
+            "unevaluatedProperties": false
+        }"#;
+        assert!(validate_json(r#"{"kind": "a", "alpha": 1}"#, schema).is_empty());
+        // `if` failed, so "kind" is not evaluated by it, and "alpha" is not
+        // evaluated by `else`.
+        assert_eq!(
+            validate_json(r#"{"kind": "b", "alpha": 1}"#, schema).len(),
+            2
+        );
+    }
+
+    #[test]
+    fn unevaluated_properties_dependent_schemas() {
+        let schema = r#"{
+            "properties": {"a": {}},
+            "dependentSchemas": {"a": {"properties": {"b": {}}}},
+            "unevaluatedProperties": false
+        }"#;
+        assert!(validate_json(r#"{"a": 1, "b": 2}"#, schema).is_empty());
+        assert_eq!(validate_json(r#"{"b": 2}"#, schema).len(), 1);
+    }
+
+    #[test]
+    fn unevaluated_properties_not_contributes_nothing() {
+        let schema = r#"{
+            "not": {"not": {"properties": {"a": {}}}},
+            "unevaluatedProperties": false
+        }"#;
+        assert_eq!(validate_json(r#"{"a": 1}"#, schema).len(), 1);
+    }
+
+    #[test]
+    fn unevaluated_properties_cousins_do_not_see_each_other() {
+        let schema = r#"{
+            "allOf": [
+                {"properties": {"a": {}}},
+                {"unevaluatedProperties": false}
+            ]
+        }"#;
+        assert_eq!(validate_json(r#"{"a": 1}"#, schema).len(), 1);
+    }
+
+    #[test]
+    fn unevaluated_properties_nested_marks_everything() {
+        let schema = r#"{
+            "allOf": [{"properties": {"a": {}}, "unevaluatedProperties": true}],
+            "unevaluatedProperties": false
+        }"#;
+        assert!(validate_json(r#"{"a": 1, "b": 2}"#, schema).is_empty());
+    }
+
+    #[test]
+    fn unevaluated_properties_schema_validates_rest() {
+        let schema = r#"{"properties": {"a": {}}, "unevaluatedProperties": {"type": "number"}}"#;
+        assert!(validate_json(r#"{"a": "x", "b": 2}"#, schema).is_empty());
+        assert_eq!(validate_json(r#"{"a": "x", "b": "y"}"#, schema).len(), 1);
+    }
+
+    #[test]
+    fn unevaluated_properties_suppresses_unknown_warnings() {
+        let schema = r#"{"properties": {"a": {}}, "unevaluatedProperties": {}}"#;
+        assert!(validate_json(r#"{"a": 1, "b": 2}"#, schema).is_empty());
+    }
+
+    #[test]
+    fn unevaluated_items_prefix_items() {
+        let schema = r#"{
+            "allOf": [{"prefixItems": [{"type": "string"}]}],
+            "unevaluatedItems": false
+        }"#;
+        assert!(validate_json(r#"["a"]"#, schema).is_empty());
+        let errors = validate_json(r#"["a", 1, 2]"#, schema);
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn unevaluated_items_items_evaluates_all() {
+        let schema = r#"{"allOf": [{"items": true}], "unevaluatedItems": false}"#;
+        assert!(validate_json(r#"[1, 2, 3]"#, schema).is_empty());
+    }
+
+    #[test]
+    fn unevaluated_items_contains() {
+        let schema = r#"{"contains": {"type": "string"}, "unevaluatedItems": {"type": "number"}}"#;
+        assert!(validate_json(r#"["a", 1, "b"]"#, schema).is_empty());
+        assert_eq!(validate_json(r#"["a", true]"#, schema).len(), 1);
+    }
+
+    /// Register each `(uri, schema)` in a fresh store and validate `json`
+    /// against the first one.
+    fn validate_with_resources(json: &str, schemas: &[(&str, &str)]) -> Vec<ValidationError> {
+        let mut store = SchemaStore::new();
+        let mut compiled = Vec::new();
+        for (uri, raw) in schemas {
+            let raw: serde_json::Value = serde_json::from_str(raw).unwrap();
+            let schema = crate::schema::resolver::compile_schema(uri, &raw);
+            store.insert_cache(uri.to_string(), schema.clone());
+            compiled.push(schema);
+        }
+        let doc = Document::new(json.into(), 0);
+        let root = tree::root_value(&doc.tree).unwrap();
+        validate(
+            root,
+            doc.source(),
+            &compiled[0],
+            store.registry(),
+            &RegexCache::new(),
+            ValidationOptions::default(),
+        )
+    }
+
+    fn validate_schema_file(json: &str) -> Vec<ValidationError> {
+        let doc = Document::new(json.into(), 0);
+        let store = SchemaStore::new();
+        let meta_uri = crate::schema::resolver::extract_schema_property(&doc).unwrap();
+        let crate::schema::resolver::SchemaLookup::Resolved(meta) =
+            store.schema_for_document("file:///a.schema.json", Some(&meta_uri))
+        else {
+            panic!("meta-schema should be bundled");
+        };
+        let root = tree::root_value(&doc.tree).unwrap();
+        validate(
+            root,
+            doc.source(),
+            &meta,
+            store.registry(),
+            &RegexCache::new(),
+            ValidationOptions::default(),
+        )
+    }
+
+    #[test]
+    fn meta_schema_draft7() {
+        let valid = r#"{"$schema": "http://json-schema.org/draft-07/schema#", "minLength": 1}"#;
+        assert!(validate_schema_file(valid).is_empty());
+        let invalid = r#"{
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "properties": {"a": {"minLength": -1}}
+        }"#;
+        assert!(has_errors(&validate_schema_file(invalid)));
+    }
+
+    #[test]
+    fn meta_schema_draft2020_follows_dynamic_refs() {
+        let valid = r##"{
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "$defs": {"a": {"type": "string"}},
+            "properties": {"a": {"$ref": "#/$defs/a"}}
+        }"##;
+        assert!(validate_schema_file(valid).is_empty());
+        let invalid = r#"{
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "properties": {"a": {"items": {"type": "strnig"}}}
+        }"#;
+        assert!(has_errors(&validate_schema_file(invalid)));
+    }
+
+    const TREE: &str = r##"{
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": "https://example.com/tree",
+        "$dynamicAnchor": "node",
+        "type": "object",
+        "properties": {
+            "data": true,
+            "children": {"type": "array", "items": {"$dynamicRef": "#node"}}
+        }
+    }"##;
+
+    const STRICT_TREE: &str = r##"{
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": "https://example.com/strict-tree",
+        "$dynamicAnchor": "node",
+        "$ref": "tree",
+        "properties": {"data": {"type": "string"}}
+    }"##;
+
+    #[test]
+    fn dynamic_ref_resolves_to_outermost_anchor() {
+        let json = r#"{"data": "a", "children": [{"data": 1}]}"#;
+        let errors = validate_with_resources(
+            json,
+            &[
+                ("https://example.com/strict-tree", STRICT_TREE),
+                ("https://example.com/tree", TREE),
+            ],
+        );
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("string"));
+
+        let errors = validate_with_resources(json, &[("https://example.com/tree", TREE)]);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn dynamic_ref_without_bookend_is_static() {
+        // The static target has no matching `$dynamicAnchor`, so the outer
+        // resource's anchor is not consulted.
+        let tree = TREE.replace(r#""$dynamicAnchor": "node","#, r#""$anchor": "node","#);
+        let errors = validate_with_resources(
+            r#"{"children": [{"data": 1}]}"#,
+            &[
+                ("https://example.com/strict-tree", STRICT_TREE),
+                ("https://example.com/tree", &tree),
+            ],
+        );
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn recursive_ref_resolves_to_outermost_recursive_anchor() {
+        let tree = r##"{
+            "$schema": "https://json-schema.org/draft/2019-09/schema",
+            "$id": "https://example.com/tree",
+            "$recursiveAnchor": true,
+            "type": "object",
+            "properties": {
+                "data": true,
+                "children": {"type": "array", "items": {"$recursiveRef": "#"}}
+            }
+        }"##;
+        let strict = r##"{
+            "$schema": "https://json-schema.org/draft/2019-09/schema",
+            "$id": "https://example.com/strict-tree",
+            "$recursiveAnchor": true,
+            "$ref": "tree",
+            "properties": {"data": {"type": "string"}}
+        }"##;
+        let json = r#"{"children": [{"children": [{"data": 1}]}]}"#;
+        let errors = validate_with_resources(
+            json,
+            &[
+                ("https://example.com/strict-tree", strict),
+                ("https://example.com/tree", tree),
+            ],
+        );
+        assert_eq!(errors.len(), 1);
+
+        let strict = strict.replace(r#""$recursiveAnchor": true,"#, "");
+        let errors = validate_with_resources(
+            json,
+            &[
+                ("https://example.com/strict-tree", &strict),
+                ("https://example.com/tree", tree),
+            ],
+        );
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn ref_external_anchor() {
+        let mut store = SchemaStore::new();
+        let other: serde_json::Value = serde_json::from_str(
+            r##"{"$defs": {"Node": {"$anchor": "node", "type": "object", "required": ["id"]}}}"##,
+        )
+        .unwrap();
+        store.insert_cache(
+            "https://example.com/other.json".into(),
+            crate::schema::resolver::compile_schema("https://example.com/other.json", &other),
+        );
+        let schema_val: serde_json::Value = serde_json::from_str(
+            r##"{"$id": "https://example.com/main.json", "$ref": "other.json#node"}"##,
+        )
+        .unwrap();
+        let schema = JsonSchema::from_value(&schema_val);
+        let doc = Document::new(r#"{}"#.into(), 0);
+        let root = tree::root_value(&doc.tree).unwrap();
+        let errors = validate(
+            root,
+            doc.source(),
+            &schema,
+            store.registry(),
+            &RegexCache::new(),
+            ValidationOptions::default(),
+        );
+        assert_eq!(errors.len(), 1);
+    }
 }
 
 fn node_to_schema_type(node: Node<'_>, source: &[u8]) -> SchemaType {
@@ -1377,6 +2575,78 @@ fn node_to_schema_type(node: Node<'_>, source: &[u8]) -> SchemaType {
     }
 }
 
+/// The source text of a value with object keys sorted, strings re-escaped
+/// and numbers written by value, so that equal values get equal text.
+fn canonical_text(node: Node<'_>, source: &[u8], out: &mut String) {
+    match node.kind() {
+        kinds::OBJECT => {
+            let mut cursor = node.walk();
+            let mut pairs: Vec<_> = tree::object_pairs(node, &mut cursor)
+                .into_iter()
+                .filter_map(|p| Some((tree::pair_key_unescaped(p, source)?, tree::pair_value(p)?)))
+                .collect();
+            pairs.sort_by(|a, b| a.0.cmp(&b.0));
+            out.push('{');
+            for (i, (key, value)) in pairs.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::Value::String(key).to_string());
+                out.push(':');
+                canonical_text(value, source, out);
+            }
+            out.push('}');
+        }
+        kinds::ARRAY => {
+            let mut cursor = node.walk();
+            out.push('[');
+            for (i, item) in tree::array_items(node, &mut cursor).into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                canonical_text(item, source, out);
+            }
+            out.push(']');
+        }
+        kinds::STRING => match tree::string_value(node, source) {
+            Some(s) => out.push_str(&serde_json::Value::String(s).to_string()),
+            None => out.push_str(node.utf8_text(source).unwrap_or("")),
+        },
+        kinds::NUMBER => canonical_number(node.utf8_text(source).unwrap_or(""), out),
+        _ => out.push_str(node.utf8_text(source).unwrap_or("")),
+    }
+}
+
+/// Integral numbers without a fraction or exponent (so `1`, `1.0` and
+/// `1e0` agree), others as the shortest float that round-trips.
+fn canonical_number(text: &str, out: &mut String) {
+    if let Ok(i) = text.parse::<i64>() {
+        out.push_str(&i.to_string());
+        return;
+    }
+    match text.parse::<f64>() {
+        Ok(v) if v.fract() == 0.0 && v.abs() < i64::MAX as f64 => {
+            out.push_str(&(v as i64).to_string())
+        }
+        Ok(v) if v.is_finite() => out.push_str(&v.to_string()),
+        _ => out.push_str(text),
+    }
+}
+
+/// Whether a number literal has the value `expected`. Integers compare
+/// exactly; otherwise, as when one side has a fraction, by `f64` value.
+fn number_matches(text: &str, expected: &serde_json::Number) -> bool {
+    if let (Some(e), Ok(v)) = (expected.as_i64(), text.parse::<i64>()) {
+        return e == v;
+    }
+    if let (Some(e), Ok(v)) = (expected.as_u64(), text.parse::<u64>()) {
+        return e == v;
+    }
+    text.parse::<f64>()
+        .ok()
+        .is_some_and(|v| Some(v) == expected.as_f64())
+}
+
 /// Compare a tree-sitter node directly against a serde_json::Value without
 /// allocating intermediate Value objects. Used for enum/const checks.
 fn node_matches_json_value(node: Node<'_>, source: &[u8], expected: &serde_json::Value) -> bool {
@@ -1384,21 +2654,9 @@ fn node_matches_json_value(node: Node<'_>, source: &[u8], expected: &serde_json:
         (kinds::STRING, serde_json::Value::String(s)) => {
             tree::string_value(node, source).as_deref() == Some(s.as_str())
         }
-        (kinds::NUMBER, serde_json::Value::Number(n)) => {
-            let text = match node.utf8_text(source) {
-                Ok(t) => t,
-                Err(_) => return false,
-            };
-            if let Some(expected_u) = n.as_u64() {
-                text.parse::<u64>().ok() == Some(expected_u)
-            } else if let Some(expected_i) = n.as_i64() {
-                text.parse::<i64>().ok() == Some(expected_i)
-            } else if let Some(expected_f) = n.as_f64() {
-                text.parse::<f64>().ok() == Some(expected_f)
-            } else {
-                false
-            }
-        }
+        (kinds::NUMBER, serde_json::Value::Number(n)) => node
+            .utf8_text(source)
+            .is_ok_and(|text| number_matches(text, n)),
         (kinds::TRUE, serde_json::Value::Bool(true)) => true,
         (kinds::FALSE, serde_json::Value::Bool(false)) => true,
         (kinds::NULL, serde_json::Value::Null) => true,
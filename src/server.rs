@@ -1,40 +1,160 @@
 /// LSP server: wires all features together via lsp-server.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
 
-use parking_lot::{Mutex, RwLock};
+use parking_lot::{Condvar, Mutex, RwLock};
 
 use crossbeam_channel::{Receiver, Sender};
-use lsp_server::{Connection, ExtractError, Message, Notification, Request, RequestId, Response};
+use lsp_server::{
+    Connection, ErrorCode, ExtractError, Message, Notification, Request, RequestId, Response,
+};
 use lsp_types::notification::{self, Notification as _};
 use lsp_types::request::{self, Request as _};
 use lsp_types::*;
 use tracing::{debug, info, warn};
 
+use crate::cancel::{CancelReason, CancelToken, Cancelled};
+use crate::code_actions;
 use crate::colors;
 use crate::completion;
 use crate::diagnostics;
-use crate::document::DocumentStore;
+use crate::document::{Document, DocumentStore};
 use crate::folding;
-use crate::formatting;
+use crate::formatting::{self, FormatSettings, SortOrder};
 use crate::hover;
 use crate::links;
-use crate::schema::resolver::{self, SchemaAssociation, SchemaLookup, SchemaStore};
+use crate::schema::catalog::Catalog;
+use crate::schema::disk_cache::{CachedSchema, DiskCache};
+use crate::schema::format::FormatMode;
+use crate::schema::resolver::{
+    self, Fetched, SchemaAssociation, SchemaLookup, SchemaRegistry, SchemaStore,
+};
 use crate::schema::types::JsonSchema;
-use crate::schema::validation::{self, RegexCache};
+use crate::schema::validation::{self, RegexCache, ValidationOptions};
 use crate::selection;
 use crate::tree;
+use crate::workspace::{self, WorkspaceFiles, WorkspaceScanner, WorkspaceSettings};
 
 pub struct ServerState {
     pub documents: DocumentStore,
     pub schemas: SchemaStore,
+    pub validation: ValidationOptions,
+    pub sort_order: SortOrder,
+    pub format: FormatSettings,
+    pub workspace: WorkspaceSettings,
+    /// Files validated while closed, when workspace validation is on.
+    pub workspace_files: WorkspaceFiles,
 }
 
-/// Shared state that the validation worker thread can access.
+/// Shared state that the validation worker and schema fetch threads can
+/// access.
 struct Shared {
     state: RwLock<ServerState>,
-    regex_cache: Mutex<RegexCache>,
-    validate_tx: Sender<Uri>,
+    regex_cache: RegexCache,
+    validations: ValidationQueue,
+    pulls: PullQueue,
+    requests: PendingRequests,
+    fetches: FetchQueue,
+    /// Downloaded schemas persisted across restarts.
+    disk_cache: Option<DiskCache>,
+    /// How diagnostics reach the client, settled by `initialize`.
+    diagnostic_mode: OnceLock<DiagnosticMode>,
+    /// Outgoing messages, for requests sent from worker threads.
+    client: Sender<Message>,
+    /// Whether the client can watch files for us, and the globs it watches.
+    file_watching: OnceLock<bool>,
+    watching: Mutex<Vec<String>>,
+    /// Held for the whole of a workspace scan, so scans don't interleave.
+    scan_lock: Mutex<()>,
+}
+
+/// How diagnostics reach the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum DiagnosticMode {
+    /// The server publishes them with `textDocument/publishDiagnostics`.
+    #[default]
+    Push,
+    /// The client requests them with `textDocument/diagnostic` and
+    /// `workspace/diagnostic`. With `refresh`, it also accepts
+    /// `workspace/diagnostic/refresh` when they change without an edit.
+    Pull { refresh: bool },
+}
+
+impl DiagnosticMode {
+    fn from_capabilities(caps: &ClientCapabilities) -> Self {
+        let pull = caps
+            .text_document
+            .as_ref()
+            .is_some_and(|t| t.diagnostic.is_some());
+        if !pull {
+            return DiagnosticMode::Push;
+        }
+        let refresh = caps
+            .workspace
+            .as_ref()
+            .and_then(|w| w.diagnostic.as_ref())
+            .and_then(|d| d.refresh_support)
+            .unwrap_or(false);
+        DiagnosticMode::Pull { refresh }
+    }
+}
+
+impl Shared {
+    fn diagnostic_mode(&self) -> DiagnosticMode {
+        self.diagnostic_mode.get().copied().unwrap_or_default()
+    }
+
+    /// Diagnostics of these documents may have changed without an edit
+    /// (settings, schemas). Push mode revalidates them; pull mode retires
+    /// their result IDs and asks the client to pull again.
+    fn invalidate_diagnostics(&self, uris: Vec<Uri>) {
+        match self.diagnostic_mode() {
+            DiagnosticMode::Push => self.validations.invalidate(uris),
+            DiagnosticMode::Pull { refresh } => {
+                self.validations.invalidate([]);
+                if refresh {
+                    self.send_request::<request::WorkspaceDiagnosticRefresh>(());
+                }
+            }
+        }
+    }
+
+    /// Workspace files changed on disk, or were dropped from the workspace
+    /// files. Changed files are revalidated; dropped ones that aren't open
+    /// lose their diagnostics.
+    fn workspace_files_changed(&self, changed: Vec<Uri>, removed: Vec<Uri>) {
+        match self.diagnostic_mode() {
+            DiagnosticMode::Push => {
+                for uri in removed {
+                    self.validations.forget(&uri);
+                    publish_diagnostics(&self.client, uri, Vec::new(), None);
+                }
+                for uri in changed {
+                    self.validations.schedule(uri, false);
+                }
+            }
+            DiagnosticMode::Pull { refresh } => {
+                if refresh && !(changed.is_empty() && removed.is_empty()) {
+                    self.send_request::<request::WorkspaceDiagnosticRefresh>(());
+                }
+            }
+        }
+    }
+
+    /// Send a request to the client, ignoring its response.
+    fn send_request<R: request::Request>(&self, params: R::Params) {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let req = Request::new(
+            RequestId::from(format!("{}-{id}", R::METHOD)),
+            R::METHOD.into(),
+            params,
+        );
+        self.client.send(Message::Request(req)).ok();
+    }
 }
 
 pub struct JsonLanguageServer {
@@ -44,41 +164,92 @@ pub struct JsonLanguageServer {
 
 impl JsonLanguageServer {
     pub fn new(connection: Connection) -> Self {
-        let (validate_tx, validate_rx) = crossbeam_channel::unbounded::<Uri>();
+        let (fetch_tx, fetch_rx) = crossbeam_channel::unbounded::<String>();
         let shared = Arc::new(Shared {
             state: RwLock::new(ServerState {
                 documents: DocumentStore::new(),
                 schemas: SchemaStore::new(),
+                validation: ValidationOptions::default(),
+                sort_order: SortOrder::default(),
+                format: FormatSettings::default(),
+                workspace: WorkspaceSettings::default(),
+                workspace_files: WorkspaceFiles::new(),
             }),
-            regex_cache: Mutex::new(RegexCache::new()),
-            validate_tx,
+            regex_cache: RegexCache::new(),
+            validations: ValidationQueue::new(),
+            pulls: PullQueue::new(),
+            requests: PendingRequests::default(),
+            fetches: FetchQueue::new(fetch_tx),
+            disk_cache: DiskCache::default_location(),
+            diagnostic_mode: OnceLock::new(),
+            client: connection.sender.clone(),
+            file_watching: OnceLock::new(),
+            watching: Mutex::new(Vec::new()),
+            scan_lock: Mutex::new(()),
         });
 
-        // Spawn a single long-lived validation worker thread.
-        {
+        // Different documents validate concurrently on a bounded pool.
+        for i in 0..validation_threads() {
             let shared = Arc::clone(&shared);
             let sender = connection.sender.clone();
             std::thread::Builder::new()
-                .name("validator".into())
-                .spawn(move || validation_worker(validate_rx, shared, sender))
+                .name(format!("validator-{i}"))
+                .spawn(move || validation_worker(shared, sender))
                 .expect("failed to spawn validation worker");
         }
 
+        // Pulled diagnostics are answered on a small pool of their own.
+        for i in 0..PULL_THREADS {
+            let shared = Arc::clone(&shared);
+            std::thread::Builder::new()
+                .name(format!("diagnostic-pull-{i}"))
+                .spawn(move || pull_worker(shared))
+                .expect("failed to spawn diagnostic pull worker");
+        }
+
+        // Schema fetches run on a small pool so a slow host never blocks
+        // requests or validation.
+        for i in 0..FETCH_THREADS {
+            let shared = Arc::clone(&shared);
+            let rx = fetch_rx.clone();
+            std::thread::Builder::new()
+                .name(format!("schema-fetch-{i}"))
+                .spawn(move || fetch_worker(rx, shared))
+                .expect("failed to spawn schema fetch worker");
+        }
+
         JsonLanguageServer { connection, shared }
     }
 
     /// Run the server: initialize, then enter the main loop.
     pub fn run(&self) {
-        let caps = self.server_capabilities();
+        let (id, params) = self.connection.initialize_start().unwrap();
+        let mut mode = DiagnosticMode::Push;
+        if let Ok(params) = serde_json::from_value::<InitializeParams>(params) {
+            mode = DiagnosticMode::from_capabilities(&params.capabilities);
+            let file_watching = params
+                .capabilities
+                .workspace
+                .as_ref()
+                .and_then(|w| w.did_change_watched_files.as_ref())
+                .and_then(|w| w.dynamic_registration)
+                .unwrap_or(false);
+            self.shared.file_watching.set(file_watching).ok();
+            self.shared
+                .state
+                .write()
+                .schemas
+                .set_workspace_folders(workspace_folders(&params));
+        }
+        self.shared.diagnostic_mode.set(mode).ok();
         let init_result = InitializeResult {
             server_info: Some(ServerInfo {
                 name: "json-language-server".into(),
                 version: Some(env!("CARGO_PKG_VERSION").into()),
             }),
-            capabilities: caps,
+            capabilities: self.server_capabilities(mode),
         };
         let init_json = serde_json::to_value(init_result).unwrap();
-        let (id, _params) = self.connection.initialize_start().unwrap();
 
         // Send the initialize response ourselves instead of using
         // initialize_finish(), which blocks until it receives `initialized`.
@@ -100,7 +271,7 @@ impl JsonLanguageServer {
         info!("json-language-server shutting down");
     }
 
-    fn server_capabilities(&self) -> ServerCapabilities {
+    fn server_capabilities(&self, mode: DiagnosticMode) -> ServerCapabilities {
         ServerCapabilities {
             text_document_sync: Some(TextDocumentSyncCapability::Options(
                 TextDocumentSyncOptions {
@@ -124,6 +295,10 @@ impl JsonLanguageServer {
             document_symbol_provider: Some(OneOf::Left(true)),
             document_formatting_provider: Some(OneOf::Left(true)),
             document_range_formatting_provider: Some(OneOf::Left(true)),
+            document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+                first_trigger_character: "}".into(),
+                more_trigger_character: Some(vec!["]".into(), "\n".into()]),
+            }),
             color_provider: Some(ColorProviderCapability::Simple(true)),
             folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
             selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
@@ -132,10 +307,30 @@ impl JsonLanguageServer {
                 work_done_progress_options: Default::default(),
             }),
             definition_provider: Some(OneOf::Left(true)),
+            code_action_provider: Some(CodeActionProviderCapability::Options(CodeActionOptions {
+                code_action_kinds: Some(vec![CodeActionKind::QUICKFIX]),
+                work_done_progress_options: Default::default(),
+                resolve_provider: Some(false),
+            })),
             execute_command_provider: Some(ExecuteCommandOptions {
                 commands: vec!["json.sort".into()],
                 work_done_progress_options: Default::default(),
             }),
+            workspace: Some(WorkspaceServerCapabilities {
+                workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                    supported: Some(true),
+                    change_notifications: Some(OneOf::Left(true)),
+                }),
+                file_operations: None,
+            }),
+            diagnostic_provider: matches!(mode, DiagnosticMode::Pull { .. }).then(|| {
+                DiagnosticServerCapabilities::Options(DiagnosticOptions {
+                    identifier: Some("json".into()),
+                    inter_file_dependencies: false,
+                    workspace_diagnostics: true,
+                    work_done_progress_options: Default::default(),
+                })
+            }),
             ..ServerCapabilities::default()
         }
     }
@@ -145,13 +340,30 @@ impl JsonLanguageServer {
     // -----------------------------------------------------------------------
 
     fn main_loop(&self) {
-        for msg in &self.connection.receiver {
+        // A reader thread sees cancellations and edits while the main loop
+        // is busy with a request, and forwards everything else.
+        let (tx, rx) = crossbeam_channel::unbounded();
+        {
+            let receiver = self.connection.receiver.clone();
+            let shared = Arc::clone(&self.shared);
+            std::thread::Builder::new()
+                .name("reader".into())
+                .spawn(move || read_messages(receiver, tx, shared))
+                .expect("failed to spawn message reader");
+        }
+
+        for msg in &rx {
             match msg {
                 Message::Request(req) => {
                     if self.connection.handle_shutdown(&req).unwrap_or(true) {
                         return;
                     }
-                    self.dispatch_request(req);
+                    let id = req.id.clone();
+                    match self.shared.requests.token(&id).reason() {
+                        Some(reason) => self.send_cancelled(id.clone(), reason),
+                        None => self.dispatch_request(req),
+                    }
+                    self.shared.requests.dispatched(&id);
                 }
                 Message::Notification(not) => {
                     self.dispatch_notification(not);
@@ -202,6 +414,11 @@ impl JsonLanguageServer {
             Err(ExtractError::MethodMismatch(req)) => req,
             Err(ExtractError::JsonError { .. }) => return,
         };
+        let req = match cast::<request::OnTypeFormatting>(req) {
+            Ok((id, params)) => return self.on_type_formatting(id, params),
+            Err(ExtractError::MethodMismatch(req)) => req,
+            Err(ExtractError::JsonError { .. }) => return,
+        };
         let req = match cast::<request::DocumentColor>(req) {
             Ok((id, params)) => return self.on_document_color(id, params),
             Err(ExtractError::MethodMismatch(req)) => req,
@@ -232,6 +449,21 @@ impl JsonLanguageServer {
             Err(ExtractError::MethodMismatch(req)) => req,
             Err(ExtractError::JsonError { .. }) => return,
         };
+        let req = match cast::<request::CodeActionRequest>(req) {
+            Ok((id, params)) => return self.on_code_action(id, params),
+            Err(ExtractError::MethodMismatch(req)) => req,
+            Err(ExtractError::JsonError { .. }) => return,
+        };
+        let req = match cast::<request::DocumentDiagnosticRequest>(req) {
+            Ok((id, params)) => return self.on_document_diagnostic(id, params),
+            Err(ExtractError::MethodMismatch(req)) => req,
+            Err(ExtractError::JsonError { .. }) => return,
+        };
+        let req = match cast::<request::WorkspaceDiagnosticRequest>(req) {
+            Ok((id, params)) => return self.on_workspace_diagnostic(id, params),
+            Err(ExtractError::MethodMismatch(req)) => req,
+            Err(ExtractError::JsonError { .. }) => return,
+        };
         match cast::<request::ExecuteCommand>(req) {
             Ok((id, params)) => return self.on_execute_command(id, params),
             Err(ExtractError::MethodMismatch(_req)) => {}
@@ -270,8 +502,18 @@ impl JsonLanguageServer {
             Err(ExtractError::MethodMismatch(not)) => not,
             Err(ExtractError::JsonError { .. }) => return,
         };
-        match cast::<notification::DidChangeConfiguration>(not) {
+        let not = match cast::<notification::DidChangeConfiguration>(not) {
             Ok(params) => return self.on_did_change_configuration(params),
+            Err(ExtractError::MethodMismatch(not)) => not,
+            Err(ExtractError::JsonError { .. }) => return,
+        };
+        let not = match cast::<notification::DidChangeWorkspaceFolders>(not) {
+            Ok(params) => return self.on_did_change_workspace_folders(params),
+            Err(ExtractError::MethodMismatch(not)) => not,
+            Err(ExtractError::JsonError { .. }) => return,
+        };
+        match cast::<notification::DidChangeWatchedFiles>(not) {
+            Ok(params) => return self.on_did_change_watched_files(params),
             Err(ExtractError::MethodMismatch(_not)) => {}
             Err(ExtractError::JsonError { .. }) => return,
         };
@@ -286,12 +528,46 @@ impl JsonLanguageServer {
         self.connection.sender.send(Message::Response(resp)).ok();
     }
 
+    /// Answer a request whose work was cancelled.
+    fn send_cancelled(&self, id: RequestId, reason: CancelReason) {
+        let resp = cancelled_response(id, reason);
+        self.connection.sender.send(Message::Response(resp)).ok();
+    }
+
+    /// Answer a diagnostic pull on the pull pool, so that slow work such as
+    /// validating every workspace file doesn't hold up the main loop. Pulls
+    /// for the same `document` (`None` for the workspace) run one at a time.
+    /// The request stays cancellable until it is answered.
+    fn answer_in_background<T, F>(&self, id: RequestId, document: Option<Uri>, work: F)
+    where
+        T: serde::Serialize,
+        F: FnOnce(&Shared, &CancelToken) -> Result<T, Cancelled> + Send + 'static,
+    {
+        let cancel = self.shared.requests.token(&id);
+        self.shared.requests.detach(&id);
+        self.shared.pulls.schedule(
+            document,
+            Box::new(move |shared| {
+                let resp = match work(shared, &cancel) {
+                    Ok(result) => Response::new_ok(id.clone(), result),
+                    Err(Cancelled) => {
+                        let reason = cancel.reason().unwrap_or(CancelReason::Requested);
+                        cancelled_response(id.clone(), reason)
+                    }
+                };
+                shared.client.send(Message::Response(resp)).ok();
+                shared.requests.finish(&id);
+            }),
+        );
+    }
+
     fn send_notification<N: notification::Notification>(&self, params: N::Params) {
         let not = Notification::new(N::METHOD.into(), params);
         self.connection.sender.send(Message::Notification(not)).ok();
     }
 
-    /// Resolve a schema for a document, fetching over HTTP if needed.
+    /// Resolve a schema for a document. A schema that still has to be
+    /// fetched is queued in the background and `None` is returned for now.
     fn resolve_schema(
         &self,
         doc_uri: &str,
@@ -303,27 +579,16 @@ impl JsonLanguageServer {
                 .schemas
                 .schema_for_document(doc_uri, inline_schema_uri)
         };
-
-        match lookup {
-            SchemaLookup::Resolved(schema) => Some(schema),
-            SchemaLookup::NeedsFetch(uri) => {
-                let agent = self.shared.state.write().schemas.http_agent();
-                let raw = resolver::fetch_schema(&agent, &uri)?;
-                let schema = JsonSchema::from_value(&raw);
-                self.shared
-                    .state
-                    .write()
-                    .schemas
-                    .insert_cache(uri, schema.clone());
-                Some(schema)
-            }
-            SchemaLookup::None => None,
-        }
+        fetch_if_missing(&self.shared, lookup)
     }
 
-    /// Schedule validation on the worker thread.
-    fn schedule_validate(&self, uri: Uri) {
-        self.shared.validate_tx.send(uri).ok();
+    /// Schedule validation on the worker thread. Documents the user just
+    /// opened or edited jump the queue. Clients that pull diagnostics ask
+    /// for them instead.
+    fn schedule_validate(&self, uri: Uri, priority: bool) {
+        if self.shared.diagnostic_mode() == DiagnosticMode::Push {
+            self.shared.validations.schedule(uri, priority);
+        }
     }
 
     // -----------------------------------------------------------------------
@@ -341,7 +606,7 @@ impl JsonLanguageServer {
                 params.text_document.version,
             );
         }
-        self.schedule_validate(uri);
+        self.schedule_validate(uri, true);
     }
 
     fn on_did_change(&self, params: DidChangeTextDocumentParams) {
@@ -362,13 +627,13 @@ impl JsonLanguageServer {
                 }
             }
         }
-        self.schedule_validate(uri);
+        self.schedule_validate(uri, true);
     }
 
     fn on_did_save(&self, params: DidSaveTextDocumentParams) {
         let uri = params.text_document.uri;
         debug!("did_save: {}", uri.as_str());
-        self.schedule_validate(uri);
+        self.schedule_validate(uri, false);
     }
 
     fn on_did_close(&self, params: DidCloseTextDocumentParams) {
@@ -376,9 +641,28 @@ impl JsonLanguageServer {
         {
             let mut state = self.shared.state.write();
             state.documents.close(&params.text_document.uri);
+            self.shared.validations.forget(&params.text_document.uri);
+            state
+                .schemas
+                .unpin_document(params.text_document.uri.as_str());
+        }
+        if self.shared.diagnostic_mode() != DiagnosticMode::Push {
+            return;
+        }
+        // A workspace file gets the diagnostics of its content on disk.
+        let uri = params.text_document.uri;
+        if self
+            .shared
+            .state
+            .read()
+            .workspace_files
+            .revision(&uri)
+            .is_some()
+        {
+            return self.schedule_validate(uri, false);
         }
         self.send_notification::<notification::PublishDiagnostics>(PublishDiagnosticsParams {
-            uri: params.text_document.uri,
+            uri,
             diagnostics: Vec::new(),
             version: None,
         });
@@ -397,23 +681,52 @@ impl JsonLanguageServer {
                 serde_json::to_string(&params.settings).unwrap_or_default()
             ),
         });
-        if let Some(schemas) = params
+        let Some(json) = params
             .settings
             .as_object()
             .and_then(|o| o.get("json"))
             .and_then(|v| v.as_object())
-            .and_then(|o| o.get("schemas"))
-            .and_then(|v| v.as_array())
-        {
+        else {
+            return;
+        };
+
+        // `json.schemaStore.enable` / `json.schemaStore.catalog`: the catalog
+        // consulted when no `json.schemas` entry matches. Read before taking
+        // the lock since a local catalog comes from disk.
+        let catalog = json.get("schemaStore").map(|store| {
+            let enabled = store.get("enable").and_then(|v| v.as_bool()) != Some(false);
+            let path = store.get("catalog").and_then(|v| v.as_str());
+            match path {
+                _ if !enabled => None,
+                Some(path) if !path.is_empty() => Catalog::load(Path::new(path))
+                    .map(Arc::new)
+                    .or_else(|| Some(Catalog::bundled())),
+                _ => Some(Catalog::bundled()),
+            }
+        });
+
+        let mut state = self.shared.state.write();
+        let mut changed = false;
+
+        if let Some(catalog) = catalog {
+            state.schemas.set_catalog(catalog);
+            changed = true;
+        }
+
+        if let Some(schemas) = json.get("schemas").and_then(|v| v.as_array()) {
             let associations: Vec<SchemaAssociation> = schemas
                 .iter()
-                .filter_map(|entry| {
+                .enumerate()
+                .filter_map(|(i, entry)| {
                     let obj = entry.as_object()?;
-                    let uri = obj
-                        .get("url")
-                        .or_else(|| obj.get("uri"))?
-                        .as_str()?
-                        .to_string();
+                    let inline = obj.get("schema").filter(|v| v.is_object());
+                    // Inline schemas without a URL get a synthetic one so
+                    // relative refs and pinning still have a base.
+                    let uri = match obj.get("url").or_else(|| obj.get("uri")) {
+                        Some(uri) => uri.as_str()?.to_string(),
+                        None if inline.is_some() => format!("inmemory://schemas/settings/{i}"),
+                        None => return None,
+                    };
                     let file_match: Vec<String> = obj
                         .get("fileMatch")?
                         .as_array()?
@@ -422,24 +735,156 @@ impl JsonLanguageServer {
                         .collect();
                     Some(SchemaAssociation {
                         file_match,
+                        schema: inline.map(|raw| resolver::compile_schema(&uri, raw)),
                         uri,
-                        schema: None,
+                        folder_uri: obj
+                            .get("folderUri")
+                            .and_then(|v| v.as_str())
+                            .map(String::from),
                     })
                 })
                 .collect();
 
-            let mut state = self.shared.state.write();
             state.schemas.clear_cache();
             state.schemas.set_associations(associations);
+            changed = true;
+        }
+
+        let validate = json.get("validate");
+
+        // `json.validate.format`: "auto" | "annotation" | "assertion".
+        if let Some(mode) = validate
+            .and_then(|v| v.get("format"))
+            .and_then(|v| v.as_str())
+        {
+            match FormatMode::from_setting(mode) {
+                Some(mode) => {
+                    changed |= state.validation.format_mode != mode;
+                    state.validation.format_mode = mode;
+                }
+                None => warn!("unknown json.validate.format value: {mode}"),
+            }
+        }
+
+        // `json.validate.workspace`: also validate files that aren't open.
+        let mut rescan = false;
+        if let Some(workspace) = validate.and_then(|v| v.get("workspace")) {
+            let settings = WorkspaceSettings::from_settings(workspace);
+            rescan = state.workspace != settings;
+            state.workspace = settings;
+        }
+
+        // `json.validate.strict`: report trailing commas and comments.
+        if let Some(strict) = validate
+            .and_then(|v| v.get("strict"))
+            .and_then(|v| v.as_bool())
+        {
+            changed |= state.validation.strict != strict;
+            state.validation.strict = strict;
+        }
+
+        // `json.format.*`: printWidth, keepLines, spaceBeforeColon,
+        // insertFinalNewline.
+        if let Some(format) = json.get("format").filter(|v| v.is_object()) {
+            state.format = FormatSettings::from_settings(format);
+        }
+
+        // `json.sort.order`: "alphabetical" | "schema".
+        if let Some(order) = json
+            .get("sort")
+            .and_then(|v| v.get("order"))
+            .and_then(|v| v.as_str())
+        {
+            match SortOrder::from_setting(order) {
+                Some(order) => state.sort_order = order,
+                None => warn!("unknown json.sort.order value: {order}"),
+            }
+        }
+
+        // Re-validate all documents so that files opened before
+        // configuration arrived get schema diagnostics immediately.
+        let uris = changed.then(|| diagnosed_documents(&state));
+        drop(state);
+        if let Some(uris) = uris {
+            self.shared.invalidate_diagnostics(uris);
+        }
+        if rescan {
+            start_workspace_scan(&self.shared);
+        }
+    }
+
+    fn on_did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        debug!("did_change_workspace_folders");
+        let mut state = self.shared.state.write();
+        let mut folders = state.schemas.workspace_folders().to_vec();
+        for removed in &params.event.removed {
+            let removed = removed.uri.as_str().trim_end_matches('/');
+            folders.retain(|f| f != removed);
+        }
+        folders.extend(
+            params
+                .event
+                .added
+                .iter()
+                .map(|f| f.uri.as_str().to_string()),
+        );
+        state.schemas.set_workspace_folders(folders);
+
+        // Relative patterns may now match differently.
+        let uris = diagnosed_documents(&state);
+        let rescan = state.workspace.enable;
+        drop(state);
+        self.shared.invalidate_diagnostics(uris);
+        if rescan {
+            start_workspace_scan(&self.shared);
+        }
+    }
+
+    fn on_did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        debug!("did_change_watched_files: {} changes", params.changes.len());
+        let (settings, folders) = {
+            let state = self.shared.state.read();
+            if !state.workspace.enable {
+                return;
+            }
+            let folders: Vec<_> = state
+                .schemas
+                .workspace_folders()
+                .iter()
+                .filter_map(|f| workspace::uri_to_path(f))
+                .collect();
+            (state.workspace.clone(), folders)
+        };
+        // A changed .gitignore can affect any file.
+        if params
+            .changes
+            .iter()
+            .any(|c| c.uri.as_str().ends_with("/.gitignore"))
+        {
+            return start_workspace_scan(&self.shared);
+        }
 
-            // Re-validate all open documents so that files opened before
-            // configuration arrived get schema diagnostics immediately.
-            let uris: Vec<Uri> = state.documents.uris().cloned().collect();
-            drop(state);
-            for uri in uris {
-                self.schedule_validate(uri);
+        let scanner = WorkspaceScanner::new(&settings);
+        let mut changed = Vec::new();
+        let mut removed = Vec::new();
+        {
+            let mut state = self.shared.state.write();
+            for change in params.changes {
+                let uri = change.uri;
+                let Some(path) = workspace::uri_to_path(uri.as_str()) else {
+                    continue;
+                };
+                let accepted = change.typ != FileChangeType::DELETED
+                    && folders.iter().any(|root| scanner.accepts(root, &path));
+                if accepted {
+                    state.workspace_files.touch(uri.clone());
+                    changed.push(uri);
+                } else if state.workspace_files.remove(&uri) && !is_open(&state, &uri) {
+                    removed.push(uri);
+                }
             }
         }
+        self.shared.workspace_files_changed(changed, removed);
     }
 
     // -----------------------------------------------------------------------
@@ -470,7 +915,7 @@ impl JsonLanguageServer {
             None => return self.send_response(id, Option::<Hover>::None),
         };
 
-        let result = hover::hover(doc, offset, schema.as_ref());
+        let result = hover::hover(doc, offset, schema.as_ref(), &state.schemas);
         self.send_response(id, result);
     }
 
@@ -502,7 +947,7 @@ impl JsonLanguageServer {
             None => return self.send_response(id, Option::<CompletionResponse>::None),
         };
 
-        let items = completion::completions(doc, offset, schema.as_ref());
+        let items = completion::completions(doc, offset, schema.as_ref(), &state.schemas);
         let result = if items.is_empty() {
             None
         } else {
@@ -524,8 +969,11 @@ impl JsonLanguageServer {
         };
         // Write symbols directly into the JSON-RPC envelope buffer —
         // avoids a second allocation + memcpy of the entire result.
-        let mut buf = Response::start_preserialized(id);
-        crate::symbols::write_document_symbols(doc, &mut buf);
+        let cancel = self.shared.requests.token(&id);
+        let mut buf = Response::start_preserialized(id.clone());
+        if crate::symbols::write_document_symbols_with_cancel(doc, &mut buf, &cancel).is_err() {
+            return self.send_cancelled(id, cancel.reason().unwrap_or(CancelReason::Requested));
+        }
         let msg = Response::finish_preserialized(buf);
         self.connection.sender.send(msg).ok();
     }
@@ -541,7 +989,7 @@ impl JsonLanguageServer {
             Some(d) => d,
             None => return self.send_response(id, Option::<Vec<TextEdit>>::None),
         };
-        let result = formatting::format_document(doc, &params.options);
+        let result = formatting::format_document(doc, &params.options, &state.format);
         self.send_response(id, Some(result));
     }
 
@@ -552,7 +1000,24 @@ impl JsonLanguageServer {
             Some(d) => d,
             None => return self.send_response(id, Option::<Vec<TextEdit>>::None),
         };
-        let result = formatting::format_range(doc, params.range, &params.options);
+        let result = formatting::format_range(doc, params.range, &params.options, &state.format);
+        self.send_response(id, Some(result));
+    }
+
+    fn on_type_formatting(&self, id: RequestId, params: DocumentOnTypeFormattingParams) {
+        let uri = &params.text_document_position.text_document.uri;
+        let state = self.shared.state.read();
+        let doc = match state.documents.get(uri) {
+            Some(d) => d,
+            None => return self.send_response(id, Option::<Vec<TextEdit>>::None),
+        };
+        let result = formatting::format_on_type(
+            doc,
+            params.text_document_position.position,
+            &params.ch,
+            &params.options,
+            &state.format,
+        );
         self.send_response(id, Some(result));
     }
 
@@ -646,26 +1111,85 @@ impl JsonLanguageServer {
         self.send_response(id, result);
     }
 
+    // -----------------------------------------------------------------------
+    // Code actions
+    // -----------------------------------------------------------------------
+
+    fn on_code_action(&self, id: RequestId, params: CodeActionParams) {
+        let uri = &params.text_document.uri;
+        let state = self.shared.state.read();
+        let doc = match state.documents.get(uri) {
+            Some(d) => d,
+            None => return self.send_response(id, Option::<CodeActionResponse>::None),
+        };
+
+        let actions: CodeActionResponse =
+            code_actions::code_actions(doc, uri, &params.context.diagnostics)
+                .into_iter()
+                .map(CodeActionOrCommand::CodeAction)
+                .collect();
+        self.send_response(id, actions);
+    }
+
+    // -----------------------------------------------------------------------
+    // Pull diagnostics
+    // -----------------------------------------------------------------------
+
+    fn on_document_diagnostic(&self, id: RequestId, params: DocumentDiagnosticParams) {
+        let document = params.text_document.uri.clone();
+        self.answer_in_background(id, Some(document), move |shared, cancel| {
+            document_diagnostic_report(shared, params, cancel)
+        });
+    }
+
+    fn on_workspace_diagnostic(&self, id: RequestId, params: WorkspaceDiagnosticParams) {
+        self.answer_in_background(id, None, move |shared, cancel| {
+            workspace_diagnostic_report(shared, params, cancel)
+        });
+    }
+
     // -----------------------------------------------------------------------
     // Execute command (sort)
     // -----------------------------------------------------------------------
 
+    /// The schema that orders keys for `json.sort`, if sorting by schema.
+    fn sort_schema(&self, uri: &Uri) -> Option<Arc<JsonSchema>> {
+        let inline_schema = {
+            let state = self.shared.state.read();
+            if state.sort_order != SortOrder::Schema {
+                return None;
+            }
+            resolver::extract_schema_property(state.documents.get(uri)?)
+        };
+        self.resolve_schema(uri.as_str(), inline_schema.as_deref())
+    }
+
     fn on_execute_command(&self, id: RequestId, params: ExecuteCommandParams) {
         match params.command.as_str() {
             "json.sort" => {
+                let cancel = self.shared.requests.token(&id);
                 let edit = {
                     if let Some(uri_val) = params.arguments.first()
                         && let Some(uri_str) = uri_val.as_str()
                         && let Ok(uri) = Uri::from_str(uri_str)
                     {
+                        let schema = self.sort_schema(&uri);
                         let state = self.shared.state.read();
                         if let Some(doc) = state.documents.get(&uri) {
-                            let edits = formatting::sort_document(doc);
+                            let Ok(edits) = formatting::sort_document_with_cancel(
+                                doc,
+                                schema.as_ref(),
+                                &state.schemas,
+                                &state.format,
+                                &cancel,
+                            ) else {
+                                drop(state);
+                                let reason = cancel.reason().unwrap_or(CancelReason::Requested);
+                                return self.send_cancelled(id, reason);
+                            };
                             if !edits.is_empty() {
-                                let mut changes = std::collections::HashMap::new();
-                                changes.insert(uri, edits);
                                 Some(WorkspaceEdit {
-                                    changes: Some(changes),
+                                    changes: Some(HashMap::from([(uri, edits)])),
                                     ..Default::default()
                                 })
                             } else {
@@ -702,104 +1226,780 @@ impl JsonLanguageServer {
 }
 
 // ---------------------------------------------------------------------------
-// Single validation worker thread
+// Workspace validation
 // ---------------------------------------------------------------------------
 
-/// Long-lived worker that processes validation requests sequentially.
-/// When a request arrives, all queued requests are drained and only the
-/// last URI is validated — the worker being busy plus a short sleep
-/// provide natural throttling for rapid keystrokes.
-fn validation_worker(rx: Receiver<Uri>, shared: Arc<Shared>, sender: Sender<Message>) {
-    while let Ok(mut uri) = rx.recv() {
-        // Drain any queued requests, keeping only the latest URI.
-        while let Ok(newer) = rx.try_recv() {
-            uri = newer;
+/// Documents whose diagnostics are reported: the open documents and the
+/// workspace files.
+fn diagnosed_documents(state: &ServerState) -> Vec<Uri> {
+    let mut uris: Vec<Uri> = state.documents.uris().cloned().collect();
+    let open: HashSet<String> = uris.iter().map(uri_key).collect();
+    uris.extend(
+        state
+            .workspace_files
+            .uris()
+            .filter(|uri| !open.contains(&uri_key(uri)))
+            .cloned(),
+    );
+    uris
+}
+
+/// Whether the file `uri` names is open, possibly under a differently
+/// escaped URI.
+fn is_open(state: &ServerState, uri: &Uri) -> bool {
+    if state.documents.get(uri).is_some() {
+        return true;
+    }
+    let key = uri_key(uri);
+    state.documents.uris().any(|open| uri_key(open) == key)
+}
+
+/// Identifies the file a URI names: the decoded path of `file` URIs, the
+/// URI itself otherwise.
+fn uri_key(uri: &Uri) -> String {
+    match workspace::uri_to_path(uri.as_str()) {
+        Some(path) => path.to_string_lossy().into_owned(),
+        None => uri.as_str().to_string(),
+    }
+}
+
+fn start_workspace_scan(shared: &Arc<Shared>) {
+    let shared = Arc::clone(shared);
+    std::thread::Builder::new()
+        .name("workspace-scan".into())
+        .spawn(move || scan_workspace(&shared))
+        .expect("failed to spawn workspace scan");
+}
+
+/// Find the files to validate in every workspace folder (none when
+/// workspace validation is off). Found files are revalidated, and files no
+/// longer found lose their diagnostics.
+fn scan_workspace(shared: &Shared) {
+    let _scan = shared.scan_lock.lock();
+    let (settings, folders) = {
+        let state = shared.state.read();
+        let folders = state.schemas.workspace_folders().to_vec();
+        (state.workspace.clone(), folders)
+    };
+    watch_workspace_files(shared, &settings);
+
+    let mut found = Vec::new();
+    if settings.enable {
+        let scanner = WorkspaceScanner::new(&settings);
+        for root in folders.iter().filter_map(|f| workspace::uri_to_path(f)) {
+            let limit = workspace::MAX_FILES - found.len();
+            let files = scanner.scan(&root, limit);
+            found.extend(files.iter().filter_map(|path| workspace::path_to_uri(path)));
+        }
+        if found.len() >= workspace::MAX_FILES {
+            warn!(
+                "workspace validation stopped after {} files",
+                workspace::MAX_FILES
+            );
         }
-        validate_and_publish(&uri, &shared.state, &shared.regex_cache, &sender);
-        std::thread::sleep(std::time::Duration::from_millis(50));
+        info!("workspace scan found {} files", found.len());
+    }
+
+    let removed = {
+        let mut state = shared.state.write();
+        let removed = state.workspace_files.replace(found.clone());
+        removed
+            .into_iter()
+            .filter(|uri| !is_open(&state, uri))
+            .collect()
+    };
+    shared.workspace_files_changed(found, removed);
+}
+
+/// Have the client report changes to the files workspace validation covers
+/// and to `.gitignore` files, replacing any earlier registration.
+fn watch_workspace_files(shared: &Shared, settings: &WorkspaceSettings) {
+    const REGISTRATION_ID: &str = "workspace-files";
+    if shared.file_watching.get() != Some(&true) {
+        return;
+    }
+    let globs: Vec<String> = if settings.enable {
+        settings
+            .include
+            .iter()
+            .filter(|p| !p.starts_with('!'))
+            .map(|p| match p.strip_prefix('/') {
+                // Anchored patterns are checked when the change arrives.
+                Some(p) => format!("**/{p}"),
+                None if p.starts_with("**/") => p.clone(),
+                None => format!("**/{p}"),
+            })
+            .chain(["**/.gitignore".to_string()])
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut watching = shared.watching.lock();
+    if *watching == globs {
+        return;
+    }
+    if !watching.is_empty() {
+        shared.send_request::<request::UnregisterCapability>(UnregistrationParams {
+            unregisterations: vec![Unregistration {
+                id: REGISTRATION_ID.into(),
+                method: notification::DidChangeWatchedFiles::METHOD.into(),
+            }],
+        });
+    }
+    if !globs.is_empty() {
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: globs
+                .iter()
+                .map(|glob| FileSystemWatcher {
+                    glob_pattern: GlobPattern::String(glob.clone()),
+                    kind: None,
+                })
+                .collect(),
+        };
+        shared.send_request::<request::RegisterCapability>(RegistrationParams {
+            registrations: vec![Registration {
+                id: REGISTRATION_ID.into(),
+                method: notification::DidChangeWatchedFiles::METHOD.into(),
+                register_options: serde_json::to_value(options).ok(),
+            }],
+        });
+    }
+    *watching = globs;
+}
+
+/// Workspace folder URIs from the initialize request, falling back to the
+/// deprecated `rootUri` for clients without workspace folder support.
+fn workspace_folders(params: &InitializeParams) -> Vec<String> {
+    if let Some(folders) = &params.workspace_folders {
+        return folders.iter().map(|f| f.uri.as_str().to_string()).collect();
     }
+    #[allow(deprecated)]
+    params
+        .root_uri
+        .iter()
+        .map(|uri| uri.as_str().to_string())
+        .collect()
 }
 
 // ---------------------------------------------------------------------------
-// Free function for validation
+// Message reader and cancellation
 // ---------------------------------------------------------------------------
 
-fn validate_and_publish(
-    uri: &Uri,
-    state: &RwLock<ServerState>,
-    regex_cache: &Mutex<RegexCache>,
-    sender: &Sender<Message>,
-) {
-    // Single read-lock snapshot: extract everything we need for schema lookup
-    // and syntax diagnostics in one pass.
-    let (mut diags, version, uri_str, inline_schema) = {
-        let state = state.read();
-        let doc = match state.documents.get(uri) {
-            Some(d) => d,
-            None => return,
-        };
-        let diags = diagnostics::syntax_diagnostics(doc);
-        let version = Some(doc.version);
-        let uri_str = uri.as_str().to_string();
-        let inline_schema = crate::schema::resolver::extract_schema_property(doc);
-        (diags, version, uri_str, inline_schema)
-    };
+/// Requests received but not yet answered, with their cancellation tokens
+/// and the document each one is about.
+#[derive(Default)]
+struct PendingRequests {
+    tokens: Mutex<HashMap<RequestId, PendingRequest>>,
+}
 
-    // Resolve the schema. This re-acquires the read lock (and possibly the
-    // write lock for HTTP fetch), but only for the schema store lookup.
-    let lookup = {
-        let state = state.read();
-        state
-            .schemas
-            .schema_for_document(&uri_str, inline_schema.as_deref())
-    };
-    let schema = match lookup {
-        SchemaLookup::Resolved(schema) => Some(schema),
-        SchemaLookup::NeedsFetch(fetch_uri) => {
-            let agent = state.write().schemas.http_agent();
-            let raw = resolver::fetch_schema(&agent, &fetch_uri);
-            raw.map(|r| {
-                let schema = JsonSchema::from_value(&r);
-                state
-                    .write()
-                    .schemas
-                    .insert_cache(fetch_uri, schema.clone());
-                schema
+struct PendingRequest {
+    token: CancelToken,
+    document: Option<Uri>,
+    /// Answered by another thread after dispatch returns.
+    detached: bool,
+}
+
+impl PendingRequests {
+    fn register(&self, req: &Request) {
+        let document = req
+            .params
+            .pointer("/textDocument/uri")
+            .or_else(|| {
+                // `json.sort` takes the document URI as its argument.
+                (req.method == request::ExecuteCommand::METHOD)
+                    .then(|| req.params.pointer("/arguments/0"))
+                    .flatten()
             })
+            .and_then(|v| v.as_str())
+            .and_then(|uri| Uri::from_str(uri).ok());
+        let pending = PendingRequest {
+            token: CancelToken::new(),
+            document,
+            detached: false,
+        };
+        self.tokens.lock().insert(req.id.clone(), pending);
+    }
+
+    /// The token of a pending request (a fresh one if it is unknown).
+    fn token(&self, id: &RequestId) -> CancelToken {
+        self.tokens
+            .lock()
+            .get(id)
+            .map(|pending| pending.token.clone())
+            .unwrap_or_default()
+    }
+
+    fn cancel(&self, id: &RequestId) {
+        if let Some(pending) = self.tokens.lock().get(id) {
+            pending.token.cancel(CancelReason::Requested);
         }
-        SchemaLookup::None => None,
+    }
+
+    /// Cancel every pending request about a document that just changed.
+    fn content_modified(&self, uri: &Uri) {
+        for pending in self.tokens.lock().values() {
+            if pending.document.as_ref() == Some(uri) {
+                pending.token.cancel(CancelReason::ContentModified);
+            }
+        }
+    }
+
+    /// Keep a request pending after dispatch returns; whoever answers it
+    /// calls `finish`.
+    fn detach(&self, id: &RequestId) {
+        if let Some(pending) = self.tokens.lock().get_mut(id) {
+            pending.detached = true;
+        }
+    }
+
+    /// The main loop dispatched a request: forget it unless it was detached.
+    fn dispatched(&self, id: &RequestId) {
+        let mut tokens = self.tokens.lock();
+        if tokens.get(id).is_some_and(|pending| !pending.detached) {
+            tokens.remove(id);
+        }
+    }
+
+    fn finish(&self, id: &RequestId) {
+        self.tokens.lock().remove(id);
+    }
+}
+
+/// The error response to a request whose work was cancelled.
+fn cancelled_response(id: RequestId, reason: CancelReason) -> Response {
+    let (code, message) = match reason {
+        CancelReason::Requested => (ErrorCode::RequestCanceled, "request cancelled"),
+        CancelReason::ContentModified => (ErrorCode::ContentModified, "content modified"),
     };
+    Response::new_err(id, code as i32, message.into())
+}
 
-    // Run schema validation if syntax is clean.
-    if let Some(schema) = schema {
-        if diags.is_empty() {
-            let state = state.read();
-            if let Some(doc) = state.documents.get(uri) {
-                if let Some(root) = tree::root_value(&doc.tree) {
-                    let mut regex_cache = regex_cache.lock();
-                    let val_errors =
-                        validation::validate(root, doc.source(), &schema, &mut regex_cache);
-                    for ve in &val_errors {
-                        diags.push(lsp_types::Diagnostic {
-                            range: doc.range_of(ve.start_byte, ve.end_byte),
-                            severity: Some(match ve.severity {
-                                validation::Severity::Error => DiagnosticSeverity::ERROR,
-                                validation::Severity::Warning => DiagnosticSeverity::WARNING,
-                            }),
-                            source: Some("json".into()),
-                            message: ve.message.clone(),
-                            ..lsp_types::Diagnostic::default()
-                        });
-                    }
+/// Forward client messages to the main loop. Cancellation takes effect
+/// here, even while the main loop is busy: `$/cancelRequest` flags the
+/// request's token, and an edit to a document cancels the requests and the
+/// validation working on its old content. Stops after `shutdown`, so that
+/// the main loop reads the `exit` notification itself.
+fn read_messages(rx: Receiver<Message>, tx: Sender<Message>, shared: Arc<Shared>) {
+    for msg in rx {
+        let mut last = false;
+        match &msg {
+            Message::Request(req) => {
+                shared.requests.register(req);
+                last = req.method == request::Shutdown::METHOD;
+            }
+            Message::Notification(not) if not.method == notification::Cancel::METHOD => {
+                if let Ok(params) = serde_json::from_value::<CancelParams>(not.params.clone()) {
+                    let id = match params.id {
+                        NumberOrString::Number(n) => RequestId::from(n),
+                        NumberOrString::String(s) => RequestId::from(s),
+                    };
+                    shared.requests.cancel(&id);
+                }
+                continue;
+            }
+            Message::Notification(not)
+                if not.method == notification::DidChangeTextDocument::METHOD
+                    || not.method == notification::DidCloseTextDocument::METHOD =>
+            {
+                if let Some(uri) = not
+                    .params
+                    .pointer("/textDocument/uri")
+                    .and_then(|v| v.as_str())
+                    .and_then(|uri| Uri::from_str(uri).ok())
+                {
+                    shared.requests.content_modified(&uri);
+                    shared.validations.cancel_running(&uri);
                 }
             }
+            _ => {}
+        }
+        if tx.send(msg).is_err() || last {
+            return;
         }
     }
+}
 
-    let params = PublishDiagnosticsParams {
-        uri: uri.clone(),
-        diagnostics: diags,
-        version,
+// ---------------------------------------------------------------------------
+// Validation worker pool
+// ---------------------------------------------------------------------------
+
+const MAX_VALIDATION_THREADS: usize = 8;
+
+fn validation_threads() -> usize {
+    std::thread::available_parallelism()
+        .map_or(2, |n| n.get())
+        .min(MAX_VALIDATION_THREADS)
+}
+
+/// Documents waiting for validation. Each URI is queued at most once, so a
+/// burst of edits to one document coalesces into a single pass while every
+/// other dirty document keeps its place. A document is handed to one worker
+/// at a time, so its diagnostics are published in order.
+struct ValidationQueue {
+    state: Mutex<PendingValidations>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct PendingValidations {
+    queue: VecDeque<Uri>,
+    queued: HashSet<Uri>,
+    /// Documents a worker is validating right now.
+    running: HashMap<Uri, CancelToken>,
+    /// Bumped whenever something besides document text changes (settings,
+    /// schemas), so documents are revalidated even at the same version.
+    generation: u64,
+    /// Document version and generation of the last published diagnostics.
+    published: HashMap<Uri, (i32, u64)>,
+}
+
+impl ValidationQueue {
+    fn new() -> Self {
+        ValidationQueue {
+            state: Mutex::new(PendingValidations::default()),
+            ready: Condvar::new(),
+        }
+    }
+
+    /// Queue a document. With `priority` it moves to the front.
+    fn schedule(&self, uri: Uri, priority: bool) {
+        let mut state = self.state.lock();
+        if priority {
+            if !state.queued.insert(uri.clone()) {
+                state.queue.retain(|u| *u != uri);
+            }
+            state.queue.push_front(uri);
+        } else if state.queued.insert(uri.clone()) {
+            state.queue.push_back(uri);
+        }
+        self.ready.notify_one();
+    }
+
+    /// Revalidate documents regardless of their version, after a change
+    /// that affects their diagnostics.
+    fn invalidate(&self, uris: impl IntoIterator<Item = Uri>) {
+        let mut state = self.state.lock();
+        state.generation += 1;
+        for uri in uris {
+            if state.queued.insert(uri.clone()) {
+                state.queue.push_back(uri);
+            }
+        }
+        self.ready.notify_all();
+    }
+
+    /// Drop a closed document, so it is validated again when reopened.
+    fn forget(&self, uri: &Uri) {
+        let mut state = self.state.lock();
+        if state.queued.remove(uri) {
+            state.queue.retain(|u| u != uri);
+        }
+        state.published.remove(uri);
+    }
+
+    /// Block until a document that no other worker is validating is
+    /// queued. Returns it with the current generation and a token that is
+    /// cancelled if the document changes meanwhile; the caller must
+    /// `finish` it.
+    fn next(&self) -> (Uri, u64, CancelToken) {
+        let mut state = self.state.lock();
+        loop {
+            let pending = &mut *state;
+            if let Some(i) = pending
+                .queue
+                .iter()
+                .position(|u| !pending.running.contains_key(u))
+                && let Some(uri) = pending.queue.remove(i)
+            {
+                pending.queued.remove(&uri);
+                let token = CancelToken::new();
+                pending.running.insert(uri.clone(), token.clone());
+                return (uri, pending.generation, token);
+            }
+            self.ready.wait(&mut state);
+        }
+    }
+
+    /// Stop the validation running on a document's old content.
+    fn cancel_running(&self, uri: &Uri) {
+        if let Some(token) = self.state.lock().running.get(uri) {
+            token.cancel(CancelReason::ContentModified);
+        }
+    }
+
+    /// Release a document taken with `next`.
+    fn finish(&self, uri: &Uri) {
+        let mut state = self.state.lock();
+        state.running.remove(uri);
+        if state.queued.contains(uri) {
+            self.ready.notify_one();
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.state.lock().queue.is_empty()
+    }
+
+    fn generation(&self) -> u64 {
+        self.state.lock().generation
+    }
+
+    /// Whether diagnostics for this version and generation were published.
+    fn is_current(&self, uri: &Uri, version: i32, generation: u64) -> bool {
+        self.state.lock().published.get(uri) == Some(&(version, generation))
+    }
+
+    fn mark_published(&self, uri: Uri, version: i32, generation: u64) {
+        self.state
+            .lock()
+            .published
+            .insert(uri, (version, generation));
+    }
+}
+
+/// Long-lived worker that validates queued documents, skipping those whose
+/// diagnostics are already current. Once the queue runs dry, a short sleep
+/// lets rapid keystrokes coalesce.
+fn validation_worker(shared: Arc<Shared>, sender: Sender<Message>) {
+    loop {
+        let (uri, generation, cancel) = shared.validations.next();
+        let version = shared.state.read().documents.get(&uri).map(|d| d.version);
+        match version {
+            Some(version) => {
+                if !shared.validations.is_current(&uri, version, generation)
+                    && let Some(version) = validate_and_publish(&uri, &shared, &sender, &cancel)
+                {
+                    shared
+                        .validations
+                        .mark_published(uri.clone(), version, generation);
+                }
+            }
+            None => validate_file_and_publish(&uri, &shared, &sender, &cancel),
+        }
+        shared.validations.finish(&uri);
+        if shared.validations.is_empty() {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Diagnostic pull pool
+// ---------------------------------------------------------------------------
+
+const PULL_THREADS: usize = 2;
+
+/// Work that answers a diagnostic pull.
+type PullJob = Box<dyn FnOnce(&Shared) + Send>;
+
+/// Diagnostic pulls waiting for the pull pool, keyed by the document they
+/// ask about (`None` for the workspace). Pulls for one key are handed to one
+/// worker at a time and answered in the order they arrived.
+struct PullQueue {
+    state: Mutex<PendingPulls>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct PendingPulls {
+    queue: VecDeque<(Option<Uri>, PullJob)>,
+    /// Keys a worker is answering a pull for right now.
+    running: HashSet<Option<Uri>>,
+}
+
+impl PullQueue {
+    fn new() -> Self {
+        PullQueue {
+            state: Mutex::new(PendingPulls::default()),
+            ready: Condvar::new(),
+        }
+    }
+
+    fn schedule(&self, document: Option<Uri>, job: PullJob) {
+        self.state.lock().queue.push_back((document, job));
+        self.ready.notify_one();
+    }
+
+    /// Block until a pull whose key no other worker holds is queued; the
+    /// caller must `finish` its key.
+    fn next(&self) -> (Option<Uri>, PullJob) {
+        let mut state = self.state.lock();
+        loop {
+            let pending = &mut *state;
+            if let Some(i) = pending
+                .queue
+                .iter()
+                .position(|(key, _)| !pending.running.contains(key))
+                && let Some((key, job)) = pending.queue.remove(i)
+            {
+                pending.running.insert(key.clone());
+                return (key, job);
+            }
+            self.ready.wait(&mut state);
+        }
+    }
+
+    /// Release a key taken with `next`.
+    fn finish(&self, document: &Option<Uri>) {
+        let mut state = self.state.lock();
+        state.running.remove(document);
+        if state.queue.iter().any(|(key, _)| key == document) {
+            self.ready.notify_one();
+        }
+    }
+}
+
+/// Long-lived worker that answers queued diagnostic pulls.
+fn pull_worker(shared: Arc<Shared>) {
+    loop {
+        let (document, job) = shared.pulls.next();
+        job(&shared);
+        shared.pulls.finish(&document);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Schema fetching
+// ---------------------------------------------------------------------------
+
+const FETCH_THREADS: usize = 4;
+
+/// How long a failed fetch is remembered before the URI is tried again.
+const FETCH_RETRY_AFTER: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Schema URIs waiting for the fetch pool. Each URI is queued at most once
+/// while its fetch is in flight.
+struct FetchQueue {
+    tx: Sender<String>,
+    in_flight: Mutex<HashSet<String>>,
+    failed: Mutex<HashMap<String, std::time::Instant>>,
+}
+
+impl FetchQueue {
+    fn new(tx: Sender<String>) -> Self {
+        FetchQueue {
+            tx,
+            in_flight: Mutex::new(HashSet::new()),
+            failed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Queue a fetch unless one is already running or recently failed.
+    fn request(&self, uri: String) {
+        if let Some(at) = self.failed.lock().get(&uri)
+            && at.elapsed() < FETCH_RETRY_AFTER
+        {
+            return;
+        }
+        if self.in_flight.lock().insert(uri.clone()) {
+            self.tx.send(uri).ok();
+        }
+    }
+
+    fn finish(&self, uri: &str, ok: bool) {
+        let mut failed = self.failed.lock();
+        if ok {
+            failed.remove(uri);
+        } else {
+            failed.insert(uri.to_string(), std::time::Instant::now());
+        }
+        self.in_flight.lock().remove(uri);
+    }
+}
+
+/// Pool thread: fetch queued schemas, then revalidate the documents that
+/// use them so the new schemas are picked up. Schemas that came from the
+/// disk cache are checked against their origin afterwards.
+fn fetch_worker(rx: Receiver<String>, shared: Arc<Shared>) {
+    while let Ok(uri) = rx.recv() {
+        let mut from_disk = Vec::new();
+        let mut fetched = HashSet::new();
+        let ok = fetch_and_cache(&shared, uri.clone(), &mut from_disk, &mut fetched).is_some();
+        shared.fetches.finish(&uri, ok);
+        if ok {
+            revalidate_documents(&shared, &fetched);
+        }
+        let changed = refresh_from_origin(&shared, from_disk);
+        if !changed.is_empty() {
+            revalidate_documents(&shared, &changed);
+        }
+    }
+}
+
+/// Revalidate the documents whose schema is one of `schema_uris`, or
+/// references one of them.
+fn revalidate_documents(shared: &Shared, schema_uris: &HashSet<String>) {
+    let uris = {
+        let state = shared.state.read();
+        let mut affected: HashMap<&str, bool> = HashMap::new();
+        diagnosed_documents(&state)
+            .into_iter()
+            .filter(|uri| {
+                let schema = state
+                    .schemas
+                    .pinned_schema(uri.as_str())
+                    .or_else(|| state.workspace_files.schema(uri));
+                schema.is_some_and(|schema| {
+                    *affected
+                        .entry(schema)
+                        .or_insert_with(|| state.schemas.depends_on(schema, schema_uris))
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+    if !uris.is_empty() {
+        shared.invalidate_diagnostics(uris);
+    }
+}
+
+/// Load a schema document: from the disk cache when it has a copy (which is
+/// added to `from_disk`), otherwise from its origin, saving remote schemas
+/// to the disk cache.
+fn load_schema(
+    shared: &Shared,
+    agent: &ureq::Agent,
+    uri: &str,
+    from_disk: &mut Vec<CachedSchema>,
+) -> Option<serde_json::Value> {
+    let Some(disk) = shared
+        .disk_cache
+        .as_ref()
+        .filter(|_| resolver::is_http(uri))
+    else {
+        return resolver::fetch_schema(agent, uri);
+    };
+    if let Some(entry) = disk.load(uri) {
+        debug!("loaded schema {} from disk cache", uri);
+        let body = entry.body.clone();
+        from_disk.push(entry);
+        return Some(body);
+    }
+    match resolver::fetch_remote(agent, uri, None)? {
+        Fetched::Modified(entry) => {
+            disk.store(&entry);
+            Some(entry.body)
+        }
+        Fetched::NotModified => None,
+    }
+}
+
+/// Revalidate schemas loaded from the disk cache with conditional requests,
+/// replacing those that changed. Returns the URIs of those that did.
+fn refresh_from_origin(shared: &Shared, entries: Vec<CachedSchema>) -> HashSet<String> {
+    let mut changed = HashSet::new();
+    let Some(disk) = &shared.disk_cache else {
+        return changed;
+    };
+    if entries.is_empty() {
+        return changed;
+    }
+    let agent = shared.state.write().schemas.http_agent();
+    for entry in entries {
+        let Some(Fetched::Modified(fresh)) =
+            resolver::fetch_remote(&agent, &entry.uri, Some(&entry))
+        else {
+            continue;
+        };
+        disk.store(&fresh);
+        if fresh.body != entry.body {
+            debug!("schema {} changed upstream", fresh.uri);
+            let schema = resolver::compile_schema(&fresh.uri, &fresh.body);
+            changed.insert(fresh.uri.clone());
+            shared.state.write().schemas.insert_cache(fresh.uri, schema);
+        }
+    }
+    changed
+}
+
+/// Fetch and cache a schema together with every external schema it
+/// references (transitively), so `$ref`s can be followed without fetching
+/// in the middle of validation. The URIs of the cached schemas are added to
+/// `fetched`.
+fn fetch_and_cache(
+    shared: &Shared,
+    uri: String,
+    from_disk: &mut Vec<CachedSchema>,
+    fetched: &mut HashSet<String>,
+) -> Option<Arc<JsonSchema>> {
+    let state = &shared.state;
+    let agent = state.write().schemas.http_agent();
+    let raw = load_schema(shared, &agent, &uri, from_disk)?;
+    let schema = resolver::compile_schema(&uri, &raw);
+
+    let mut pending = {
+        let mut state = state.write();
+        state.schemas.insert_cache(uri.clone(), schema.clone());
+        state.schemas.missing_refs(&schema)
+    };
+    fetched.insert(uri.clone());
+    let mut attempted = HashSet::from([uri]);
+    while let Some(ref_uri) = pending.pop() {
+        if !attempted.insert(ref_uri.clone()) {
+            continue;
+        }
+        let Some(raw) = load_schema(shared, &agent, &ref_uri, from_disk) else {
+            continue;
+        };
+        let referenced = resolver::compile_schema(&ref_uri, &raw);
+        let mut state = state.write();
+        pending.extend(state.schemas.missing_refs(&referenced));
+        fetched.insert(ref_uri.clone());
+        state.schemas.insert_cache(ref_uri, referenced);
+    }
+
+    Some(schema)
+}
+
+// ---------------------------------------------------------------------------
+// Free function for validation
+// ---------------------------------------------------------------------------
+
+/// Validate a document and publish its diagnostics. Returns the version
+/// that was published, or `None` if the document is no longer open or the
+/// validation was cancelled.
+fn validate_and_publish(
+    uri: &Uri,
+    shared: &Shared,
+    sender: &Sender<Message>,
+    cancel: &CancelToken,
+) -> Option<i32> {
+    let (version, diagnostics) = document_diagnostics(uri, shared, cancel)?;
+    publish_diagnostics(sender, uri.clone(), diagnostics, Some(version));
+    Some(version)
+}
+
+/// Validate a workspace file that isn't open from disk and publish its
+/// diagnostics, unless it was opened or dropped from the workspace files
+/// meanwhile.
+fn validate_file_and_publish(
+    uri: &Uri,
+    shared: &Shared,
+    sender: &Sender<Message>,
+    cancel: &CancelToken,
+) {
+    if shared.state.read().workspace_files.revision(uri).is_none() {
+        return;
+    }
+    let Some(diagnostics) = file_diagnostics(uri, shared, cancel) else {
+        return;
+    };
+    let state = shared.state.read();
+    if !is_open(&state, uri) && state.workspace_files.revision(uri).is_some() {
+        publish_diagnostics(sender, uri.clone(), diagnostics, None);
+    }
+}
+
+fn publish_diagnostics(
+    sender: &Sender<Message>,
+    uri: Uri,
+    diagnostics: Vec<Diagnostic>,
+    version: Option<i32>,
+) {
+    let params = PublishDiagnosticsParams {
+        uri,
+        diagnostics,
+        version,
     };
     let not = Notification::new(
         notification::PublishDiagnostics::METHOD.into(),
@@ -807,3 +2007,748 @@ fn validate_and_publish(
     );
     sender.send(Message::Notification(not)).ok();
 }
+
+/// Syntax and schema diagnostics for an open document, with the version
+/// they describe. `None` if the document is not open or the validation was
+/// cancelled.
+fn document_diagnostics(
+    uri: &Uri,
+    shared: &Shared,
+    cancel: &CancelToken,
+) -> Option<(i32, Vec<Diagnostic>)> {
+    let state = &shared.state;
+    let uri_str = uri.as_str();
+    // Validation runs on a snapshot of the document, the schema registry and
+    // the options, taken under one read lock, so edits and requests don't
+    // wait for it. A schema that isn't cached yet is fetched in the
+    // background; the document is revalidated when it arrives.
+    let (doc, lookup, repin, registry, options) = {
+        let state = state.read();
+        let doc = state.documents.snapshot(uri)?;
+        let inline_schema = resolver::extract_schema_property(&doc);
+        let schema_uri = state
+            .schemas
+            .schema_uri_for_document(uri_str, inline_schema.as_deref());
+        let repin = state.schemas.pinned_schema(uri_str) != schema_uri.as_deref();
+        let lookup = state
+            .schemas
+            .schema_for_document(uri_str, inline_schema.as_deref());
+        (
+            doc,
+            lookup,
+            repin.then_some(schema_uri),
+            state.schemas.registry().clone(),
+            state.validation,
+        )
+    };
+    // Keep the schema cached for as long as the document stays open.
+    if let Some(schema_uri) = repin {
+        let mut state = state.write();
+        if state.documents.get(uri).is_some() {
+            state.schemas.pin_document(uri_str, schema_uri.as_deref());
+        }
+    }
+
+    let mut diags = diagnostics::syntax_diagnostics(&doc, options.strict);
+    if let Some(schema) = fetch_if_missing(shared, lookup) {
+        add_schema_diagnostics(
+            &mut diags, &doc, &schema, &registry, options, shared, cancel,
+        )
+        .ok()?;
+    }
+
+    Some((doc.version, diags))
+}
+
+/// Syntax and schema diagnostics for a workspace file that isn't open,
+/// read from disk. `None` if the file can't be read or the validation was
+/// cancelled.
+fn file_diagnostics(uri: &Uri, shared: &Shared, cancel: &CancelToken) -> Option<Vec<Diagnostic>> {
+    let doc = workspace::parse_file(&workspace::uri_to_path(uri.as_str())?)?;
+    let inline_schema = resolver::extract_schema_property(&doc);
+    // Recorded before any fetch starts, so its arrival revalidates the file.
+    let schema_uri = shared
+        .state
+        .read()
+        .schemas
+        .schema_uri_for_document(uri.as_str(), inline_schema.as_deref());
+    shared
+        .state
+        .write()
+        .workspace_files
+        .set_schema(uri, schema_uri);
+    let (lookup, registry, options) = {
+        let state = shared.state.read();
+        let lookup = state
+            .schemas
+            .schema_for_document(uri.as_str(), inline_schema.as_deref());
+        (lookup, state.schemas.registry().clone(), state.validation)
+    };
+    let mut diags = diagnostics::syntax_diagnostics(&doc, options.strict);
+    if let Some(schema) = fetch_if_missing(shared, lookup) {
+        add_schema_diagnostics(
+            &mut diags, &doc, &schema, &registry, options, shared, cancel,
+        )
+        .ok()?;
+    }
+    Some(diags)
+}
+
+/// The schema of a lookup. One that isn't cached yet is fetched in the
+/// background, and documents are revalidated when it arrives.
+fn fetch_if_missing(shared: &Shared, lookup: SchemaLookup) -> Option<Arc<JsonSchema>> {
+    match lookup {
+        SchemaLookup::Resolved(schema) => Some(schema),
+        SchemaLookup::NeedsFetch(fetch_uri) => {
+            shared.fetches.request(fetch_uri);
+            None
+        }
+        SchemaLookup::None => None,
+    }
+}
+
+/// Validate a document against its schema, adding to its syntax
+/// diagnostics. Skipped if the document failed to parse; duplicate keys and
+/// (in strict mode) trailing commas or comments don't prevent it.
+fn add_schema_diagnostics(
+    diags: &mut Vec<Diagnostic>,
+    doc: &Document,
+    schema: &Arc<JsonSchema>,
+    registry: &SchemaRegistry,
+    options: ValidationOptions,
+    shared: &Shared,
+    cancel: &CancelToken,
+) -> Result<(), Cancelled> {
+    let parse_error = NumberOrString::String(diagnostics::codes::SYNTAX_ERROR.into());
+    if diags.iter().any(|d| d.code.as_ref() == Some(&parse_error)) {
+        return Ok(());
+    }
+    let Some(root) = tree::root_value(&doc.tree) else {
+        return Ok(());
+    };
+    let val_errors = validation::validate_with_cancel(
+        root,
+        doc.source(),
+        schema,
+        registry,
+        &shared.regex_cache,
+        options,
+        cancel,
+    )?;
+    diags.extend(
+        val_errors
+            .iter()
+            .map(|ve| diagnostics::schema_diagnostic(doc, ve)),
+    );
+    Ok(())
+}
+
+/// Outcome of a diagnostic pull for one document. Workspace files that
+/// aren't open have no version.
+#[derive(Debug, PartialEq)]
+enum PullReport {
+    /// Fresh diagnostics under a new result ID.
+    Full {
+        version: Option<i32>,
+        result_id: String,
+        items: Vec<Diagnostic>,
+    },
+    /// The result the client already holds is still current.
+    Unchanged {
+        version: Option<i32>,
+        result_id: String,
+    },
+    /// The document is neither open nor a readable workspace file.
+    Closed,
+}
+
+/// Answer `textDocument/diagnostic`.
+fn document_diagnostic_report(
+    shared: &Shared,
+    params: DocumentDiagnosticParams,
+    cancel: &CancelToken,
+) -> Result<DocumentDiagnosticReportResult, Cancelled> {
+    let report = pull_report(
+        shared,
+        &params.text_document.uri,
+        params.previous_result_id.as_deref(),
+        cancel,
+    )?;
+    let report = match report {
+        PullReport::Full {
+            result_id, items, ..
+        } => DocumentDiagnosticReport::Full(RelatedFullDocumentDiagnosticReport {
+            related_documents: None,
+            full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                result_id: Some(result_id),
+                items,
+            },
+        }),
+        PullReport::Unchanged { result_id, .. } => {
+            DocumentDiagnosticReport::Unchanged(RelatedUnchangedDocumentDiagnosticReport {
+                related_documents: None,
+                unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport {
+                    result_id,
+                },
+            })
+        }
+        PullReport::Closed => DocumentDiagnosticReport::Full(Default::default()),
+    };
+    Ok(DocumentDiagnosticReportResult::Report(report))
+}
+
+/// Answer `workspace/diagnostic` with a report for every open document and
+/// workspace file. Cancellation is checked between files.
+fn workspace_diagnostic_report(
+    shared: &Shared,
+    params: WorkspaceDiagnosticParams,
+    cancel: &CancelToken,
+) -> Result<WorkspaceDiagnosticReportResult, Cancelled> {
+    let previous: HashMap<String, String> = params
+        .previous_result_ids
+        .into_iter()
+        .map(|p| (uri_key(&p.uri), p.value))
+        .collect();
+    let uris = diagnosed_documents(&shared.state.read());
+    let mut items = Vec::with_capacity(uris.len());
+    for uri in uris {
+        let previous = previous.get(&uri_key(&uri)).map(String::as_str);
+        items.push(match pull_report(shared, &uri, previous, cancel)? {
+            PullReport::Full {
+                version,
+                result_id,
+                items,
+            } => WorkspaceDocumentDiagnosticReport::Full(WorkspaceFullDocumentDiagnosticReport {
+                uri,
+                version: version.map(Into::into),
+                full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                    result_id: Some(result_id),
+                    items,
+                },
+            }),
+            PullReport::Unchanged { version, result_id } => {
+                WorkspaceDocumentDiagnosticReport::Unchanged(
+                    WorkspaceUnchangedDocumentDiagnosticReport {
+                        uri,
+                        version: version.map(Into::into),
+                        unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport {
+                            result_id,
+                        },
+                    },
+                )
+            }
+            PullReport::Closed => continue,
+        });
+    }
+    let report = WorkspaceDiagnosticReport { items };
+    Ok(WorkspaceDiagnosticReportResult::Report(report))
+}
+
+/// Diagnostics for a pull request. The result ID names the document version
+/// (or a closed file's revision on disk) and the validation generation,
+/// which moves when settings or schemas change, so a client asking again
+/// with the ID it holds gets an unchanged report without revalidating.
+fn pull_report(
+    shared: &Shared,
+    uri: &Uri,
+    previous_result_id: Option<&str>,
+    cancel: &CancelToken,
+) -> Result<PullReport, Cancelled> {
+    cancel.check()?;
+    // Read before validating: a schema arriving meanwhile bumps the
+    // generation, so the next pull validates again.
+    let generation = shared.validations.generation();
+    let (version, revision) = {
+        let state = shared.state.read();
+        let version = state.documents.get(uri).map(|d| d.version);
+        (version, state.workspace_files.revision(uri))
+    };
+    let result_id = match (version, revision) {
+        (Some(version), _) => format!("{version}:{generation}"),
+        (None, Some(revision)) => format!("file-{revision}:{generation}"),
+        (None, None) => return Ok(PullReport::Closed),
+    };
+    if previous_result_id == Some(result_id.as_str()) {
+        return Ok(PullReport::Unchanged { version, result_id });
+    }
+    let report = match version {
+        Some(_) => {
+            document_diagnostics(uri, shared, cancel).map(|(version, items)| PullReport::Full {
+                version: Some(version),
+                result_id: format!("{version}:{generation}"),
+                items,
+            })
+        }
+        None => file_diagnostics(uri, shared, cancel).map(|items| PullReport::Full {
+            version: None,
+            result_id,
+            items,
+        }),
+    };
+    cancel.check()?;
+    Ok(report.unwrap_or(PullReport::Closed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uri(path: &str) -> Uri {
+        Uri::from_str(&format!("file:///{path}")).unwrap()
+    }
+
+    #[test]
+    fn validation_queue_coalesces_and_keeps_every_document() {
+        let queue = ValidationQueue::new();
+        queue.schedule(uri("a.json"), false);
+        queue.schedule(uri("b.json"), false);
+        queue.schedule(uri("a.json"), false);
+        queue.schedule(uri("c.json"), false);
+        assert_eq!(queue.next().0, uri("a.json"));
+        assert_eq!(queue.next().0, uri("b.json"));
+        assert_eq!(queue.next().0, uri("c.json"));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn validation_queue_prioritises_the_active_document() {
+        let queue = ValidationQueue::new();
+        queue.schedule(uri("a.json"), false);
+        queue.schedule(uri("b.json"), false);
+        queue.schedule(uri("b.json"), true);
+        queue.schedule(uri("c.json"), true);
+        let order: Vec<Uri> = (0..3).map(|_| queue.next().0).collect();
+        assert_eq!(order, [uri("c.json"), uri("b.json"), uri("a.json")]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn validation_queue_hands_a_document_to_one_worker_at_a_time() {
+        let queue = ValidationQueue::new();
+        queue.schedule(uri("a.json"), false);
+        assert_eq!(queue.next().0, uri("a.json"));
+        queue.schedule(uri("a.json"), true);
+        queue.schedule(uri("b.json"), false);
+        // `a` is still running, so it waits behind `b`.
+        assert_eq!(queue.next().0, uri("b.json"));
+        queue.finish(&uri("a.json"));
+        assert_eq!(queue.next().0, uri("a.json"));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn pull_queue_answers_one_pull_per_document_at_a_time() {
+        let queue = PullQueue::new();
+        let answered = Arc::new(Mutex::new(Vec::new()));
+        let pull = |name: &'static str| -> PullJob {
+            let answered = Arc::clone(&answered);
+            Box::new(move |_| answered.lock().push(name))
+        };
+        queue.schedule(Some(uri("a.json")), pull("a1"));
+        queue.schedule(Some(uri("a.json")), pull("a2"));
+        queue.schedule(None, pull("workspace"));
+        let shared = test_shared();
+
+        let (a, job) = queue.next();
+        job(&shared);
+        // `a` is still running, so its second pull waits behind the workspace.
+        let (workspace, job) = queue.next();
+        assert_eq!(workspace, None);
+        job(&shared);
+        queue.finish(&a);
+        let (a, job) = queue.next();
+        assert_eq!(a, Some(uri("a.json")));
+        job(&shared);
+        queue.finish(&a);
+        queue.finish(&workspace);
+        assert_eq!(*answered.lock(), ["a1", "workspace", "a2"]);
+        assert!(queue.state.lock().queue.is_empty());
+    }
+
+    #[test]
+    fn validation_queue_cancels_running_validation_on_change() {
+        let queue = ValidationQueue::new();
+        queue.schedule(uri("a.json"), false);
+        let (a, _, token) = queue.next();
+        queue.cancel_running(&uri("b.json"));
+        assert!(!token.is_cancelled());
+        queue.cancel_running(&a);
+        assert_eq!(token.reason(), Some(CancelReason::ContentModified));
+        queue.finish(&a);
+        queue.schedule(a.clone(), false);
+        assert!(!queue.next().2.is_cancelled());
+    }
+
+    fn request(id: i32, method: &str, params: serde_json::Value) -> Message {
+        Message::Request(Request::new(id.into(), method.into(), params))
+    }
+
+    fn notification(method: &str, params: serde_json::Value) -> Message {
+        Message::Notification(Notification::new(method.into(), params))
+    }
+
+    fn test_shared() -> Shared {
+        let (fetch_tx, _) = crossbeam_channel::unbounded();
+        Shared {
+            state: RwLock::new(ServerState {
+                documents: DocumentStore::new(),
+                schemas: SchemaStore::new(),
+                validation: ValidationOptions::default(),
+                sort_order: SortOrder::default(),
+                format: FormatSettings::default(),
+                workspace: WorkspaceSettings::default(),
+                workspace_files: WorkspaceFiles::new(),
+            }),
+            regex_cache: RegexCache::new(),
+            validations: ValidationQueue::new(),
+            pulls: PullQueue::new(),
+            requests: PendingRequests::default(),
+            fetches: FetchQueue::new(fetch_tx),
+            disk_cache: None,
+            diagnostic_mode: OnceLock::new(),
+            client: crossbeam_channel::unbounded().0,
+            file_watching: OnceLock::new(),
+            watching: Mutex::new(Vec::new()),
+            scan_lock: Mutex::new(()),
+        }
+    }
+
+    #[test]
+    fn reader_applies_cancellation_and_content_changes() {
+        let shared = Arc::new(test_shared());
+        let (client_tx, client_rx) = crossbeam_channel::unbounded();
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let doc = serde_json::json!({"textDocument": {"uri": "file:///a.json"}});
+        for msg in [
+            request(1, request::DocumentSymbolRequest::METHOD, doc.clone()),
+            request(2, request::HoverRequest::METHOD, doc.clone()),
+            request(3, request::Formatting::METHOD, doc.clone()),
+            notification(notification::Cancel::METHOD, serde_json::json!({"id": 1})),
+            notification(notification::DidChangeTextDocument::METHOD, doc.clone()),
+            request(4, request::Shutdown::METHOD, serde_json::Value::Null),
+            notification(notification::Exit::METHOD, serde_json::Value::Null),
+        ] {
+            client_tx.send(msg).unwrap();
+        }
+        read_messages(client_rx.clone(), tx, Arc::clone(&shared));
+
+        let requests = &shared.requests;
+        assert_eq!(
+            requests.token(&1.into()).reason(),
+            Some(CancelReason::Requested)
+        );
+        for id in [2, 3] {
+            assert_eq!(
+                requests.token(&id.into()).reason(),
+                Some(CancelReason::ContentModified)
+            );
+        }
+        assert_eq!(requests.token(&4.into()).reason(), None);
+        // `$/cancelRequest` is consumed; the reader stops after `shutdown`.
+        assert_eq!(rx.try_iter().count(), 5);
+        assert!(matches!(client_rx.try_recv(), Ok(Message::Notification(n)) if n.method == "exit"));
+
+        requests.finish(&1.into());
+        assert_eq!(requests.token(&1.into()).reason(), None);
+    }
+
+    #[test]
+    fn detached_requests_stay_cancellable_until_finished() {
+        let requests = PendingRequests::default();
+        for id in [1, 2] {
+            let Message::Request(req) = request(
+                id,
+                request::WorkspaceDiagnosticRequest::METHOD,
+                serde_json::json!({}),
+            ) else {
+                unreachable!();
+            };
+            requests.register(&req);
+        }
+        let detached = requests.token(&2.into());
+        requests.detach(&2.into());
+        requests.dispatched(&1.into());
+        requests.dispatched(&2.into());
+
+        requests.cancel(&1.into());
+        requests.cancel(&2.into());
+        assert_eq!(detached.reason(), Some(CancelReason::Requested));
+        requests.finish(&2.into());
+        assert_eq!(requests.token(&2.into()).reason(), None);
+    }
+
+    #[test]
+    fn workspace_diagnostics_stop_when_cancelled() {
+        let shared = test_shared();
+        shared
+            .state
+            .write()
+            .documents
+            .open(uri("a.json"), "{}".into(), 1);
+        let params: WorkspaceDiagnosticParams =
+            serde_json::from_value(serde_json::json!({"previousResultIds": []})).unwrap();
+        let cancel = CancelToken::new();
+        cancel.cancel(CancelReason::Requested);
+        assert!(matches!(
+            workspace_diagnostic_report(&shared, params.clone(), &cancel),
+            Err(Cancelled)
+        ));
+        let Ok(WorkspaceDiagnosticReportResult::Report(report)) =
+            workspace_diagnostic_report(&shared, params, &CancelToken::new())
+        else {
+            panic!("expected a workspace report");
+        };
+        assert_eq!(report.items.len(), 1);
+    }
+
+    #[test]
+    fn validation_queue_skips_unchanged_versions_until_invalidated() {
+        let queue = ValidationQueue::new();
+        let a = uri("a.json");
+        queue.schedule(a.clone(), false);
+        let (_, generation, _) = queue.next();
+        assert!(!queue.is_current(&a, 1, generation));
+        queue.mark_published(a.clone(), 1, generation);
+        assert!(queue.is_current(&a, 1, generation));
+        assert!(!queue.is_current(&a, 2, generation));
+
+        queue.finish(&a);
+        queue.invalidate([a.clone()]);
+        let (next, generation, _) = queue.next();
+        assert_eq!(next, a);
+        assert!(!queue.is_current(&a, 1, generation));
+
+        queue.mark_published(a.clone(), 1, generation);
+        queue.forget(&a);
+        assert!(!queue.is_current(&a, 1, generation));
+    }
+
+    #[test]
+    fn state_stays_writable_while_a_document_validates() {
+        let shared = Arc::new(test_shared());
+        let a = uri("a.json");
+        {
+            let mut state = shared.state.write();
+            // Both branches fail at the innermost item, so every level tries
+            // both: the validation doesn't finish before it is cancelled.
+            let schema = serde_json::json!({
+                "type": "array",
+                "items": {"anyOf": [{"$ref": "#"}, {"$ref": "#"}]},
+            });
+            state.schemas.set_associations(vec![SchemaAssociation {
+                file_match: vec!["*.json".into()],
+                uri: "inline://schema".into(),
+                schema: Some(resolver::compile_schema("inline://schema", &schema)),
+                folder_uri: None,
+            }]);
+            let text = format!("{}1{}", "[".repeat(64), "]".repeat(64));
+            state.documents.open(a.clone(), text, 1);
+        }
+        let opened = shared.state.read().documents.snapshot(&a).unwrap();
+
+        let cancel = CancelToken::new();
+        let worker = {
+            let shared = shared.clone();
+            let a = a.clone();
+            let cancel = cancel.clone();
+            std::thread::spawn(move || document_diagnostics(&a, &shared, &cancel))
+        };
+        // The store, `opened` and the worker's snapshot.
+        while Arc::strong_count(&opened) < 3 {
+            std::thread::yield_now();
+        }
+
+        // An edit goes through while the validation is still in progress.
+        let mut state = shared
+            .state
+            .try_write_for(std::time::Duration::from_secs(5))
+            .expect("validation holds the state lock");
+        state
+            .documents
+            .get_mut(&a)
+            .unwrap()
+            .replace_full("{}".into(), 2);
+        drop(state);
+        assert!(!worker.is_finished());
+        assert_eq!(Arc::strong_count(&opened), 2);
+        assert_eq!(opened.version, 1);
+
+        cancel.cancel(CancelReason::Requested);
+        assert!(worker.join().unwrap().is_none());
+    }
+
+    #[test]
+    fn pull_report_is_unchanged_until_version_or_generation_moves() {
+        let shared = test_shared();
+        let a = uri("a.json");
+        let cancel = CancelToken::new();
+        assert_eq!(
+            pull_report(&shared, &a, None, &cancel),
+            Ok(PullReport::Closed)
+        );
+
+        shared
+            .state
+            .write()
+            .documents
+            .open(a.clone(), "{".into(), 1);
+        let Ok(PullReport::Full {
+            version,
+            result_id,
+            items,
+        }) = pull_report(&shared, &a, None, &cancel)
+        else {
+            panic!("expected a full report");
+        };
+        assert_eq!(version, Some(1));
+        assert!(!items.is_empty());
+        assert_eq!(
+            pull_report(&shared, &a, Some(&result_id), &cancel),
+            Ok(PullReport::Unchanged {
+                version: Some(1),
+                result_id: result_id.clone(),
+            })
+        );
+
+        // A settings or schema change retires the ID.
+        shared.validations.invalidate([]);
+        let Ok(PullReport::Full { result_id, .. }) =
+            pull_report(&shared, &a, Some(&result_id), &cancel)
+        else {
+            panic!("expected a full report after invalidation");
+        };
+
+        // So does an edit.
+        shared
+            .state
+            .write()
+            .documents
+            .get_mut(&a)
+            .unwrap()
+            .replace_full("{}".into(), 2);
+        assert!(matches!(
+            pull_report(&shared, &a, Some(&result_id), &cancel),
+            Ok(PullReport::Full { version: Some(2), ref items, .. }) if items.is_empty()
+        ));
+
+        cancel.cancel(CancelReason::Requested);
+        assert_eq!(pull_report(&shared, &a, None, &cancel), Err(Cancelled));
+    }
+
+    #[test]
+    fn workspace_scan_reports_closed_files_from_disk() {
+        let root = std::env::temp_dir().join(format!(
+            "light-json-lsp-test-server-scan-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("ignored")).unwrap();
+        std::fs::write(root.join(".gitignore"), "ignored/\n").unwrap();
+        std::fs::write(root.join("a.json"), "{\"a\": }").unwrap();
+        std::fs::write(root.join("ignored/b.json"), "{}").unwrap();
+        let a = workspace::path_to_uri(&root.join("a.json")).unwrap();
+        let b = workspace::path_to_uri(&root.join("ignored/b.json")).unwrap();
+
+        let shared = test_shared();
+        {
+            let mut state = shared.state.write();
+            let folder = workspace::path_to_uri(&root).unwrap();
+            state
+                .schemas
+                .set_workspace_folders(vec![folder.as_str().to_string()]);
+            state.workspace.enable = true;
+        }
+        scan_workspace(&shared);
+        assert!(shared.state.read().workspace_files.revision(&b).is_none());
+        assert_eq!(diagnosed_documents(&shared.state.read()), vec![a.clone()]);
+
+        let cancel = CancelToken::new();
+        let Ok(PullReport::Full {
+            version: None,
+            result_id,
+            items,
+        }) = pull_report(&shared, &a, None, &cancel)
+        else {
+            panic!("expected a full report for the closed file");
+        };
+        assert!(!items.is_empty());
+        assert!(matches!(
+            pull_report(&shared, &a, Some(&result_id), &cancel),
+            Ok(PullReport::Unchanged { version: None, .. })
+        ));
+
+        // A change on disk moves the file's revision.
+        std::fs::write(root.join("a.json"), "{}").unwrap();
+        shared.state.write().workspace_files.touch(a.clone());
+        assert!(matches!(
+            pull_report(&shared, &a, Some(&result_id), &cancel),
+            Ok(PullReport::Full { version: None, ref items, .. }) if items.is_empty()
+        ));
+
+        shared.state.write().workspace.enable = false;
+        scan_workspace(&shared);
+        assert!(diagnosed_documents(&shared.state.read()).is_empty());
+        assert_eq!(
+            pull_report(&shared, &a, None, &cancel),
+            Ok(PullReport::Closed)
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn fetched_schemas_revalidate_only_their_documents() {
+        let shared = test_shared();
+        let a = uri("a.json");
+        let b = uri("b.json");
+        {
+            let mut state = shared.state.write();
+            state.documents.open(a.clone(), "{}".into(), 1);
+            state.documents.open(b.clone(), "{}".into(), 1);
+            let main = resolver::compile_schema(
+                "https://x.com/main.json",
+                &serde_json::json!({"$ref": "dep.json"}),
+            );
+            state
+                .schemas
+                .insert_cache("https://x.com/main.json".into(), main);
+            state
+                .schemas
+                .pin_document(a.as_str(), Some("https://x.com/main.json"));
+            state
+                .schemas
+                .pin_document(b.as_str(), Some("https://x.com/other.json"));
+        }
+        let generation = shared.validations.generation();
+        revalidate_documents(&shared, &HashSet::from(["https://x.com/none.json".into()]));
+        assert_eq!(shared.validations.generation(), generation);
+        assert!(shared.validations.is_empty());
+
+        revalidate_documents(&shared, &HashSet::from(["https://x.com/dep.json".into()]));
+        assert_eq!(shared.validations.next().0, a);
+        assert!(shared.validations.is_empty());
+    }
+
+    #[test]
+    fn fetch_queue_deduplicates_in_flight_requests() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let queue = FetchQueue::new(tx);
+        queue.request("https://example.com/a.json".into());
+        queue.request("https://example.com/a.json".into());
+        queue.request("https://example.com/b.json".into());
+        assert_eq!(rx.try_iter().count(), 2);
+
+        queue.finish("https://example.com/a.json", true);
+        queue.request("https://example.com/a.json".into());
+        assert_eq!(rx.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn fetch_queue_backs_off_after_failure() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let queue = FetchQueue::new(tx);
+        queue.request("https://example.com/a.json".into());
+        queue.finish("https://example.com/a.json", false);
+        queue.request("https://example.com/a.json".into());
+        assert_eq!(rx.try_iter().count(), 1);
+    }
+}
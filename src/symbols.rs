@@ -7,6 +7,7 @@
 use lsp_types::*;
 use tree_sitter::Node;
 
+use crate::cancel::{CancelToken, Cancelled};
 use crate::document::Document;
 use crate::tree::{self, FieldIds, KindIds, is_value_node_id};
 
@@ -207,6 +208,7 @@ struct WriteCtx<'a> {
     kinds: &'a KindIds,
     fields: &'a FieldIds,
     is_ascii: bool,
+    cancel: &'a CancelToken,
 }
 
 /// Produce document symbols serialized directly to a JSON string.
@@ -222,11 +224,21 @@ pub fn document_symbols_string(doc: &Document) -> String {
 /// to pre-fill the buffer with a prefix (e.g. a JSON-RPC envelope) and
 /// avoid a second allocation + copy.
 pub fn write_document_symbols(doc: &Document, buf: &mut String) {
+    let _ = write_document_symbols_with_cancel(doc, buf, &CancelToken::new());
+}
+
+/// Like [`write_document_symbols`], but stops early once `cancel` is
+/// cancelled, leaving `buf` incomplete.
+pub fn write_document_symbols_with_cancel(
+    doc: &Document,
+    buf: &mut String,
+    cancel: &CancelToken,
+) -> Result<(), Cancelled> {
     let mut itoa_buf = itoa::Buffer::new();
 
     let Some(root_value) = tree::root_value(&doc.tree) else {
         buf.push_str("[]");
-        return;
+        return Ok(());
     };
 
     let ctx = WriteCtx {
@@ -235,6 +247,7 @@ pub fn write_document_symbols(doc: &Document, buf: &mut String) {
         kinds: doc.kind_ids(),
         fields: doc.field_ids(),
         is_ascii: doc.is_ascii(),
+        cancel,
     };
     let root_kind = root_value.kind_id();
 
@@ -249,6 +262,7 @@ pub fn write_document_symbols(doc: &Document, buf: &mut String) {
         write_array_children(buf, &mut itoa_buf, &ctx, &mut cursor, root_value);
     }
     buf.push(']');
+    cancel.check()
 }
 
 fn write_object_children<'a>(
@@ -259,7 +273,7 @@ fn write_object_children<'a>(
     object: Node<'a>,
 ) {
     cursor.reset(object);
-    if !cursor.goto_first_child() {
+    if ctx.cancel.is_cancelled() || !cursor.goto_first_child() {
         return;
     }
 
@@ -325,7 +339,7 @@ fn write_array_children<'a>(
     array: Node<'a>,
 ) {
     cursor.reset(array);
-    if !cursor.goto_first_child() {
+    if ctx.cancel.is_cancelled() || !cursor.goto_first_child() {
         return;
     }
 
@@ -579,6 +593,24 @@ mod tests {
     use super::*;
     use crate::document::Document;
 
+    #[test]
+    fn cancelled_symbols_stop_early() {
+        let doc = Document::new(r#"{"a": {"b": [1, 2]}}"#.into(), 0);
+        let cancel = CancelToken::new();
+        let mut buf = String::new();
+        assert_eq!(
+            write_document_symbols_with_cancel(&doc, &mut buf, &cancel),
+            Ok(())
+        );
+        assert_eq!(buf, document_symbols_string(&doc));
+
+        cancel.cancel(crate::cancel::CancelReason::Requested);
+        assert_eq!(
+            write_document_symbols_with_cancel(&doc, &mut String::new(), &cancel),
+            Err(Cancelled)
+        );
+    }
+
     #[test]
     fn empty_object_no_symbols() {
         let doc = Document::new("{}".into(), 0);
@@ -0,0 +1,580 @@
+/// Workspace-wide validation: finds the JSON files in the workspace folders
+/// so that files which aren't open get diagnostics too. Scans honour
+/// git's ignore files and the include/exclude globs of
+/// `json.validate.workspace`.
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder, gitconfig_excludes_path};
+use lsp_types::Uri;
+use percent_encoding::{AsciiSet, CONTROLS, percent_decode_str, utf8_percent_encode};
+use tracing::{debug, warn};
+
+use crate::document::Document;
+use crate::schema::resolver::FileMatch;
+
+/// A scan stops after this many files.
+pub const MAX_FILES: usize = 10_000;
+
+/// Files larger than this are not validated.
+const MAX_FILE_SIZE: u64 = 4 * 1024 * 1024;
+
+/// `json.validate.workspace` settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkspaceSettings {
+    /// Validate files that aren't open. Off by default.
+    pub enable: bool,
+    /// Globs of the files to validate, relative to the workspace folder.
+    pub include: Vec<String>,
+    /// Globs of files and directories to skip.
+    pub exclude: Vec<String>,
+}
+
+impl Default for WorkspaceSettings {
+    fn default() -> Self {
+        WorkspaceSettings {
+            enable: false,
+            include: vec!["**/*.json".into()],
+            exclude: vec!["**/node_modules/**".into()],
+        }
+    }
+}
+
+impl WorkspaceSettings {
+    /// Parse a `json.validate.workspace` value: `true`/`false`, or an object
+    /// with `enable`, `include` and `exclude`.
+    pub fn from_settings(value: &serde_json::Value) -> Self {
+        let defaults = WorkspaceSettings::default();
+        if let Some(enable) = value.as_bool() {
+            return WorkspaceSettings { enable, ..defaults };
+        }
+        let globs = |key: &str| {
+            value.get(key).and_then(|v| v.as_array()).map(|globs| {
+                globs
+                    .iter()
+                    .filter_map(|v| v.as_str().map(String::from))
+                    .collect()
+            })
+        };
+        WorkspaceSettings {
+            enable: value
+                .get("enable")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+            include: globs("include").unwrap_or(defaults.include),
+            exclude: globs("exclude").unwrap_or(defaults.exclude),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Scanning
+// ---------------------------------------------------------------------------
+
+/// Decides which files of a workspace folder are validated. Globs use the
+/// `fileMatch` rules: a leading `/` anchors a pattern to the folder, other
+/// patterns match at any depth.
+pub struct WorkspaceScanner {
+    include: FileMatch,
+    exclude: FileMatch,
+}
+
+impl WorkspaceScanner {
+    pub fn new(settings: &WorkspaceSettings) -> Self {
+        WorkspaceScanner {
+            include: FileMatch::new(&settings.include),
+            exclude: FileMatch::new(&settings.exclude),
+        }
+    }
+
+    /// The matching files under `root`, in a stable order. Ignored and
+    /// excluded directories are not entered, and symlinks are not followed.
+    /// Stops after `limit` files.
+    pub fn scan(&self, root: &Path, limit: usize) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        self.walk(root, root, &mut inherited_ignores(root), &mut files, limit);
+        files
+    }
+
+    fn walk(
+        &self,
+        root: &Path,
+        dir: &Path,
+        ignores: &mut Vec<Gitignore>,
+        files: &mut Vec<PathBuf>,
+        limit: usize,
+    ) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                debug!("skipping {}: {}", dir.display(), e);
+                return;
+            }
+        };
+        let mut entries: Vec<_> = entries.filter_map(Result::ok).collect();
+        entries.sort_by_key(|entry| entry.file_name());
+
+        let pushed = load_gitignore(dir).map(|g| ignores.push(g)).is_some();
+        for entry in entries {
+            if files.len() >= limit {
+                break;
+            }
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            if file_type.is_dir() {
+                if self.enters(root, &path, ignores) {
+                    self.walk(root, &path, ignores, files, limit);
+                }
+            } else if file_type.is_file() && self.matches(root, &path, ignores) {
+                files.push(path);
+            }
+        }
+        if pushed {
+            ignores.pop();
+        }
+    }
+
+    /// Whether a scan of `root` would pick up `path`.
+    pub fn accepts(&self, root: &Path, path: &Path) -> bool {
+        let Ok(rel) = path.strip_prefix(root) else {
+            return false;
+        };
+        let mut ignores = inherited_ignores(root);
+        ignores.extend(load_gitignore(root));
+        let mut dir = root.to_path_buf();
+        let mut components: Vec<_> = rel.components().collect();
+        if components.pop().is_none() {
+            return false;
+        }
+        for component in components {
+            dir.push(component);
+            if !self.enters(root, &dir, &ignores) {
+                return false;
+            }
+            ignores.extend(load_gitignore(&dir));
+        }
+        self.matches(root, path, &ignores)
+    }
+
+    fn enters(&self, root: &Path, dir: &Path, ignores: &[Gitignore]) -> bool {
+        dir.file_name().is_some_and(|name| name != ".git")
+            && !is_ignored(ignores, dir, true)
+            && !self
+                .exclude
+                .is_match(&format!("{}/", relative_path(root, dir)))
+    }
+
+    fn matches(&self, root: &Path, path: &Path, ignores: &[Gitignore]) -> bool {
+        let rel = relative_path(root, path);
+        self.include.is_match(&rel)
+            && !self.exclude.is_match(&rel)
+            && !is_ignored(ignores, path, false)
+    }
+}
+
+/// Read and parse a file that isn't open. Unreadable and oversized files
+/// are skipped.
+pub fn parse_file(path: &Path) -> Option<Document> {
+    let size = fs::metadata(path).ok()?.len();
+    if size > MAX_FILE_SIZE {
+        debug!("not validating {}: {} bytes", path.display(), size);
+        return None;
+    }
+    let text = fs::read_to_string(path)
+        .map_err(|e| debug!("failed to read {}: {}", path.display(), e))
+        .ok()?;
+    Some(Document::new(text, 0))
+}
+
+/// `path` relative to `base`, `/`-prefixed with `/` separators.
+fn relative_path(base: &Path, path: &Path) -> String {
+    let rel = path.strip_prefix(base).unwrap_or(path);
+    let mut out = String::new();
+    for component in rel.components() {
+        out.push('/');
+        out.push_str(&component.as_os_str().to_string_lossy());
+    }
+    out
+}
+
+// ---------------------------------------------------------------------------
+// .gitignore
+// ---------------------------------------------------------------------------
+
+/// The `.gitignore` of `dir`, if it has one.
+fn load_gitignore(dir: &Path) -> Option<Gitignore> {
+    read_ignore_file(dir, &dir.join(".gitignore"))
+}
+
+/// The rules that apply to a workspace folder besides its own `.gitignore`
+/// files, from lowest to highest precedence: `core.excludesFile`, the
+/// repository's `.git/info/exclude`, and the `.gitignore` files between the
+/// repository root and `root`.
+fn inherited_ignores(root: &Path) -> Vec<Gitignore> {
+    let repo = root.ancestors().find(|dir| dir.join(".git").exists());
+    let mut ignores = Vec::new();
+    if let Some(path) = gitconfig_excludes_path() {
+        ignores.extend(read_ignore_file(repo.unwrap_or(root), &path));
+    }
+    if let Some(repo) = repo {
+        ignores.extend(read_ignore_file(repo, &repo.join(".git/info/exclude")));
+        let mut parents: Vec<_> = root
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(repo))
+            .collect();
+        parents.reverse();
+        ignores.extend(parents.into_iter().filter_map(load_gitignore));
+    }
+    ignores
+}
+
+/// The patterns of an ignore file, relative to `root`.
+fn read_ignore_file(root: &Path, path: &Path) -> Option<Gitignore> {
+    if !path.is_file() {
+        return None;
+    }
+    let mut builder = GitignoreBuilder::new(root);
+    if let Some(e) = builder.add(path) {
+        warn!("invalid patterns in {}: {}", path.display(), e);
+    }
+    builder
+        .build()
+        .map_err(|e| warn!("failed to load {}: {}", path.display(), e))
+        .ok()
+}
+
+/// Whether the innermost ignore file with a matching rule ignores a path.
+fn is_ignored(ignores: &[Gitignore], path: &Path, is_dir: bool) -> bool {
+    ignores
+        .iter()
+        .rev()
+        .map(|gitignore| gitignore.matched(path, is_dir))
+        .find(|m| !m.is_none())
+        .is_some_and(|m| m.is_ignore())
+}
+
+// ---------------------------------------------------------------------------
+// File URIs
+// ---------------------------------------------------------------------------
+
+/// Characters escaped when a path becomes a `file` URI.
+const PATH_ESCAPES: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b']')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+pub fn path_to_uri(path: &Path) -> Option<Uri> {
+    let path = path.to_str()?;
+    Uri::from_str(&format!(
+        "file://{}",
+        utf8_percent_encode(path, PATH_ESCAPES)
+    ))
+    .ok()
+}
+
+/// The local path of a `file` URI.
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+    let path = &rest[rest.find('/')?..];
+    let path = percent_decode_str(path).decode_utf8().ok()?;
+    match path.trim_end_matches('/') {
+        "" => Some(PathBuf::from("/")),
+        path => Some(PathBuf::from(path)),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Known files
+// ---------------------------------------------------------------------------
+
+/// Files found by the last scan. Each has a revision that moves whenever
+/// it changes on disk, which identifies its diagnostics.
+///
+/// Files are keyed by their local path: clients escape URIs differently
+/// (`%40` vs `@`), so URIs are decoded before they are compared. Each file
+/// keeps the URI it was last reported under.
+#[derive(Debug, Default)]
+pub struct WorkspaceFiles {
+    files: HashMap<PathBuf, WorkspaceFile>,
+    next_revision: u64,
+}
+
+#[derive(Debug)]
+struct WorkspaceFile {
+    uri: Uri,
+    revision: u64,
+    /// The schema the file was last validated against.
+    schema: Option<String>,
+}
+
+impl WorkspaceFiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the file set with a fresh scan, giving every file a new
+    /// revision. Returns the files that are no longer part of it.
+    pub fn replace(&mut self, uris: Vec<Uri>) -> Vec<Uri> {
+        let scanned_after = self.next_revision;
+        for uri in uris {
+            self.touch(uri);
+        }
+        let mut removed = Vec::new();
+        self.files.retain(|_, file| {
+            let found = file.revision > scanned_after;
+            if !found {
+                removed.push(file.uri.clone());
+            }
+            found
+        });
+        removed
+    }
+
+    /// Record a created or changed file. URIs of non-`file` schemes are
+    /// ignored.
+    pub fn touch(&mut self, uri: Uri) {
+        let Some(path) = uri_to_path(uri.as_str()) else {
+            return;
+        };
+        self.next_revision += 1;
+        let schema = self.files.remove(&path).and_then(|file| file.schema);
+        let file = WorkspaceFile {
+            uri,
+            revision: self.next_revision,
+            schema,
+        };
+        self.files.insert(path, file);
+    }
+
+    pub fn remove(&mut self, uri: &Uri) -> bool {
+        uri_to_path(uri.as_str()).is_some_and(|path| self.files.remove(&path).is_some())
+    }
+
+    pub fn revision(&self, uri: &Uri) -> Option<u64> {
+        let path = uri_to_path(uri.as_str())?;
+        self.files.get(&path).map(|file| file.revision)
+    }
+
+    /// Record the schema a file was validated against.
+    pub fn set_schema(&mut self, uri: &Uri, schema: Option<String>) {
+        if let Some(file) = uri_to_path(uri.as_str()).and_then(|path| self.files.get_mut(&path)) {
+            file.schema = schema;
+        }
+    }
+
+    pub fn schema(&self, uri: &Uri) -> Option<&str> {
+        let path = uri_to_path(uri.as_str())?;
+        self.files.get(&path)?.schema.as_deref()
+    }
+
+    pub fn uris(&self) -> impl Iterator<Item = &Uri> {
+        self.files.values().map(|file| &file.uri)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch directory tree, removed when dropped.
+    struct TempTree(PathBuf);
+
+    impl TempTree {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let root = std::env::temp_dir()
+                .join(format!("light-json-lsp-test-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            for (path, content) in files {
+                let path = root.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, content).unwrap();
+            }
+            TempTree(root)
+        }
+
+        fn scan(&self, settings: &WorkspaceSettings) -> Vec<String> {
+            WorkspaceScanner::new(settings)
+                .scan(&self.0, MAX_FILES)
+                .iter()
+                .map(|p| relative_path(&self.0, p))
+                .collect()
+        }
+    }
+
+    impl Drop for TempTree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn settings_parse_with_defaults() {
+        let settings = WorkspaceSettings::from_settings(&serde_json::json!(true));
+        assert!(settings.enable);
+        assert_eq!(settings.include, ["**/*.json"]);
+
+        let settings = WorkspaceSettings::from_settings(&serde_json::json!({
+            "enable": true,
+            "exclude": ["/dist/**"]
+        }));
+        assert!(settings.enable);
+        assert_eq!(settings.include, ["**/*.json"]);
+        assert_eq!(settings.exclude, ["/dist/**"]);
+        assert!(!WorkspaceSettings::from_settings(&serde_json::json!({})).enable);
+    }
+
+    #[test]
+    fn scan_honours_gitignore_and_globs() {
+        let tree = TempTree::new(
+            "workspace-scan",
+            &[
+                (
+                    ".gitignore",
+                    "# build output\nbuild/\n*.gen.json\n!keep.gen.json\n/top.json\n",
+                ),
+                ("a.json", "{}"),
+                ("top.json", "{}"),
+                ("notes.txt", ""),
+                ("build/out.json", "{}"),
+                ("x.gen.json", "{}"),
+                ("keep.gen.json", "{}"),
+                ("sub/top.json", "{}"),
+                ("sub/.gitignore", "local.json\n!x.gen.json\n"),
+                ("sub/local.json", "{}"),
+                ("sub/x.gen.json", "{}"),
+                ("node_modules/pkg/package.json", "{}"),
+                (".git/config.json", "{}"),
+            ],
+        );
+        let mut settings = WorkspaceSettings {
+            enable: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            tree.scan(&settings),
+            [
+                "/a.json",
+                "/keep.gen.json",
+                "/sub/top.json",
+                "/sub/x.gen.json"
+            ]
+        );
+
+        settings.include = vec!["/sub/*.json".into()];
+        settings.exclude = vec!["x.gen.json".into()];
+        assert_eq!(tree.scan(&settings), ["/sub/top.json"]);
+    }
+
+    #[test]
+    fn scan_honours_repository_ignores() {
+        let tree = TempTree::new(
+            "workspace-repo-ignores",
+            &[
+                (".git/info/exclude", "local/\n"),
+                (
+                    ".gitignore",
+                    "\\#hash.json\n\\!bang.json\ncache.json/\nsecret.json\n",
+                ),
+                ("ws/a.json", "{}"),
+                ("ws/#hash.json", "{}"),
+                ("ws/!bang.json", "{}"),
+                ("ws/cache.json", "{}"),
+                ("ws/sub/cache.json/x.json", "{}"),
+                ("ws/local/b.json", "{}"),
+                ("ws/secret.json", "{}"),
+            ],
+        );
+        let scanner = WorkspaceScanner::new(&WorkspaceSettings::default());
+        let root = tree.0.join("ws");
+        let files: Vec<_> = scanner
+            .scan(&root, MAX_FILES)
+            .iter()
+            .map(|p| relative_path(&root, p))
+            .collect();
+        assert_eq!(files, ["/a.json", "/cache.json"]);
+        assert!(!scanner.accepts(&root, &root.join("local/c.json")));
+        assert!(!scanner.accepts(&root, &root.join("#hash.json")));
+    }
+
+    #[test]
+    fn accepts_matches_scan_for_single_files() {
+        let tree = TempTree::new(
+            "workspace-accepts",
+            &[
+                (".gitignore", "build/\n"),
+                ("a.json", "{}"),
+                ("build/out.json", "{}"),
+                ("src/.gitignore", "*.json\n"),
+                ("src/b.json", "{}"),
+                ("node_modules/c.json", "{}"),
+            ],
+        );
+        let scanner = WorkspaceScanner::new(&WorkspaceSettings::default());
+        let root = &tree.0;
+        assert!(scanner.accepts(root, &root.join("a.json")));
+        assert!(scanner.accepts(root, &root.join("new/d.json")));
+        assert!(!scanner.accepts(root, &root.join("build/out.json")));
+        assert!(!scanner.accepts(root, &root.join("src/b.json")));
+        assert!(!scanner.accepts(root, &root.join("node_modules/c.json")));
+        assert!(!scanner.accepts(root, Path::new("/elsewhere/a.json")));
+    }
+
+    #[test]
+    fn file_uris_round_trip() {
+        let path = Path::new("/home/me/my project/a#1.json");
+        let uri = path_to_uri(path).unwrap();
+        assert_eq!(uri.as_str(), "file:///home/me/my%20project/a%231.json");
+        assert_eq!(uri_to_path(uri.as_str()).as_deref(), Some(path));
+        assert_eq!(
+            uri_to_path("file:///home/me/project/").as_deref(),
+            Some(Path::new("/home/me/project"))
+        );
+        assert_eq!(uri_to_path("https://example.com/a.json"), None);
+    }
+
+    #[test]
+    fn files_match_differently_escaped_uris() {
+        let path = Path::new("/ws/node_modules/@scope/pkg/package.json");
+        let scanned = path_to_uri(path).unwrap();
+        let client = Uri::from_str("file:///ws/node_modules/%40scope/pkg/package.json").unwrap();
+        let mut files = WorkspaceFiles::new();
+        files.replace(vec![scanned.clone()]);
+        assert_eq!(files.revision(&client), files.revision(&scanned));
+        assert!(files.revision(&client).is_some());
+
+        files.touch(client.clone());
+        assert_eq!(files.uris().collect::<Vec<_>>(), [&client]);
+        assert!(files.remove(&scanned));
+        assert_eq!(files.revision(&client), None);
+    }
+
+    #[test]
+    fn rescans_report_removed_files_and_move_revisions() {
+        let a = Uri::from_str("file:///a.json").unwrap();
+        let b = Uri::from_str("file:///b.json").unwrap();
+        let mut files = WorkspaceFiles::new();
+        assert!(files.replace(vec![a.clone(), b.clone()]).is_empty());
+        let revision = files.revision(&a).unwrap();
+
+        files.touch(a.clone());
+        assert!(files.revision(&a).unwrap() > revision);
+        assert_eq!(files.replace(vec![a.clone()]), vec![b.clone()]);
+        assert_eq!(files.revision(&b), None);
+        assert!(files.remove(&a));
+        assert!(!files.remove(&a));
+    }
+}
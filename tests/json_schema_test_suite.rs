@@ -0,0 +1,197 @@
+/// Runs the JSON-Schema-Test-Suite fixtures under `tests/JSON-Schema-Test-Suite`
+/// through the validator. Remote refs (`http://localhost:1234/...`) are served
+/// from the suite's `remotes` directory, so no network access is needed.
+///
+/// Every case that disagrees with the suite must be listed in
+/// `KNOWN_FAILURES`; an entry that starts passing must be removed from it.
+/// `scripts/update-test-suite.sh` vendors the suite and regenerates the list,
+/// using the failures this test writes to `$SUITE_FAILURES_OUT` when set.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use light_json_lsp::document::Document;
+use light_json_lsp::schema::SchemaStore;
+use light_json_lsp::schema::format::FormatMode;
+use light_json_lsp::schema::resolver::compile_schema;
+use light_json_lsp::schema::validation::{self, RegexCache, Severity, ValidationOptions};
+use light_json_lsp::tree;
+
+const DRAFTS: &[(&str, &str)] = &[
+    ("draft4", "http://json-schema.org/draft-04/schema#"),
+    ("draft6", "http://json-schema.org/draft-06/schema#"),
+    ("draft7", "http://json-schema.org/draft-07/schema#"),
+    (
+        "draft2019-09",
+        "https://json-schema.org/draft/2019-09/schema",
+    ),
+    (
+        "draft2020-12",
+        "https://json-schema.org/draft/2020-12/schema",
+    ),
+];
+
+const REMOTES_BASE: &str = "http://localhost:1234/";
+
+/// Retrieval URI for test schemas that don't declare an `$id`.
+const ROOT_URI: &str = "urn:json-schema-test-suite:root";
+
+/// Cases the validator is known to get wrong, as
+/// `"<draft>/<file> :: <case description> :: <test description>"`.
+const KNOWN_FAILURES: &[&str] = &[];
+
+#[test]
+fn json_schema_test_suite() {
+    let suite = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/JSON-Schema-Test-Suite");
+    let remotes = load_remotes(&suite.join("remotes"));
+
+    let mut failures = Vec::new();
+    let mut summary = BTreeMap::new();
+    for (draft, meta_schema) in DRAFTS {
+        let dir = suite.join("tests").join(draft);
+        for file in json_files(&dir) {
+            let name = file
+                .strip_prefix(&dir)
+                .unwrap()
+                .to_string_lossy()
+                .replace('\\', "/");
+            let format_mode = if name.starts_with("optional/format/") {
+                FormatMode::Assertion
+            } else {
+                FormatMode::Auto
+            };
+            let cases: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(&file).unwrap()).unwrap();
+            for case in cases.as_array().unwrap() {
+                for test in case["tests"].as_array().unwrap() {
+                    let id = format!(
+                        "{draft}/{name} :: {} :: {}",
+                        case["description"].as_str().unwrap(),
+                        test["description"].as_str().unwrap()
+                    );
+                    let expected = test["valid"].as_bool().unwrap();
+                    let actual = is_valid(
+                        &case["schema"],
+                        &test["data"],
+                        meta_schema,
+                        &remotes,
+                        format_mode,
+                    );
+                    let counts: &mut (usize, usize) = summary.entry(*draft).or_default();
+                    counts.1 += 1;
+                    if actual == expected {
+                        counts.0 += 1;
+                    } else {
+                        failures.push(id);
+                    }
+                }
+            }
+        }
+    }
+
+    for (draft, (passed, total)) in &summary {
+        println!("{draft}: {passed}/{total} passed");
+    }
+    assert!(
+        !summary.is_empty(),
+        "no suite fixtures found under {}",
+        suite.display()
+    );
+    if let Some(out) = std::env::var_os("SUITE_FAILURES_OUT") {
+        let list: String = failures.iter().map(|id| format!("{id}\n")).collect();
+        fs::write(out, list).unwrap();
+    }
+
+    let unexpected: Vec<_> = failures
+        .iter()
+        .filter(|id| !KNOWN_FAILURES.contains(&id.as_str()))
+        .collect();
+    let fixed: Vec<_> = KNOWN_FAILURES
+        .iter()
+        .filter(|id| !failures.iter().any(|f| f == *id))
+        .collect();
+    assert!(
+        unexpected.is_empty() && fixed.is_empty(),
+        "unexpected failures:\n  {}\nknown failures that now pass:\n  {}",
+        unexpected
+            .iter()
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>()
+            .join("\n  "),
+        fixed
+            .iter()
+            .copied()
+            .copied()
+            .collect::<Vec<_>>()
+            .join("\n  ")
+    );
+}
+
+fn is_valid(
+    schema: &serde_json::Value,
+    data: &serde_json::Value,
+    meta_schema: &str,
+    remotes: &[(String, serde_json::Value)],
+    format_mode: FormatMode,
+) -> bool {
+    // Suite schemas rely on the directory to pick the dialect.
+    let mut schema = schema.clone();
+    if let Some(obj) = schema.as_object_mut() {
+        obj.entry("$schema").or_insert_with(|| meta_schema.into());
+    }
+
+    let mut store = SchemaStore::new();
+    for (uri, raw) in remotes {
+        store.insert_cache(uri.clone(), compile_schema(uri, raw));
+    }
+    let compiled = compile_schema(ROOT_URI, &schema);
+    store.insert_cache(ROOT_URI.to_string(), compiled.clone());
+
+    let doc = Document::new(serde_json::to_string(data).unwrap(), 0);
+    let root = tree::root_value(&doc.tree).expect("test data should parse");
+    let errors = validation::validate(
+        root,
+        doc.source(),
+        &compiled,
+        store.registry(),
+        &RegexCache::new(),
+        ValidationOptions {
+            format_mode,
+            ..Default::default()
+        },
+    );
+    errors.iter().all(|e| e.severity != Severity::Error)
+}
+
+fn load_remotes(dir: &Path) -> Vec<(String, serde_json::Value)> {
+    json_files(dir)
+        .into_iter()
+        .map(|path| {
+            let rel = path
+                .strip_prefix(dir)
+                .unwrap()
+                .to_string_lossy()
+                .replace('\\', "/");
+            let raw = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+            (format!("{REMOTES_BASE}{rel}"), raw)
+        })
+        .collect()
+}
+
+/// All `.json` files below `dir`, sorted for stable output.
+fn json_files(dir: &Path) -> Vec<PathBuf> {
+    let mut out = Vec::new();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return out;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            out.extend(json_files(&path));
+        } else if path.extension().is_some_and(|e| e == "json") {
+            out.push(path);
+        }
+    }
+    out.sort();
+    out
+}